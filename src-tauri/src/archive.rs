@@ -0,0 +1,592 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::backup::partial_path;
+use crate::blobs;
+use crate::database::{Attachment, AttachmentBlob, Database, Note, Project, Step, StepDependency, StepStatus, ATTACHMENT_KIND_FILE, ATTACHMENT_KIND_IMAGE};
+use crate::error::AppError;
+use crate::media;
+use crate::protocol::ATTACHMENT_SCHEME;
+use crate::storage::StoragePaths;
+use crate::thumbnails;
+use crate::AppState;
+
+// An export is a zip holding `manifest.json` and the attachment files under
+// `files/`. Bump the version when the manifest changes in a way older builds
+// can't read; imports refuse versions newer than they know.
+const MANIFEST_ENTRY: &str = "manifest.json";
+const FILES_DIR: &str = "files";
+const ARCHIVE_FORMAT: &str = "projectsteps-archive";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    #[serde(rename = "exportedAt")]
+    exported_at: String,
+    projects: Vec<Project>,
+    steps: Vec<Step>,
+    // Missing from archives written before steps could depend on each other
+    #[serde(default)]
+    dependencies: Vec<StepDependency>,
+    // Statuses of projects with their own set; the global set isn't exported
+    #[serde(default)]
+    statuses: Vec<StepStatus>,
+    notes: Vec<Note>,
+    attachments: Vec<ArchivedAttachment>,
+}
+
+// Attachment metadata without anything tied to the exporting machine's
+// store. `file` is the archive entry holding the bytes.
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedAttachment {
+    id: String,
+    filename: String,
+    #[serde(rename = "contentType")]
+    content_type: String,
+    #[serde(rename = "contentId")]
+    content_id: String,
+    #[serde(rename = "contentTypeEnum")]
+    content_type_enum: String,
+    #[serde(rename = "createdAt")]
+    created_at: String,
+    kind: String,
+    file: String,
+}
+
+// Files to archive as (entry name, path on disk)
+type ArchiveFiles = Vec<(String, PathBuf)>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub path: String,
+    pub projects: usize,
+    pub steps: usize,
+    pub notes: usize,
+    pub attachments: usize,
+    // Attachments left out because their file is missing from the store
+    #[serde(rename = "missingFiles")]
+    pub missing_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportMode {
+    // Everything gets a new id, so the archive can be imported next to
+    // existing data, even more than once
+    Remap,
+    // Ids are kept; items that already exist are updated if the archive's copy
+    // is newer and skipped otherwise. Items in the trash are left alone and
+    // reported as conflicts, together with anything nested under them.
+    Merge,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportCounts {
+    pub projects: usize,
+    pub steps: usize,
+    pub notes: usize,
+    pub attachments: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub created: ImportCounts,
+    pub updated: ImportCounts,
+    pub skipped: ImportCounts,
+    pub conflicts: ImportCounts,
+}
+
+// Every project when `project_id` is None
+pub fn export(state: &AppState, project_id: Option<&str>, path: &Path) -> Result<ExportReport, AppError> {
+    let (manifest, files, missing_files) = {
+        let db = state.db.lock().unwrap();
+        let storage = state.storage.lock().unwrap();
+        collect(&db, &storage, project_id)?
+    };
+
+    // Attachment files never change once written, so they are read unlocked
+    write_archive(&manifest, &files, path)?;
+
+    Ok(ExportReport {
+        path: path.to_string_lossy().to_string(),
+        projects: manifest.projects.len(),
+        steps: manifest.steps.len(),
+        notes: manifest.notes.len(),
+        attachments: manifest.attachments.len(),
+        missing_files,
+    })
+}
+
+// Returns the manifest, the files to archive by entry name, and the ids of
+// attachments whose file is missing
+fn collect(
+    db: &Database,
+    storage: &StoragePaths,
+    project_id: Option<&str>,
+) -> Result<(Manifest, ArchiveFiles, Vec<String>), AppError> {
+    let projects = match project_id {
+        Some(project_id) => vec![db.get_project(project_id)?],
+        None => db.get_all_projects()?,
+    };
+
+    let mut manifest = Manifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        projects: Vec::new(),
+        steps: Vec::new(),
+        dependencies: Vec::new(),
+        statuses: Vec::new(),
+        notes: Vec::new(),
+        attachments: Vec::new(),
+    };
+    let mut attachments = Vec::new();
+
+    for project in projects {
+        for content_type_enum in ["project", "project_description"] {
+            attachments.extend(db.get_attachments_by_content(&project.id, content_type_enum, None)?);
+        }
+        let statuses = db.get_step_statuses(Some(&project.id))?;
+        manifest.statuses.extend(statuses.into_iter().filter(|status| status.project_id.is_some()));
+        for step in db.get_steps_by_project(&project.id)? {
+            attachments.extend(db.get_attachments_by_content(&step.id, "step", None)?);
+            manifest.steps.push(step);
+        }
+        manifest.dependencies.extend(db.get_step_dependencies(&project.id)?);
+        for note in db.get_notes_by_project(&project.id)? {
+            attachments.extend(db.get_attachments_by_content(&note.id, "note", None)?);
+            manifest.notes.push(note);
+        }
+        manifest.projects.push(project);
+    }
+
+    // Attachments sharing a blob share one entry
+    let mut files = Vec::new();
+    let mut entries = HashSet::new();
+    let mut missing_files = Vec::new();
+    for attachment in attachments {
+        let path = match storage.checked_attachment_path(&attachment.file_path) {
+            Ok(path) => path,
+            Err(AppError::NotFound(_)) => {
+                missing_files.push(attachment.id);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let entry = format!("{}/{}", FILES_DIR, file_name);
+        if entries.insert(entry.clone()) {
+            files.push((entry.clone(), path));
+        }
+
+        manifest.attachments.push(ArchivedAttachment {
+            id: attachment.id,
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            content_id: attachment.content_id,
+            content_type_enum: attachment.content_type_enum,
+            created_at: attachment.created_at,
+            kind: attachment.kind,
+            file: entry,
+        });
+    }
+
+    Ok((manifest, files, missing_files))
+}
+
+fn write_archive(manifest: &Manifest, files: &ArchiveFiles, path: &Path) -> Result<(), AppError> {
+    let partial = partial_path(path);
+    let result = (|| -> Result<(), AppError> {
+        let mut zip = ZipWriter::new(File::create(&partial)?);
+        zip.start_file(MANIFEST_ENTRY, SimpleFileOptions::default().compression_method(CompressionMethod::Deflated))?;
+        serde_json::to_writer_pretty(&mut zip, manifest)
+            .map_err(|e| AppError::Internal(format!("Failed to write the manifest: {}", e)))?;
+
+        // Images are compressed already
+        let stored = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(true);
+        for (entry, source) in files {
+            zip.start_file(entry.as_str(), stored)?;
+            io::copy(&mut File::open(source)?, &mut zip)?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => fs::rename(&partial, path).map_err(AppError::from),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+// Imports everything in the archive in one transaction, so a failure leaves
+// the workspace as it was, including the files it wrote to the blob store.
+pub fn import(state: &AppState, path: &Path, mode: ImportMode) -> Result<ImportReport, AppError> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let manifest: Manifest = serde_json::from_reader(archive.by_name(MANIFEST_ENTRY)?)
+        .map_err(|e| AppError::InvalidInput(format!("The archive's manifest is invalid: {}", e)))?;
+    if manifest.format != ARCHIVE_FORMAT {
+        return Err(AppError::InvalidInput("The file is not a ProjectSteps archive".to_string()));
+    }
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(AppError::InvalidInput(format!(
+            "The archive has format version {}, newer than this build supports (version {})",
+            manifest.version, ARCHIVE_VERSION
+        )));
+    }
+
+    let (report, created, unreferenced) = {
+        let db = state.db.lock().unwrap();
+        let storage = state.storage.lock().unwrap();
+        let mut stored_blobs = Vec::new();
+        let imported =
+            db.with_transaction(|| import_manifest(&db, &storage, &mut archive, manifest, mode, &mut stored_blobs));
+        let (report, created) = match imported {
+            Ok(imported) => imported,
+            Err(e) => {
+                // The rollback dropped the rows of blobs this import stored first
+                let written: Vec<String> = stored_blobs
+                    .into_iter()
+                    .filter(|blob| matches!(db.get_blob(&blob.hash), Ok(None)))
+                    .map(|blob| blob.file_path)
+                    .collect();
+                crate::remove_attachment_files(&storage, &written);
+                return Err(e);
+            }
+        };
+        // Blobs written for attachments that were skipped after all
+        let unreferenced = db.take_unreferenced_blobs().unwrap_or_default();
+        crate::remove_attachment_files(&storage, &unreferenced);
+        (report, created, unreferenced.len())
+    };
+    if unreferenced > 0 {
+        println!("Removed {} unused files after import", unreferenced);
+    }
+
+    // The import is usable without thumbnails; the backfill retries on next start
+    for attachment in created.iter().filter(|attachment| attachment.kind == ATTACHMENT_KIND_IMAGE) {
+        if let Err(e) = thumbnails::generate_and_save(state, attachment) {
+            println!("Failed to generate thumbnails for {}: {}", attachment.id, e);
+        }
+    }
+
+    Ok(report)
+}
+
+// RFC 3339 timestamps are compared as instants, anything else as text
+fn is_newer(incoming: &str, local: &str) -> bool {
+    match (DateTime::parse_from_rfc3339(incoming), DateTime::parse_from_rfc3339(local)) {
+        (Ok(incoming), Ok(local)) => incoming > local,
+        _ => incoming > local,
+    }
+}
+
+// Points attachment URLs in rich text at the attachments' new ids, in both
+// the `<scheme>://localhost/<id>` and `http://<scheme>.localhost/<id>` forms.
+fn rewrite_attachment_refs(html: &str, attachment_ids: &HashMap<String, String>) -> String {
+    if !html.contains(ATTACHMENT_SCHEME) {
+        return html.to_string();
+    }
+    let mut html = html.to_string();
+    for (old_id, new_id) in attachment_ids.iter().filter(|(old_id, new_id)| old_id != new_id) {
+        for host in [format!("{}://localhost/", ATTACHMENT_SCHEME), format!("{}.localhost/", ATTACHMENT_SCHEME)] {
+            html = html.replace(&format!("{}{}", host, old_id), &format!("{}{}", host, new_id));
+        }
+    }
+    html
+}
+
+// How deep each step is nested, counting parents within the archive only
+fn step_depths(steps: &[Step]) -> HashMap<String, usize> {
+    let parents: HashMap<&str, &str> = steps
+        .iter()
+        .filter_map(|step| Some((step.id.as_str(), step.parent_step_id.as_deref()?)))
+        .collect();
+    steps
+        .iter()
+        .map(|step| {
+            let mut depth = 0;
+            let mut current = step.id.as_str();
+            while let Some(parent) = parents.get(current) {
+                depth += 1;
+                // A cycle can only come from an edited archive
+                if depth > steps.len() {
+                    break;
+                }
+                current = parent;
+            }
+            (step.id.clone(), depth)
+        })
+        .collect()
+}
+
+enum Action {
+    Create,
+    Update,
+    Skip,
+    // The local copy is in the trash
+    Conflict,
+}
+
+// What to do with an incoming project, step or note
+fn action_for(db: &Database, mode: ImportMode, kind: &str, id: &str, updated_at: &str) -> Result<Action, AppError> {
+    if mode == ImportMode::Remap {
+        return Ok(Action::Create);
+    }
+    Ok(match db.get_updated_at(kind, id)? {
+        None => Action::Create,
+        Some((_, true)) => Action::Conflict,
+        Some((local, false)) if is_newer(updated_at, &local) => Action::Update,
+        Some(_) => Action::Skip,
+    })
+}
+
+fn import_manifest(
+    db: &Database,
+    storage: &StoragePaths,
+    archive: &mut ZipArchive<File>,
+    manifest: Manifest,
+    mode: ImportMode,
+    stored_blobs: &mut Vec<AttachmentBlob>,
+) -> Result<(ImportReport, Vec<Attachment>), AppError> {
+    let mut report = ImportReport::default();
+    let new_id = |id: &str| match mode {
+        ImportMode::Remap => Uuid::new_v4().to_string(),
+        ImportMode::Merge => id.to_string(),
+    };
+
+    let project_ids: HashMap<String, String> = manifest.projects.iter().map(|p| (p.id.clone(), new_id(&p.id))).collect();
+    let step_ids: HashMap<String, String> = manifest.steps.iter().map(|s| (s.id.clone(), new_id(&s.id))).collect();
+    let note_ids: HashMap<String, String> = manifest.notes.iter().map(|n| (n.id.clone(), new_id(&n.id))).collect();
+    let attachment_ids: HashMap<String, String> = manifest.attachments.iter().map(|a| (a.id.clone(), new_id(&a.id))).collect();
+    let status_ids: HashMap<String, String> = manifest.statuses.iter().map(|s| (s.id.clone(), new_id(&s.id))).collect();
+
+    let mut written_projects = HashSet::new();
+    // Projects and steps whose local copy is in the trash
+    let mut conflicts = HashSet::new();
+    for mut project in manifest.projects {
+        project.id = project_ids[&project.id].clone();
+        project.description = rewrite_attachment_refs(&project.description, &attachment_ids);
+        project.current_step_id = project.current_step_id.and_then(|id| step_ids.get(&id).cloned());
+        match action_for(db, mode, "project", &project.id, &project.updated_at)? {
+            Action::Create => {
+                db.create_project(&project)?;
+                report.created.projects += 1;
+                written_projects.insert(project.id);
+            }
+            Action::Update => {
+                db.update_project(&project)?;
+                report.updated.projects += 1;
+                written_projects.insert(project.id);
+            }
+            Action::Skip => report.skipped.projects += 1,
+            Action::Conflict => {
+                report.conflicts.projects += 1;
+                conflicts.insert(project.id);
+            }
+        }
+    }
+
+    // Status sets come along with the projects they belong to
+    let mut statuses_by_project: HashMap<String, Vec<StepStatus>> = HashMap::new();
+    for mut status in manifest.statuses {
+        let Some(project_id) = status.project_id.as_ref().and_then(|id| project_ids.get(id)) else {
+            continue;
+        };
+        status.project_id = Some(project_id.clone());
+        status.id = status_ids[&status.id].clone();
+        statuses_by_project.entry(project_id.clone()).or_default().push(status);
+    }
+    for (project_id, mut statuses) in statuses_by_project {
+        if written_projects.contains(&project_id) {
+            statuses.sort_by_key(|status| status.order_index);
+            db.write_step_statuses(Some(&project_id), &statuses)?;
+        }
+    }
+    let mut project_statuses: HashMap<String, HashSet<String>> = HashMap::new();
+
+    // Parents are created before the steps nested under them
+    let depths = step_depths(&manifest.steps);
+    let mut steps = manifest.steps;
+    steps.sort_by_key(|step| depths.get(&step.id).copied().unwrap_or(0));
+
+    for mut step in steps {
+        let Some(project_id) = project_ids.get(&step.project_id) else {
+            report.skipped.steps += 1;
+            continue;
+        };
+        step.project_id = project_id.clone();
+        step.id = step_ids[&step.id].clone();
+        step.parent_step_id = step.parent_step_id.and_then(|id| step_ids.get(&id).cloned());
+        if conflicts.contains(project_id) || step.parent_step_id.as_ref().is_some_and(|id| conflicts.contains(id)) {
+            report.conflicts.steps += 1;
+            conflicts.insert(step.id);
+            continue;
+        }
+        // A status the project doesn't have falls back to following `completed`
+        if !project_statuses.contains_key(project_id) {
+            let statuses = db.get_step_statuses(Some(project_id))?;
+            project_statuses.insert(project_id.clone(), statuses.into_iter().map(|status| status.id).collect());
+        }
+        step.status = step
+            .status
+            .map(|status| status_ids.get(&status).cloned().unwrap_or(status))
+            .filter(|status| project_statuses[project_id].contains(status));
+        step.description = rewrite_attachment_refs(&step.description, &attachment_ids);
+        match action_for(db, mode, "step", &step.id, &step.updated_at)? {
+            Action::Create => {
+                db.create_step(&step)?;
+                report.created.steps += 1;
+            }
+            Action::Update => {
+                db.write_step(&step)?;
+                report.updated.steps += 1;
+            }
+            Action::Skip => report.skipped.steps += 1,
+            Action::Conflict => {
+                report.conflicts.steps += 1;
+                conflicts.insert(step.id);
+            }
+        }
+    }
+
+    // A dependency that would clash with ones already in the workspace is dropped
+    for dependency in manifest.dependencies {
+        let (Some(step_id), Some(depends_on_step_id)) = (
+            step_ids.get(&dependency.step_id),
+            step_ids.get(&dependency.depends_on_step_id),
+        ) else {
+            continue;
+        };
+        match db.add_step_dependency(step_id, depends_on_step_id) {
+            Ok(()) | Err(AppError::InvalidInput(_)) | Err(AppError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    for mut note in manifest.notes {
+        let Some(project_id) = project_ids.get(&note.project_id) else {
+            report.skipped.notes += 1;
+            continue;
+        };
+        if conflicts.contains(project_id) {
+            report.conflicts.notes += 1;
+            continue;
+        }
+        note.project_id = project_id.clone();
+        note.id = note_ids[&note.id].clone();
+        note.content = rewrite_attachment_refs(&note.content, &attachment_ids);
+        match action_for(db, mode, "note", &note.id, &note.updated_at)? {
+            Action::Create => {
+                db.create_note(&note)?;
+                report.created.notes += 1;
+            }
+            Action::Update => {
+                db.write_note(&note)?;
+                report.updated.notes += 1;
+            }
+            Action::Skip => report.skipped.notes += 1,
+            Action::Conflict => report.conflicts.notes += 1,
+        }
+    }
+
+    // Attachments never change, so one that already exists is skipped. Ones over
+    // the upload limit are skipped too, without trusting the declared size.
+    let max_bytes = db.get_max_upload_bytes()?;
+    let mut created = Vec::new();
+    for archived in manifest.attachments {
+        let owners = match archived.content_type_enum.as_str() {
+            "project" | "project_description" => &project_ids,
+            "step" => &step_ids,
+            "note" => &note_ids,
+            _ => {
+                report.skipped.attachments += 1;
+                continue;
+            }
+        };
+        let id = attachment_ids[&archived.id].clone();
+        let owner = match owners.get(&archived.content_id) {
+            Some(owner) if !(mode == ImportMode::Merge && db.attachment_exists(&id)?) => owner.clone(),
+            _ => {
+                report.skipped.attachments += 1;
+                continue;
+            }
+        };
+
+        let mut data = Vec::new();
+        match archive.by_name(&archived.file) {
+            Ok(entry) if entry.size() > max_bytes => {
+                report.skipped.attachments += 1;
+                continue;
+            }
+            Ok(entry) => entry.take(max_bytes + 1).read_to_end(&mut data)?,
+            Err(zip::result::ZipError::FileNotFound) => {
+                report.skipped.attachments += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if data.len() as u64 > max_bytes {
+            report.skipped.attachments += 1;
+            continue;
+        }
+
+        let mut attachment = Attachment {
+            id,
+            file_path: String::new(),
+            filename: archived.filename,
+            content_type: archived.content_type,
+            content_id: owner,
+            content_type_enum: archived.content_type_enum,
+            created_at: archived.created_at,
+            width: None,
+            height: None,
+            byte_size: Some(data.len() as u64),
+            blob_hash: None,
+            transformed: false,
+            original_blob_hash: None,
+            kind: ATTACHMENT_KIND_FILE.to_string(),
+        };
+        // Images are checked the same way as uploads
+        let extension = match media::inspect_image(&data) {
+            Ok(info) if archived.kind == ATTACHMENT_KIND_IMAGE => {
+                attachment.kind = ATTACHMENT_KIND_IMAGE.to_string();
+                attachment.content_type = info.content_type.to_string();
+                attachment.width = Some(info.width);
+                attachment.height = Some(info.height);
+                info.extension.to_string()
+            }
+            _ => media::file_extension(&archived.file),
+        };
+
+        let blob = blobs::store_blob(db, storage, &data, &extension)?;
+        attachment.file_path = blob.file_path.clone();
+        attachment.blob_hash = Some(blob.hash.clone());
+        stored_blobs.push(blob);
+        match db.create_attachment(&attachment) {
+            Ok(()) => {
+                report.created.attachments += 1;
+                created.push(attachment);
+            }
+            // The owner is in the trash here
+            Err(AppError::InvalidInput(_)) => report.skipped.attachments += 1,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok((report, created))
+}
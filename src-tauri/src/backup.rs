@@ -0,0 +1,453 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, Runtime};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::database::{BackupSettings, Database};
+use crate::error::AppError;
+use crate::migrations;
+use crate::storage::StoragePaths;
+use crate::AppState;
+
+// Backups live in the data directory as `projectsteps-<kind>-<UTC time>.db`,
+// or `.zip` when the attachments are archived along with the database. Only
+// automatic backups are pruned; manual ones and the safety copies taken before
+// a restore stay until deleted.
+const FILE_PREFIX: &str = "projectsteps-";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S-%3f";
+const TIMESTAMP_LEN: usize = 19;
+
+// Entries of a backup archive
+const ARCHIVE_DB_ENTRY: &str = "projectsteps.db";
+const ARCHIVE_ATTACHMENTS_DIR: &str = "attachments";
+
+// How often the scheduler checks whether a backup is due
+const SCHEDULER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BackupKind {
+    Automatic,
+    Manual,
+    PreRestore,
+}
+
+impl BackupKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            BackupKind::Automatic => "automatic",
+            BackupKind::Manual => "manual",
+            BackupKind::PreRestore => "pre-restore",
+        }
+    }
+
+    fn parse(value: &str) -> Option<BackupKind> {
+        match value {
+            "automatic" => Some(BackupKind::Automatic),
+            "manual" => Some(BackupKind::Manual),
+            "pre-restore" => Some(BackupKind::PreRestore),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupInfo {
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    pub path: String,
+    pub kind: BackupKind,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "byteSize")]
+    pub byte_size: u64,
+    #[serde(rename = "includesAttachments")]
+    pub includes_attachments: bool,
+}
+
+struct BackupFile {
+    file_name: String,
+    kind: BackupKind,
+    created_at: DateTime<Utc>,
+    includes_attachments: bool,
+}
+
+fn backup_file_name(kind: BackupKind, created_at: DateTime<Utc>, includes_attachments: bool) -> String {
+    format!(
+        "{}{}-{}.{}",
+        FILE_PREFIX,
+        kind.as_str(),
+        created_at.format(TIMESTAMP_FORMAT),
+        if includes_attachments { "zip" } else { "db" }
+    )
+}
+
+fn parse_file_name(file_name: &str) -> Option<BackupFile> {
+    let (stem, extension) = file_name.strip_prefix(FILE_PREFIX)?.rsplit_once('.')?;
+    let includes_attachments = match extension {
+        "db" => false,
+        "zip" => true,
+        _ => return None,
+    };
+    let split = stem.len().checked_sub(TIMESTAMP_LEN)?;
+    if !stem.is_char_boundary(split) {
+        return None;
+    }
+    let (kind, timestamp) = stem.split_at(split);
+    let created_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc();
+
+    Some(BackupFile {
+        file_name: file_name.to_string(),
+        kind: BackupKind::parse(kind.strip_suffix('-')?)?,
+        created_at,
+        includes_attachments,
+    })
+}
+
+// Where a file is written before being renamed into place, so a backup that
+// fails part-way never shows up in the list
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+fn list_files(dir: &Path) -> io::Result<Vec<BackupFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(backup) = parse_file_name(&entry.file_name().to_string_lossy()) {
+            backups.push(backup);
+        }
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+    Ok(backups)
+}
+
+fn backup_info(storage: &StoragePaths, backup: BackupFile) -> Result<BackupInfo, AppError> {
+    let path = storage.backups_dir().join(&backup.file_name);
+    Ok(BackupInfo {
+        byte_size: fs::metadata(&path)?.len(),
+        path: path.to_string_lossy().to_string(),
+        file_name: backup.file_name,
+        kind: backup.kind,
+        created_at: backup.created_at.to_rfc3339(),
+        includes_attachments: backup.includes_attachments,
+    })
+}
+
+// Newest first
+pub fn list_backups(storage: &StoragePaths) -> Result<Vec<BackupInfo>, AppError> {
+    list_files(&storage.backups_dir())?
+        .into_iter()
+        .map(|backup| backup_info(storage, backup))
+        .collect()
+}
+
+fn find_backup(storage: &StoragePaths, file_name: &str) -> Result<BackupFile, AppError> {
+    // Only names we generate are accepted, which also rules out paths
+    let backup = parse_file_name(file_name)
+        .ok_or_else(|| AppError::InvalidInput(format!("{} is not a backup", file_name)))?;
+    if !storage.backups_dir().join(file_name).is_file() {
+        return Err(AppError::NotFound(format!("Backup {} not found", file_name)));
+    }
+    Ok(backup)
+}
+
+// Copies the database to `path`. The caller holds the database lock.
+fn snapshot_database(db: &Database, path: &Path) -> Result<(), AppError> {
+    let partial = partial_path(path);
+    let _ = fs::remove_file(&partial);
+    let result = db
+        .backup_to(&partial)
+        .map_err(AppError::from)
+        .and_then(|_| fs::rename(&partial, path).map_err(AppError::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+// Takes a backup now. The database snapshot is taken under the lock;
+// archiving the attachments isn't, since their files never change once written.
+pub fn create_backup(state: &AppState, kind: BackupKind, include_attachments: bool) -> Result<BackupInfo, AppError> {
+    let file_name = backup_file_name(kind, Utc::now(), include_attachments);
+
+    let (storage, snapshot) = {
+        let db = state.db.lock().unwrap();
+        let storage = state.storage.lock().unwrap().clone();
+        fs::create_dir_all(storage.backups_dir())?;
+        let snapshot = if include_attachments {
+            storage.backups_dir().join(format!(".{}.db", file_name))
+        } else {
+            storage.backups_dir().join(&file_name)
+        };
+        snapshot_database(&db, &snapshot)?;
+        (storage, snapshot)
+    };
+
+    if include_attachments {
+        let result = write_archive(&snapshot, &storage.attachments_dir(), &storage.backups_dir().join(&file_name));
+        let _ = fs::remove_file(&snapshot);
+        result?;
+    }
+
+    println!("Created {} backup {}", kind.as_str(), file_name);
+    let backup = find_backup(&storage, &file_name)?;
+    backup_info(&storage, backup)
+}
+
+fn write_archive(db_path: &Path, attachments_dir: &Path, path: &Path) -> Result<(), AppError> {
+    let partial = partial_path(path);
+    let result = (|| -> Result<(), AppError> {
+        let mut zip = ZipWriter::new(File::create(&partial)?);
+        let deflated = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(true);
+        zip.start_file(ARCHIVE_DB_ENTRY, deflated)?;
+        io::copy(&mut File::open(db_path)?, &mut zip)?;
+
+        // Images are compressed already
+        let stored = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(true);
+        let mut files = Vec::new();
+        if attachments_dir.exists() {
+            collect_files(attachments_dir, "", &mut files)?;
+        }
+        for relative in files {
+            // A file deleted since the listing is no longer referenced
+            let mut file = match File::open(attachments_dir.join(&relative)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            zip.start_file(format!("{}/{}", ARCHIVE_ATTACHMENTS_DIR, relative), stored)?;
+            io::copy(&mut file, &mut zip)?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => fs::rename(&partial, path).map_err(AppError::from),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+// Relative paths with `/` separators, as stored in an archive
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let relative = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", relative), files)?;
+        } else {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+// Checks that `path` is an intact ProjectSteps database this build can open,
+// returning its schema version
+fn validate_database(path: &Path) -> Result<i32, AppError> {
+    let not_a_database = || AppError::InvalidInput("The backup is not a ProjectSteps database".to_string());
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let check: String = conn
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .map_err(|_| not_a_database())?;
+    if check != "ok" {
+        return Err(AppError::InvalidInput(format!("The backup is damaged: {}", check)));
+    }
+
+    let version: i32 = conn
+        .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+        .map_err(|_| not_a_database())?;
+    if version == 0 {
+        return Err(not_a_database());
+    }
+    if version > migrations::latest_version() {
+        return Err(AppError::SchemaTooNew { version, supported: migrations::latest_version() });
+    }
+    Ok(version)
+}
+
+// Replaces the current database with a backup, saving a safety copy of it
+// first, which is returned. Attachments in an archive are added back where
+// missing; files already present are left alone, as a blob's name is its content.
+pub fn restore_backup(state: &AppState, file_name: &str) -> Result<BackupInfo, AppError> {
+    let storage = state.storage.lock().unwrap().clone();
+    let backup = find_backup(&storage, file_name)?;
+    let path = storage.backups_dir().join(file_name);
+
+    // The database is taken out of an archive into a scratch file first
+    let extracted = backup
+        .includes_attachments
+        .then(|| storage.backups_dir().join(format!(".restore-{}.db", Uuid::new_v4())));
+
+    let result = (|| -> Result<BackupInfo, AppError> {
+        let db_path = match &extracted {
+            Some(extracted) => {
+                let mut archive = ZipArchive::new(File::open(&path)?)?;
+                let mut entry = archive.by_name(ARCHIVE_DB_ENTRY)?;
+                io::copy(&mut entry, &mut File::create(extracted)?)?;
+                extracted.clone()
+            }
+            None => path.clone(),
+        };
+        let version = validate_database(&db_path)?;
+
+        let safety_copy = backup_file_name(BackupKind::PreRestore, Utc::now(), false);
+        {
+            let mut db = state.db.lock().unwrap();
+            snapshot_database(&db, &storage.backups_dir().join(&safety_copy))?;
+            db.restore_from(&db_path)?;
+        }
+        println!(
+            "Restored backup {} (schema version {}); the previous database was saved as {}",
+            file_name, version, safety_copy
+        );
+
+        if backup.includes_attachments {
+            let restored = extract_attachments(&path, &storage.attachments_dir())?;
+            println!("Restored {} attachment files", restored);
+        }
+
+        let safety_copy = find_backup(&storage, &safety_copy)?;
+        backup_info(&storage, safety_copy)
+    })();
+
+    if let Some(extracted) = extracted {
+        let _ = fs::remove_file(extracted);
+    }
+    result
+}
+
+fn extract_attachments(archive_path: &Path, attachments_dir: &Path) -> Result<usize, AppError> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut restored = 0;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // `enclosed_name` rejects absolute paths and `..`
+        let relative = match entry.enclosed_name() {
+            Some(name) => match name.strip_prefix(ARCHIVE_ATTACHMENTS_DIR) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+
+        let target = attachments_dir.join(relative);
+        if target.exists() {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let partial = partial_path(&target);
+        let result = io::copy(&mut entry, &mut File::create(&partial)?)
+            .and_then(|_| fs::rename(&partial, &target));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(e.into());
+        }
+        restored += 1;
+    }
+    Ok(restored)
+}
+
+pub fn delete_backup(storage: &StoragePaths, file_name: &str) -> Result<(), AppError> {
+    find_backup(storage, file_name)?;
+    fs::remove_file(storage.backups_dir().join(file_name))?;
+    Ok(())
+}
+
+// Keeps the newest automatic backup of each of the last `keep_hourly` hours
+// and `keep_daily` days, plus the newest overall, and deletes the rest.
+// Returns how many were deleted.
+pub fn prune_backups(storage: &StoragePaths, settings: &BackupSettings) -> Result<usize, AppError> {
+    let now = Utc::now();
+    let automatic: Vec<BackupFile> = list_files(&storage.backups_dir())?
+        .into_iter()
+        .filter(|backup| backup.kind == BackupKind::Automatic)
+        .collect();
+
+    let mut hours = HashSet::new();
+    let mut days = HashSet::new();
+    let mut removed = 0;
+    for (index, backup) in automatic.iter().enumerate() {
+        let age = now - backup.created_at;
+        let hourly = age < Duration::hours(settings.keep_hourly.into())
+            && hours.insert(backup.created_at.format("%Y%m%d%H").to_string());
+        let daily = age < Duration::days(settings.keep_daily.into())
+            && days.insert(backup.created_at.date_naive());
+        if index == 0 || hourly || daily {
+            continue;
+        }
+
+        match fs::remove_file(storage.backups_dir().join(&backup.file_name)) {
+            Ok(()) => removed += 1,
+            Err(e) => println!("Failed to remove backup {}: {}", backup.file_name, e),
+        }
+    }
+    Ok(removed)
+}
+
+fn run_scheduled_backup(state: &AppState) -> Result<(), AppError> {
+    let settings = state.db.lock().unwrap().get_backup_settings()?;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let storage = state.storage.lock().unwrap().clone();
+    let latest = list_files(&storage.backups_dir())?
+        .into_iter()
+        .find(|backup| backup.kind == BackupKind::Automatic)
+        .map(|backup| backup.created_at);
+    let due = match latest {
+        Some(latest) => Utc::now() - latest >= Duration::hours(settings.interval_hours.into()),
+        None => true,
+    };
+    if due {
+        create_backup(state, BackupKind::Automatic, settings.include_attachments)?;
+    }
+
+    let removed = prune_backups(&storage, &settings)?;
+    if removed > 0 {
+        println!("Removed {} expired backups", removed);
+    }
+    Ok(())
+}
+
+// Checks every few minutes whether an automatic backup is due, starting with
+// one right away if the last is older than the interval.
+pub fn spawn_scheduler<R: Runtime>(app: AppHandle<R>) {
+    std::thread::spawn(move || loop {
+        let state = app.state::<AppState>();
+        if let Err(e) = run_scheduled_backup(&state) {
+            println!("Automatic backup failed: {}", e);
+        }
+        std::thread::sleep(SCHEDULER_CHECK_INTERVAL);
+    });
+}
@@ -0,0 +1,111 @@
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::database::{AttachmentBlob, Database};
+use crate::error::AppError;
+use crate::media;
+use crate::storage::StoragePaths;
+
+// Subdirectory of the attachments root holding one file per distinct content
+const BLOBS_DIR: &str = "blobs";
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Returns the blob holding `data`, writing it to `blobs/<hash>.<ext>` if this
+// content hasn't been stored before. The caller must keep the database locked
+// until the attachment row referencing the blob is inserted, or a concurrent
+// delete could release it in between.
+pub fn store_blob(db: &Database, storage: &StoragePaths, data: &[u8], extension: &str) -> Result<AttachmentBlob, AppError> {
+    let hash = hash_bytes(data);
+    if let Some(blob) = db.get_blob(&hash)? {
+        // The bytes are at hand, so a file that went missing is written again
+        let path = storage.resolve_attachment_path(&blob.file_path);
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, data)?;
+        }
+        return Ok(blob);
+    }
+
+    let file_path = format!("{}/{}.{}", BLOBS_DIR, hash, extension);
+    let path = storage.attachments_dir().join(&file_path);
+    fs::create_dir_all(storage.attachments_dir().join(BLOBS_DIR))?;
+    fs::write(&path, data)?;
+
+    let blob = AttachmentBlob {
+        hash,
+        file_path,
+        byte_size: data.len() as u64,
+        ref_count: 0,
+        created_at: Utc::now().to_rfc3339(),
+    };
+    if let Err(e) = db.create_blob(&blob) {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+    Ok(blob)
+}
+
+// Moves attachments written before content addressing into the blob store,
+// merging identical files. The blob is written and the row repointed before
+// the old file is removed, so an interrupted run only leaves a stray copy.
+// Rows whose file can't be read are left for `repair_attachment_paths` and
+// retried on the next start.
+pub fn migrate_existing(db: &Database, storage: &StoragePaths) -> Result<(), AppError> {
+    let pending = db.get_attachments_without_blob()?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let mut migrated = 0;
+    for attachment in &pending {
+        let data = match storage
+            .checked_attachment_path(&attachment.file_path)
+            .and_then(|path| fs::read(path).map_err(AppError::from))
+        {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Skipping blob migration for {}: {}", attachment.id, e);
+                continue;
+            }
+        };
+
+        let extension = media::inspect_image(&data)
+            .map(|info| info.extension.to_string())
+            .ok()
+            .or_else(|| {
+                Path::new(&attachment.file_path)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+            })
+            .unwrap_or_else(|| "bin".to_string());
+
+        let blob = store_blob(db, storage, &data, &extension)?;
+        db.set_attachment_blob(&attachment.id, &blob.hash, &blob.file_path)?;
+        migrated += 1;
+
+        if db.count_attachments_with_path(&attachment.file_path)? == 0 {
+            if let Err(e) = storage
+                .checked_attachment_path(&attachment.file_path)
+                .and_then(|path| fs::remove_file(path).map_err(AppError::from))
+            {
+                println!("Failed to remove migrated attachment {}: {}", attachment.file_path, e);
+            }
+        }
+    }
+
+    let report = db.get_attachment_storage_report()?;
+    println!(
+        "Moved {} of {} attachments into the blob store; deduplication saves {} bytes",
+        migrated,
+        pending.len(),
+        report.saved_bytes
+    );
+    Ok(())
+}
@@ -1,7 +1,86 @@
-use rusqlite::{Connection, Result};
+use chrono::{Duration, Utc};
+use rusqlite::backup::Backup;
+use rusqlite::{params, params_from_iter, Connection, Error, OpenFlags, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
+use crate::diff::{self, DiffLine};
+use crate::error::AppError;
+use crate::migrations;
+use crate::planning::{self, StepState};
+use crate::schedule::{self, AgendaItem, AgendaItemKind, Dated, DueWindow, Zone};
+use uuid::Uuid;
+
+pub const TRASH_RETENTION_DAYS_KEY: &str = "trash_retention_days";
+pub const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+pub const MAX_UPLOAD_BYTES_KEY: &str = "max_upload_bytes";
+pub const DEFAULT_MAX_UPLOAD_BYTES: u64 = 20 * 1024 * 1024;
+pub const SCAN_ATTACHMENTS_ON_STARTUP_KEY: &str = "scan_attachments_on_startup";
+pub const STRIP_IMAGE_METADATA_KEY: &str = "strip_image_metadata";
+pub const MAX_IMAGE_DIMENSION_KEY: &str = "max_image_dimension";
+pub const MAX_IMAGE_BYTES_KEY: &str = "max_image_bytes";
+pub const KEEP_ORIGINAL_IMAGES_KEY: &str = "keep_original_images";
+// IANA name of the zone days are counted in for due dates; unset uses the system's
+pub const TIME_ZONE_KEY: &str = "time_zone";
+pub const BACKUPS_ENABLED_KEY: &str = "backups_enabled";
+pub const BACKUP_INTERVAL_HOURS_KEY: &str = "backup_interval_hours";
+pub const BACKUP_KEEP_HOURLY_KEY: &str = "backup_keep_hourly";
+pub const BACKUP_KEEP_DAILY_KEY: &str = "backup_keep_daily";
+pub const BACKUP_INCLUDE_ATTACHMENTS_KEY: &str = "backup_include_attachments";
+pub const DEFAULT_BACKUP_INTERVAL_HOURS: u32 = 1;
+pub const DEFAULT_BACKUP_KEEP_HOURLY: u32 = 24;
+pub const DEFAULT_BACKUP_KEEP_DAILY: u32 = 30;
+
+pub const ATTACHMENT_KIND_IMAGE: &str = "image";
+pub const ATTACHMENT_KIND_FILE: &str = "file";
+
+// Saves of the same item closer together than this share one revision, so
+// autosave doesn't produce a revision per keystroke burst.
+const REVISION_COALESCE_SECONDS: i64 = 300;
+
+// The database lock is held for the whole backup anyway, so it's copied in
+// large steps without pausing
+const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 1024;
+
+const PROJECT_COLUMNS: &str =
+    "id, name, description, created_at, updated_at, gradient, current_step_id, start_date, due_date";
+
+const STEP_COLUMNS: &str =
+    "id, project_id, title, description, plain_text, order_index, completed, created_at, updated_at, parent_step_id, status,
+     status_changed_at, start_date, due_date";
+
+const STEP_STATUS_COLUMNS: &str = "id, project_id, name, order_index, counts_as_done";
+
+// The step `?1` and every step nested under it, trashed or not
+const STEP_SUBTREE: &str = "WITH RECURSIVE subtree(id) AS (
+        SELECT ?1 UNION SELECT s.id FROM steps s JOIN subtree ON s.parent_step_id = subtree.id
+    )
+    SELECT id FROM subtree";
+
+const ATTACHMENT_COLUMNS: &str =
+    "id, file_path, filename, content_type, content_id, content_type_enum, created_at, width, height, byte_size, blob_hash,
+     transformed, original_blob_hash, kind";
+
+// Conditions selecting the attachments owned by a project (including those of
+// its steps and notes), a step or a note. `?1` is the owner id.
+const PROJECT_ATTACHMENTS: &str = "(content_type_enum IN ('project', 'project_description') AND content_id = ?1)
+     OR (content_type_enum = 'step' AND content_id IN (SELECT id FROM steps WHERE project_id = ?1))
+     OR (content_type_enum = 'note' AND content_id IN (SELECT id FROM notes WHERE project_id = ?1))";
+const STEP_ATTACHMENTS: &str = "content_type_enum = 'step' AND content_id IN (
+        WITH RECURSIVE subtree(id) AS (
+            SELECT ?1 UNION SELECT s.id FROM steps s JOIN subtree ON s.parent_step_id = subtree.id
+        )
+        SELECT id FROM subtree
+    )";
+const NOTE_ATTACHMENTS: &str = "content_type_enum = 'note' AND content_id = ?1";
+
+// Attachments whose owner row no longer exists, trashed or not
+const ORPHANED_ATTACHMENTS: &str = "(content_type_enum IN ('project', 'project_description') AND content_id NOT IN (SELECT id FROM projects))
+     OR (content_type_enum = 'step' AND content_id NOT IN (SELECT id FROM steps))
+     OR (content_type_enum = 'note' AND content_id NOT IN (SELECT id FROM notes))
+     OR content_type_enum NOT IN ('project', 'project_description', 'step', 'note')";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Project {
     pub id: String,
@@ -12,6 +91,11 @@ pub struct Project {
     pub gradient: String,
     #[serde(rename = "currentStepId")]
     pub current_step_id: Option<String>,
+    // A day ("2024-05-01") or a time; times are stored in UTC
+    #[serde(rename = "startDate", default)]
+    pub start_date: Option<String>,
+    #[serde(rename = "dueDate", default)]
+    pub due_date: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +110,88 @@ pub struct Step {
     pub completed: bool,
     pub created_at: String,
     pub updated_at: String,
+    // None for a top-level step; `order_index` is scoped to the step's siblings
+    #[serde(rename = "parentStepId", default)]
+    pub parent_step_id: Option<String>,
+    // One of the project's statuses. Left out, it follows `completed`;
+    // given, `completed` follows it.
+    #[serde(default)]
+    pub status: Option<String>,
+    // Set by the database whenever the status changes
+    #[serde(rename = "statusChangedAt", default)]
+    pub status_changed_at: Option<String>,
+    // Same format as the project's dates
+    #[serde(rename = "startDate", default)]
+    pub start_date: Option<String>,
+    #[serde(rename = "dueDate", default)]
+    pub due_date: Option<String>,
+}
+
+// How step listings are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StepSort {
+    // Each step followed by the steps nested under it
+    #[default]
+    Tree,
+    // Soonest due first, steps without a due date last, otherwise tree order
+    DueDate,
+}
+
+// A project uses its own statuses if it has any, and the global set otherwise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepStatus {
+    pub id: String,
+    // None for the global set
+    #[serde(rename = "projectId", default)]
+    pub project_id: Option<String>,
+    pub name: String,
+    #[serde(rename = "orderIndex", default)]
+    pub order_index: i32,
+    #[serde(rename = "countsAsDone")]
+    pub counts_as_done: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StepStatusChange {
+    pub id: i64,
+    #[serde(rename = "stepId")]
+    pub step_id: String,
+    // None for the status the step was created with
+    #[serde(rename = "fromStatus")]
+    pub from_status: Option<String>,
+    #[serde(rename = "toStatus")]
+    pub to_status: String,
+    #[serde(rename = "changedAt")]
+    pub changed_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StepWithState {
+    #[serde(flatten)]
+    pub step: Step,
+    pub state: StepState,
+    // Ids of the steps this one waits on directly
+    #[serde(rename = "dependsOn")]
+    pub depends_on: Vec<String>,
+}
+
+// `step_id` can't start until `depends_on_step_id` is done
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepDependency {
+    #[serde(rename = "stepId")]
+    pub step_id: String,
+    #[serde(rename = "dependsOnStepId")]
+    pub depends_on_step_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StepNode {
+    #[serde(flatten)]
+    pub step: Step,
+    // Completed, or every step nested under it is done
+    pub done: bool,
+    pub children: Vec<StepNode>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,7 +212,7 @@ pub struct Note {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ImageAttachment {
+pub struct Attachment {
     pub id: String,
     #[serde(rename = "filePath")]
     pub file_path: String,
@@ -59,360 +225,1087 @@ pub struct ImageAttachment {
     pub content_type_enum: String,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    // Unknown for attachments whose file couldn't be read
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(rename = "byteSize", default)]
+    pub byte_size: Option<u64>,
+    // Set once the file lives in the content-addressed blob store
+    #[serde(rename = "blobHash", default)]
+    pub blob_hash: Option<String>,
+    // Metadata was stripped or the image re-encoded on upload
+    #[serde(default)]
+    pub transformed: bool,
+    // The uploaded bytes, kept only when the user opted in
+    #[serde(rename = "originalBlobHash", default)]
+    pub original_blob_hash: Option<String>,
+    // `image` or `file`; only images get thumbnails and processing
+    #[serde(default = "default_attachment_kind")]
+    pub kind: String,
+}
+
+fn default_attachment_kind() -> String {
+    ATTACHMENT_KIND_IMAGE.to_string()
+}
+
+// How uploaded images are processed before they are stored. Limits left
+// unset disable downscaling.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageProcessingSettings {
+    #[serde(rename = "stripMetadata")]
+    pub strip_metadata: bool,
+    #[serde(rename = "maxDimension")]
+    pub max_dimension: Option<u32>,
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: Option<u64>,
+    #[serde(rename = "keepOriginal")]
+    pub keep_original: bool,
+}
+
+// When automatic backups are taken and which are kept: the newest backup of
+// each of the last `keep_hourly` hours and of each of the last `keep_daily` days.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupSettings {
+    pub enabled: bool,
+    #[serde(rename = "intervalHours")]
+    pub interval_hours: u32,
+    #[serde(rename = "keepHourly")]
+    pub keep_hourly: u32,
+    #[serde(rename = "keepDaily")]
+    pub keep_daily: u32,
+    #[serde(rename = "includeAttachments")]
+    pub include_attachments: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentBlob {
+    pub hash: String,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "byteSize")]
+    pub byte_size: u64,
+    #[serde(rename = "refCount")]
+    pub ref_count: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentStorageReport {
+    pub attachments: i64,
+    pub files: i64,
+    // Bytes the attachments would take if each had its own file
+    #[serde(rename = "referencedBytes")]
+    pub referenced_bytes: i64,
+    #[serde(rename = "storedBytes")]
+    pub stored_bytes: i64,
+    #[serde(rename = "savedBytes")]
+    pub saved_bytes: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Thumbnail {
+    #[serde(rename = "attachmentId")]
+    pub attachment_id: String,
+    pub size: u32,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchFilters {
+    #[serde(rename = "projectId")]
+    pub project_id: Option<String>,
+    pub kinds: Option<Vec<String>>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub kind: String,
+    pub id: String,
+    #[serde(rename = "projectId")]
+    pub project_id: String,
+    #[serde(rename = "projectName")]
+    pub project_name: Option<String>,
+    // HTML: the text is escaped and matches are wrapped in <mark>
+    pub title: String,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub kind: String,
+    pub id: String,
+    #[serde(rename = "projectId")]
+    pub project_id: String,
+    pub title: String,
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: String,
+    #[serde(rename = "itemType")]
+    pub item_type: String,
+    #[serde(rename = "itemId")]
+    pub item_id: String,
+    pub content: String,
+    #[serde(rename = "plainText")]
+    pub plain_text: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
 }
 
 pub struct Database {
     conn: Connection,
 }
 
+// The table holding the owner of an attachment with the given `content_type_enum`.
+// `project_description` predates attachments on the project itself and is kept
+// for images embedded in the description.
+fn attachment_owner_table(content_type_enum: &str) -> Option<&'static str> {
+    match content_type_enum {
+        "project" | "project_description" => Some("projects"),
+        "step" => Some("steps"),
+        "note" => Some("notes"),
+        _ => None,
+    }
+}
+
+// Search marks matches with control characters rather than tags, so the
+// indexed text can be escaped before the marks become <mark> elements
+fn highlighted_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2}' => html.push_str("<mark>"),
+            '\u{3}' => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+// Mutations that touch no rows report `QueryReturnedNoRows`, which the command
+// layer surfaces as a not-found error instead of silently succeeding.
+fn ensure_found(rows: usize) -> Result<()> {
+    if rows == 0 {
+        Err(Error::QueryReturnedNoRows)
+    } else {
+        Ok(())
+    }
+}
+
 impl Database {
-    pub fn new(db_path: &Path) -> Result<Self> {
-        let is_new_db = !db_path.exists();
-        let conn = Connection::open(db_path)?;
-        
-        // Create schema version table first
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS schema_version (
-                version INTEGER PRIMARY KEY
-            )",
-            [],
-        )?;
-        
-        let current_version = Self::get_schema_version(&conn)?;
-        println!("Database version: {}", current_version);
-        
-        if is_new_db {
-            println!("Creating new database at: {}", db_path.display());
-            Self::create_initial_schema(&conn)?;
-            Self::set_schema_version(&conn, 6)?;
-        } else {
+    pub fn new(db_path: &Path) -> Result<Self, AppError> {
+        if db_path.exists() {
             println!("Using existing database at: {}", db_path.display());
-            Self::apply_migrations(&conn, current_version)?;
+        } else {
+            println!("Creating new database at: {}", db_path.display());
         }
+        let conn = Connection::open(db_path)?;
+        // The bundled SQLite turns foreign keys on for every connection. A
+        // migration that rebuilds a table drops the old one, which would run
+        // the ON DELETE actions of every table pointing at it, so they stay
+        // off until the schema is current.
+        conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+
+        let current_version = migrations::get_schema_version(&conn)?;
+        println!("Database version: {}", current_version);
+
+        migrations::run(&conn)?;
+
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
 
         Ok(Database { conn })
     }
-    
-    fn get_schema_version(conn: &Connection) -> Result<i32> {
-        let version: i32 = conn.query_row(
-            "SELECT version FROM schema_version ORDER BY version DESC LIMIT 1",
-            [],
-            |row| row.get(0)
-        ).unwrap_or(0);
-        Ok(version)
+
+    // Runs `f` in a single transaction that is committed only if it succeeds.
+    // Methods that open their own transaction can't be called from `f`; the
+    // `write_*` variants exist for that.
+    pub fn with_transaction<T, E: From<Error>>(&self, f: impl FnOnce() -> std::result::Result<T, E>) -> std::result::Result<T, E> {
+        let tx = self.conn.unchecked_transaction()?;
+        let value = f()?;
+        tx.commit()?;
+        Ok(value)
     }
-    
-    fn set_schema_version(conn: &Connection, version: i32) -> Result<()> {
-        conn.execute(
-            "INSERT OR REPLACE INTO schema_version (version) VALUES (?1)",
-            [version],
-        )?;
+
+    // Writes a consistent copy of the database to `path` while it stays open.
+    pub fn copy_to(&self, path: &Path) -> Result<()> {
+        self.conn.execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
         Ok(())
     }
-    
-    fn create_initial_schema(conn: &Connection) -> Result<()> {
-        conn.execute(
-            "CREATE TABLE projects (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                description TEXT,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL,
-                gradient TEXT NOT NULL,
-                current_step_id TEXT
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE steps (
-                id TEXT PRIMARY KEY,
-                project_id TEXT NOT NULL,
-                title TEXT NOT NULL,
-                description TEXT,
-                plain_text TEXT,
-                order_index INTEGER NOT NULL,
-                completed INTEGER NOT NULL DEFAULT 0,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL,
-                FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
 
-        conn.execute(
-            "CREATE TABLE notes (
-                id TEXT PRIMARY KEY,
-                project_id TEXT NOT NULL,
-                title TEXT NOT NULL,
-                content TEXT NOT NULL,
-                plain_text TEXT NOT NULL,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL,
-                is_important INTEGER DEFAULT 0,
-                FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+    // Snapshots the database into `path` with SQLite's online backup API.
+    // Unlike `copy_to` the pages are copied as they are, so the snapshot is
+    // an exact image of the live file.
+    pub fn backup_to(&self, path: &Path) -> Result<()> {
+        let mut dst = Connection::open(path)?;
+        let backup = Backup::new(&self.conn, &mut dst)?;
+        backup.run_to_completion(BACKUP_PAGES_PER_STEP, std::time::Duration::ZERO, None)
+    }
 
-        conn.execute(
-            "CREATE TABLE image_attachments (
-                id TEXT PRIMARY KEY,
-                file_path TEXT NOT NULL,
-                filename TEXT NOT NULL,
-                content_type TEXT NOT NULL,
-                content_id TEXT NOT NULL,
-                content_type_enum TEXT NOT NULL CHECK (content_type_enum IN ('step', 'note', 'project_description')),
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        println!("Initial database schema created successfully");
-        Ok(())
+    // Replaces the live database with the snapshot at `path` and migrates it
+    // to the current schema. The connection stays open throughout.
+    pub fn restore_from(&mut self, path: &Path) -> Result<(), AppError> {
+        let src = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        // Off for the same reason as in `new`: migrations may rebuild tables
+        self.conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+        let result = Backup::new(&src, &mut self.conn)
+            .and_then(|backup| backup.run_to_completion(BACKUP_PAGES_PER_STEP, std::time::Duration::ZERO, None))
+            .map_err(AppError::from)
+            .and_then(|_| migrations::run(&self.conn));
+        self.conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        result
     }
-    
-    fn apply_migrations(conn: &Connection, current_version: i32) -> Result<()> {
-        let latest_version = 6; // Update this when adding new migrations
-        
-        if current_version < latest_version {
-            println!("Applying database migrations from version {} to {}", current_version, latest_version);
-            
-            if current_version < 2 {
-                conn.execute("ALTER TABLE projects ADD COLUMN current_step_id TEXT", [])?;
-                Self::set_schema_version(conn, 2)?;
-            }
-            
-            if current_version < 3 {
-                conn.execute(
-                    "CREATE TABLE IF NOT EXISTS notes (
-                        id TEXT PRIMARY KEY,
-                        project_id TEXT NOT NULL,
-                        title TEXT NOT NULL,
-                        content TEXT NOT NULL,
-                        plain_text TEXT NOT NULL,
-                        created_at TEXT NOT NULL,
-                        updated_at TEXT NOT NULL,
-                        FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
-                    )",
-                    [],
-                )?;
-                Self::set_schema_version(conn, 3)?;
-            }
-            
-            if current_version < 4 {
-                conn.execute("ALTER TABLE steps ADD COLUMN plain_text TEXT", [])?;
-                Self::set_schema_version(conn, 4)?;
-            }
-            
-            if current_version < 5 {
-                conn.execute(
-                    "CREATE TABLE IF NOT EXISTS image_attachments (
-                        id TEXT PRIMARY KEY,
-                        file_path TEXT NOT NULL,
-                        filename TEXT NOT NULL,
-                        content_type TEXT NOT NULL,
-                        content_id TEXT NOT NULL,
-                        content_type_enum TEXT NOT NULL CHECK (content_type_enum IN ('step', 'note', 'project_description')),
-                        created_at TEXT NOT NULL
-                    )",
-                    [],
-                )?;
-                Self::set_schema_version(conn, 5)?;
-            }
-            
-            if current_version < 6 {
-                conn.execute("ALTER TABLE notes ADD COLUMN is_important INTEGER DEFAULT 0", [])?;
-                Self::set_schema_version(conn, 6)?;
-            }
-            
-            println!("Database migrations completed");
+
+    // Turns free-form user input into an FTS5 query: every word becomes a quoted
+    // prefix term so punctuation in the input can't produce a syntax error.
+    fn build_match_query(query: &str) -> Option<String> {
+        let terms: Vec<String> = query
+            .split_whitespace()
+            .map(|term| term.replace('"', ""))
+            .filter(|term| !term.is_empty())
+            .map(|term| format!("\"{}\"*", term))
+            .collect();
+
+        if terms.is_empty() {
+            None
         } else {
-            println!("Database is up to date (version {})", current_version);
+            Some(terms.join(" "))
         }
-        
-        Ok(())
     }
 
-    pub fn get_all_projects(&self) -> Result<Vec<Project>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, name, description, created_at, updated_at, gradient, current_step_id FROM projects ORDER BY created_at DESC"
-        )?;
-        
-        let projects = stmt.query_map([], |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-                gradient: row.get(5)?,
-                current_step_id: row.get(6)?,
+    pub fn search(&self, query: &str, filters: &SearchFilters) -> Result<Vec<SearchHit>> {
+        let match_query = match Self::build_match_query(query) {
+            Some(q) => q,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut sql = String::from(
+            "SELECT s.kind, s.item_id, s.project_id, p.name,
+                    highlight(search_index, 3, char(2), char(3)),
+                    snippet(search_index, 4, char(2), char(3), '…', 16),
+                    bm25(search_index, 0.0, 0.0, 0.0, 10.0, 1.0) AS rank
+             FROM search_index s
+             LEFT JOIN projects p ON p.id = s.project_id
+             WHERE search_index MATCH ?"
+        );
+        let mut values: Vec<String> = vec![match_query];
+
+        if let Some(project_id) = &filters.project_id {
+            sql.push_str(" AND s.project_id = ?");
+            values.push(project_id.clone());
+        }
+
+        if let Some(kinds) = filters.kinds.as_ref().filter(|k| !k.is_empty()) {
+            let placeholders = vec!["?"; kinds.len()].join(", ");
+            sql.push_str(&format!(" AND s.kind IN ({})", placeholders));
+            values.extend(kinds.iter().cloned());
+        }
+
+        sql.push_str(&format!(" ORDER BY rank LIMIT {}", filters.limit.unwrap_or(50)));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let hits = stmt.query_map(params_from_iter(values.iter()), |row| {
+            Ok(SearchHit {
+                kind: row.get(0)?,
+                id: row.get(1)?,
+                project_id: row.get(2)?,
+                project_name: row.get(3)?,
+                title: highlighted_html(&row.get::<_, String>(4)?),
+                snippet: highlighted_html(&row.get::<_, String>(5)?),
+                rank: row.get(6)?,
             })
         })?;
 
+        hits.collect()
+    }
+
+    pub fn get_all_projects(&self) -> Result<Vec<Project>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM projects WHERE deleted_at IS NULL ORDER BY created_at DESC",
+            PROJECT_COLUMNS
+        ))?;
+        let projects = stmt.query_map([], Self::row_to_project)?;
         projects.collect()
     }
 
-    pub fn create_project(&self, project: &Project) -> Result<()> {
+    pub fn get_project(&self, project_id: &str) -> Result<Project> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM projects WHERE id = ?1 AND deleted_at IS NULL", PROJECT_COLUMNS),
+            [project_id],
+            Self::row_to_project,
+        )
+    }
+
+    fn row_to_project(row: &rusqlite::Row) -> Result<Project> {
+        Ok(Project {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+            gradient: row.get(5)?,
+            current_step_id: row.get(6)?,
+            start_date: row.get(7)?,
+            due_date: row.get(8)?,
+        })
+    }
+
+    pub fn create_project(&self, project: &Project) -> Result<(), AppError> {
         let current_step_id = project.current_step_id.as_deref().unwrap_or("");
+        let (start_date, due_date) = self.normalize_dates(project.start_date.as_deref(), project.due_date.as_deref())?;
         self.conn.execute(
-            "INSERT INTO projects (id, name, description, created_at, updated_at, gradient, current_step_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            [&project.id, &project.name, &project.description, &project.created_at, &project.updated_at, &project.gradient, current_step_id],
+            "INSERT INTO projects (id, name, description, created_at, updated_at, gradient, current_step_id, start_date, due_date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                &project.id,
+                &project.name,
+                &project.description,
+                &project.created_at,
+                &project.updated_at,
+                &project.gradient,
+                current_step_id,
+                start_date,
+                due_date,
+            ],
         )?;
         Ok(())
     }
 
-    pub fn update_project(&self, project: &Project) -> Result<()> {
+    pub fn update_project(&self, project: &Project) -> Result<(), AppError> {
         let current_step_id = project.current_step_id.as_deref().unwrap_or("");
-        self.conn.execute(
-            "UPDATE projects SET name = ?1, description = ?2, updated_at = ?3, gradient = ?4, current_step_id = ?5 WHERE id = ?6",
-            [&project.name, &project.description, &project.updated_at, &project.gradient, current_step_id, &project.id],
+        let (start_date, due_date) = self.normalize_dates(project.start_date.as_deref(), project.due_date.as_deref())?;
+        let updated = self.conn.execute(
+            "UPDATE projects SET name = ?1, description = ?2, updated_at = ?3, gradient = ?4, current_step_id = ?5, start_date = ?6,
+             due_date = ?7 WHERE id = ?8 AND deleted_at IS NULL",
+            params![
+                &project.name,
+                &project.description,
+                &project.updated_at,
+                &project.gradient,
+                current_step_id,
+                start_date,
+                due_date,
+                &project.id,
+            ],
         )?;
-        Ok(())
+        Ok(ensure_found(updated)?)
     }
 
+    // Checks a start and due date and puts them in their stored form
+    fn normalize_dates(&self, start: Option<&str>, due: Option<&str>) -> Result<(Option<String>, Option<String>), AppError> {
+        schedule::normalize_dates(start, due, &self.get_time_zone()?).map_err(AppError::InvalidInput)
+    }
+
+    // Moves the project to the trash together with its steps, notes and
+    // attachments. Children share the project's `deleted_at` so that restoring
+    // the project brings back exactly what was trashed with it.
     pub fn delete_project(&self, project_id: &str) -> Result<()> {
-        // Delete all steps for this project first
-        self.conn.execute(
-            "DELETE FROM steps WHERE project_id = ?1",
-            [project_id],
+        let deleted_at = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute(
+            &format!("UPDATE attachments SET deleted_at = ?2 WHERE deleted_at IS NULL AND ({})", PROJECT_ATTACHMENTS),
+            [project_id, &deleted_at],
         )?;
-        
-        // Then delete the project
-        self.conn.execute(
-            "DELETE FROM projects WHERE id = ?1",
-            [project_id],
+        tx.execute(
+            "UPDATE steps SET deleted_at = ?2 WHERE project_id = ?1 AND deleted_at IS NULL",
+            [project_id, &deleted_at],
+        )?;
+        tx.execute(
+            "UPDATE notes SET deleted_at = ?2 WHERE project_id = ?1 AND deleted_at IS NULL",
+            [project_id, &deleted_at],
         )?;
+        let updated = tx.execute(
+            "UPDATE projects SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            [project_id, &deleted_at],
+        )?;
+        ensure_found(updated)?;
+
+        tx.commit()?;
         Ok(())
     }
 
     pub fn update_project_current_step(&self, project_id: &str, step_id: Option<&str>) -> Result<()> {
-        self.conn.execute(
-            "UPDATE projects SET current_step_id = ?1 WHERE id = ?2",
+        let updated = self.conn.execute(
+            "UPDATE projects SET current_step_id = ?1 WHERE id = ?2 AND deleted_at IS NULL",
             [step_id.unwrap_or(""), project_id],
         )?;
-        Ok(())
+        ensure_found(updated)
     }
 
+    // Steps in tree order: each step is followed by the steps nested under it
     pub fn get_steps_by_project(&self, project_id: &str) -> Result<Vec<Step>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, project_id, title, description, plain_text, order_index, completed, created_at, updated_at 
-             FROM steps WHERE project_id = ?1 ORDER BY order_index"
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM steps WHERE project_id = ?1 AND deleted_at IS NULL ORDER BY order_index",
+            STEP_COLUMNS
+        ))?;
+        let steps = stmt.query_map([project_id], Self::row_to_step)?;
+        Ok(tree_order(steps.collect::<Result<_>>()?))
+    }
+
+    pub fn get_all_steps(&self) -> Result<Vec<Step>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM steps WHERE deleted_at IS NULL ORDER BY project_id, order_index",
+            STEP_COLUMNS
+        ))?;
+        let steps = stmt.query_map([], Self::row_to_step)?;
+        Ok(tree_order(steps.collect::<Result<_>>()?))
+    }
+
+    pub fn get_step(&self, step_id: &str) -> Result<Step> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM steps WHERE id = ?1 AND deleted_at IS NULL", STEP_COLUMNS),
+            [step_id],
+            Self::row_to_step,
+        )
+    }
+
+    // The project's steps nested under their parents, with completion rolled up
+    pub fn get_step_tree(&self, project_id: &str) -> Result<Vec<StepNode>> {
+        let mut children = group_by_parent(self.get_steps_by_project(project_id)?);
+        Ok(build_step_tree(None, &mut children))
+    }
+
+    fn row_to_step(row: &rusqlite::Row) -> Result<Step> {
+        Ok(Step {
+            id: row.get(0)?,
+            project_id: row.get(1)?,
+            title: row.get(2)?,
+            description: row.get(3)?,
+            plain_text: row.get(4)?,
+            order_index: row.get(5)?,
+            completed: row.get::<_, i32>(6)? != 0,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+            parent_step_id: row.get(9)?,
+            status: row.get(10)?,
+            status_changed_at: row.get(11)?,
+            start_date: row.get(12)?,
+            due_date: row.get(13)?,
+        })
+    }
+
+    // Steps and notes can only be added to a live project
+    fn check_project_live(&self, project_id: &str) -> Result<(), AppError> {
+        let live: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM projects WHERE id = ?1 AND deleted_at IS NULL)",
+            [project_id],
+            |row| row.get(0),
         )?;
-        
-        let steps = stmt.query_map([project_id], |row| {
-            Ok(Step {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                title: row.get(2)?,
-                description: row.get(3)?,
-                plain_text: row.get(4)?,
-                order_index: row.get(5)?,
-                completed: row.get::<_, i32>(6)? != 0,
-                created_at: row.get(7)?,
-                updated_at: row.get(8)?,
-            })
-        })?;
+        if live {
+            Ok(())
+        } else {
+            Err(AppError::InvalidInput(format!("Cannot add to project {}: it does not exist", project_id)))
+        }
+    }
 
-        steps.collect()
+    // A parent step must be live and in the same project
+    fn check_step_parent(&self, project_id: &str, parent_step_id: &str) -> Result<(), AppError> {
+        let parent_project: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT project_id FROM steps WHERE id = ?1 AND deleted_at IS NULL",
+                [parent_step_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match parent_project {
+            None => Err(AppError::InvalidInput(format!("Parent step {} doesn't exist", parent_step_id))),
+            Some(parent_project) if parent_project != project_id => Err(AppError::InvalidInput(
+                "A step can only be nested under a step of the same project".to_string(),
+            )),
+            Some(_) => Ok(()),
+        }
     }
 
-    pub fn get_all_steps(&self) -> Result<Vec<Step>> {
+    // Live steps under `parent_step_id`, or the top-level ones when None, in order
+    fn sibling_ids(&self, project_id: &str, parent_step_id: Option<&str>) -> Result<Vec<String>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, project_id, title, description, plain_text, order_index, completed, created_at, updated_at 
-             FROM steps ORDER BY project_id, order_index"
+            "SELECT id FROM steps WHERE project_id = ?1 AND parent_step_id IS ?2 AND deleted_at IS NULL
+             ORDER BY order_index",
         )?;
-        
-        let steps = stmt.query_map([], |row| {
-            Ok(Step {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                title: row.get(2)?,
-                description: row.get(3)?,
-                plain_text: row.get(4)?,
-                order_index: row.get(5)?,
-                completed: row.get::<_, i32>(6)? != 0,
-                created_at: row.get(7)?,
-                updated_at: row.get(8)?,
-            })
-        })?;
+        let ids = stmt.query_map((project_id, parent_step_id), |row| row.get(0))?;
+        ids.collect()
+    }
 
-        steps.collect()
+    fn renumber_steps(&self, step_ids: &[String]) -> Result<()> {
+        for (order_index, step_id) in step_ids.iter().enumerate() {
+            self.conn.execute(
+                "UPDATE steps SET order_index = ?1 WHERE id = ?2",
+                (order_index as i32, step_id),
+            )?;
+        }
+        Ok(())
     }
 
-    pub fn create_step(&self, step: &Step) -> Result<()> {
+    // Moves a step, together with the steps nested under it, to position
+    // `index` among the children of `parent_step_id` (the top-level steps when
+    // None). Both the old and the new siblings are renumbered.
+    pub fn move_step(&self, step_id: &str, parent_step_id: Option<&str>, index: usize) -> Result<(), AppError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let step = self.get_step(step_id)?;
+
+        if let Some(parent_step_id) = parent_step_id {
+            self.check_step_parent(&step.project_id, parent_step_id)?;
+            let inside: bool = self.conn.query_row(
+                &format!("SELECT ?2 IN ({})", STEP_SUBTREE),
+                [step_id, parent_step_id],
+                |row| row.get(0),
+            )?;
+            if inside {
+                return Err(AppError::InvalidInput(
+                    "A step can't be moved under itself or a step nested under it".to_string(),
+                ));
+            }
+        }
+
+        let mut old_siblings = self.sibling_ids(&step.project_id, step.parent_step_id.as_deref())?;
+        old_siblings.retain(|id| id != step_id);
+        self.renumber_steps(&old_siblings)?;
+
         self.conn.execute(
-            "INSERT INTO steps (id, project_id, title, description, plain_text, order_index, completed, created_at, updated_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            [
-                &step.id, 
-                &step.project_id, 
-                &step.title, 
-                &step.description,
-                step.plain_text.as_deref().unwrap_or(""),
-                &step.order_index.to_string(),
-                &(if step.completed { "1" } else { "0" }).to_string(),
-                &step.created_at, 
-                &step.updated_at
-            ],
+            "UPDATE steps SET parent_step_id = ?1, updated_at = ?2 WHERE id = ?3",
+            (parent_step_id, Utc::now().to_rfc3339(), step_id),
         )?;
+        let mut new_siblings = self.sibling_ids(&step.project_id, parent_step_id)?;
+        new_siblings.retain(|id| id != step_id);
+        new_siblings.insert(index.min(new_siblings.len()), step_id.to_string());
+        self.renumber_steps(&new_siblings)?;
+
+        // Nesting a step under one it depends on, or the other way round, could never finish
+        let steps = self.get_steps_by_project(&step.project_id)?;
+        if planning::has_cycle(&steps, &self.get_step_dependencies(&step.project_id)?) {
+            return Err(AppError::InvalidInput(
+                "That move would make steps wait on each other through their dependencies".to_string(),
+            ));
+        }
+
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn update_step(&self, step: &Step) -> Result<()> {
+    pub fn create_step(&self, step: &Step) -> Result<(), AppError> {
+        self.check_project_live(&step.project_id)?;
+        if let Some(parent_step_id) = &step.parent_step_id {
+            self.check_step_parent(&step.project_id, parent_step_id)?;
+        }
+        let (status, completed) = self.resolve_step_status(&step.project_id, step.status.as_deref(), step.completed, None)?;
+        let (start_date, due_date) = self.normalize_dates(step.start_date.as_deref(), step.due_date.as_deref())?;
+        let now = Utc::now().to_rfc3339();
         self.conn.execute(
-            "UPDATE steps SET title = ?1, description = ?2, plain_text = ?3, order_index = ?4, completed = ?5, updated_at = ?6 
-             WHERE id = ?7",
-            [
+            "INSERT INTO steps (id, project_id, title, description, plain_text, order_index, completed, created_at, updated_at, parent_step_id,
+                                status, status_changed_at, start_date, due_date) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                &step.id,
+                &step.project_id,
                 &step.title,
                 &step.description,
                 step.plain_text.as_deref().unwrap_or(""),
-                &step.order_index.to_string(),
-                &(if step.completed { "1" } else { "0" }).to_string(),
+                step.order_index,
+                completed as i32,
+                &step.created_at,
                 &step.updated_at,
-                &step.id,
+                &step.parent_step_id,
+                &status,
+                &now,
+                start_date,
+                due_date,
             ],
         )?;
+        self.conn.execute(
+            "INSERT INTO step_status_changes (step_id, from_status, to_status, changed_at) VALUES (?1, NULL, ?2, ?3)",
+            [&step.id, &status, &now],
+        )?;
         Ok(())
     }
 
-    pub fn update_steps_batch(&self, steps: &[Step]) -> Result<()> {
+    // Returns the project's current step afterwards, which moves on when the
+    // step being completed was current
+    pub fn update_step(&self, step: &Step) -> Result<Option<String>, AppError> {
         let tx = self.conn.unchecked_transaction()?;
-        
-        for step in steps {
-            tx.execute(
-                "UPDATE steps SET title = ?1, description = ?2, plain_text = ?3, order_index = ?4, completed = ?5, updated_at = ?6 
-                 WHERE id = ?7",
-                [
-                    &step.title,
-                    &step.description,
-                    step.plain_text.as_deref().unwrap_or(""),
-                    &step.order_index.to_string(),
-                    &(if step.completed { "1" } else { "0" }).to_string(),
-                    &step.updated_at,
-                    &step.id,
-                ],
-            )?;
-        }
-        
-        tx.commit()?;
-        Ok(())
+        let was_completed: Option<bool> = self
+            .conn
+            .query_row("SELECT completed FROM steps WHERE id = ?1", [&step.id], |row| row.get(0))
+            .optional()?;
+        self.write_step(step)?;
+        // The status may have decided otherwise than `step.completed`
+        let completed: bool = self.conn.query_row("SELECT completed FROM steps WHERE id = ?1", [&step.id], |row| row.get(0))?;
+
+        let current_step_id = if completed && was_completed == Some(false) {
+            self.advance_current_step(&step.project_id, &step.id)?
+        } else {
+            self.get_project(&step.project_id)?.current_step_id.filter(|id| !id.is_empty())
+        };
+
+        tx.commit()?;
+        Ok(current_step_id)
+    }
+
+    // Picks a new current step after `completed_step_id` was completed, if the
+    // current one is unset, was that step, or is now done through roll-up
+    fn advance_current_step(&self, project_id: &str, completed_step_id: &str) -> Result<Option<String>> {
+        let current_step_id = self.get_project(project_id)?.current_step_id.filter(|id| !id.is_empty());
+        let steps = self.get_steps_by_project(project_id)?;
+        let dependencies = self.get_step_dependencies(project_id)?;
+        let plan = planning::plan(&steps, &dependencies);
+
+        let keep = current_step_id.as_ref().is_some_and(|id| {
+            id != completed_step_id && plan.states.get(id).is_some_and(|state| *state != StepState::Done)
+        });
+        if keep {
+            return Ok(current_step_id);
+        }
+
+        let next = planning::next_current_step(&plan, &steps, &dependencies, completed_step_id);
+        self.update_project_current_step(project_id, next.as_deref())?;
+        Ok(next)
+    }
+
+    // Dependencies between the project's live steps
+    pub fn get_step_dependencies(&self, project_id: &str) -> Result<Vec<StepDependency>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT d.step_id, d.depends_on_step_id FROM step_dependencies d
+             JOIN steps s ON s.id = d.step_id
+             JOIN steps o ON o.id = d.depends_on_step_id
+             WHERE s.project_id = ?1 AND s.deleted_at IS NULL AND o.deleted_at IS NULL
+             ORDER BY d.created_at",
+        )?;
+        let dependencies = stmt.query_map([project_id], |row| {
+            Ok(StepDependency {
+                step_id: row.get(0)?,
+                depends_on_step_id: row.get(1)?,
+            })
+        })?;
+        dependencies.collect()
     }
 
-    pub fn delete_step(&self, step_id: &str) -> Result<()> {
+    // Both steps must be live and in the same project, and the new dependency
+    // must not leave steps waiting on each other. Adding one that exists is a no-op.
+    pub fn add_step_dependency(&self, step_id: &str, depends_on_step_id: &str) -> Result<(), AppError> {
+        if step_id == depends_on_step_id {
+            return Err(AppError::InvalidInput("A step can't depend on itself".to_string()));
+        }
+        let step = self.get_step(step_id)?;
+        if self.get_step(depends_on_step_id)?.project_id != step.project_id {
+            return Err(AppError::InvalidInput(
+                "A step can only depend on a step of the same project".to_string(),
+            ));
+        }
+
+        let steps = self.get_steps_by_project(&step.project_id)?;
+        let mut dependencies = self.get_step_dependencies(&step.project_id)?;
+        dependencies.push(StepDependency {
+            step_id: step_id.to_string(),
+            depends_on_step_id: depends_on_step_id.to_string(),
+        });
+        if planning::has_cycle(&steps, &dependencies) {
+            return Err(AppError::InvalidInput(
+                "That dependency would make steps wait on each other".to_string(),
+            ));
+        }
+
         self.conn.execute(
-            "DELETE FROM steps WHERE id = ?1",
-            [step_id],
+            "INSERT OR IGNORE INTO step_dependencies (step_id, depends_on_step_id, created_at) VALUES (?1, ?2, ?3)",
+            [step_id, depends_on_step_id, &Utc::now().to_rfc3339()],
         )?;
         Ok(())
     }
 
+    pub fn remove_step_dependency(&self, step_id: &str, depends_on_step_id: &str) -> Result<()> {
+        let deleted = self.conn.execute(
+            "DELETE FROM step_dependencies WHERE step_id = ?1 AND depends_on_step_id = ?2",
+            [step_id, depends_on_step_id],
+        )?;
+        ensure_found(deleted)
+    }
+
+    // The project's steps in tree order with whether each is blocked, ready or done
+    pub fn get_steps_with_state(&self, project_id: &str) -> Result<Vec<StepWithState>> {
+        let steps = self.get_steps_by_project(project_id)?;
+        let dependencies = self.get_step_dependencies(project_id)?;
+        let mut plan = planning::plan(&steps, &dependencies);
+        Ok(with_state(steps, &dependencies, &mut plan.states))
+    }
+
+    // The steps left to do, without parent steps, ordered so that each comes
+    // after everything it waits on. The ready ones can be started now.
+    pub fn get_next_steps(&self, project_id: &str) -> Result<Vec<StepWithState>> {
+        let steps = self.get_steps_by_project(project_id)?;
+        let dependencies = self.get_step_dependencies(project_id)?;
+        let mut plan = planning::plan(&steps, &dependencies);
+
+        let position: HashMap<&str, usize> = plan.next.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+        let mut next: Vec<(usize, Step)> = steps
+            .into_iter()
+            .filter_map(|step| Some((*position.get(step.id.as_str())?, step)))
+            .collect();
+        next.sort_by_key(|(position, _)| *position);
+        Ok(with_state(next.into_iter().map(|(_, step)| step).collect(), &dependencies, &mut plan.states))
+    }
+
+    // Stable, so items due together or without a due date keep their order
+    pub fn sort_by_due_date<T>(&self, items: &mut [T], due_date: impl Fn(&T) -> Option<&str>) -> Result<()> {
+        let zone = self.get_time_zone()?;
+        items.sort_by_key(|item| schedule::due_order(due_date(item), &zone));
+        Ok(())
+    }
+
+    // Live projects and steps with a start or due date. A project counts as
+    // done once it has steps and all of them are completed.
+    fn get_dated_items(&self) -> Result<Vec<Dated>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT p.id, p.name, p.start_date, p.due_date,
+                    EXISTS (SELECT 1 FROM steps s WHERE s.project_id = p.id AND s.deleted_at IS NULL)
+                    AND NOT EXISTS (SELECT 1 FROM steps s WHERE s.project_id = p.id AND s.deleted_at IS NULL AND s.completed = 0)
+             FROM projects p
+             WHERE p.deleted_at IS NULL AND (p.start_date IS NOT NULL OR p.due_date IS NOT NULL)",
+        )?;
+        let projects = stmt.query_map([], |row| {
+            Ok(Dated {
+                kind: AgendaItemKind::Project,
+                id: row.get(0)?,
+                project_id: row.get(0)?,
+                project_name: row.get(1)?,
+                title: row.get(1)?,
+                start_date: row.get(2)?,
+                due_date: row.get(3)?,
+                done: row.get(4)?,
+            })
+        })?;
+        let mut dated = projects.collect::<Result<Vec<_>>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.project_id, p.name, s.title, s.start_date, s.due_date, s.completed
+             FROM steps s JOIN projects p ON p.id = s.project_id
+             WHERE s.deleted_at IS NULL AND p.deleted_at IS NULL AND (s.start_date IS NOT NULL OR s.due_date IS NOT NULL)",
+        )?;
+        let steps = stmt.query_map([], |row| {
+            Ok(Dated {
+                kind: AgendaItemKind::Step,
+                id: row.get(0)?,
+                project_id: row.get(1)?,
+                project_name: row.get(2)?,
+                title: row.get(3)?,
+                start_date: row.get(4)?,
+                due_date: row.get(5)?,
+                done: row.get(6)?,
+            })
+        })?;
+        for step in steps {
+            dated.push(step?);
+        }
+        Ok(dated)
+    }
+
+    // Projects and steps across all projects that are overdue, or due today
+    // or this week in the configured time zone
+    pub fn get_due_items(&self, window: DueWindow) -> Result<Vec<AgendaItem>> {
+        let dated = self.get_dated_items()?;
+        Ok(schedule::due_items(&dated, window, &self.get_time_zone()?, Utc::now()))
+    }
+
+    // Start and due dates of projects and steps falling between the days
+    // `from` and `to` (inclusive, "YYYY-MM-DD"), in date order
+    pub fn get_agenda(&self, from: &str, to: &str) -> Result<Vec<AgendaItem>, AppError> {
+        let (Some(from), Some(to)) = (schedule::parse_day(from), schedule::parse_day(to)) else {
+            return Err(AppError::InvalidInput("Agenda ranges are given as YYYY-MM-DD days".to_string()));
+        };
+        if from > to {
+            return Err(AppError::InvalidInput("The agenda range ends before it starts".to_string()));
+        }
+        let dated = self.get_dated_items()?;
+        Ok(schedule::agenda(&dated, from, to, &self.get_time_zone()?, Utc::now()))
+    }
+
+    pub fn update_steps_batch(&self, steps: &[Step]) -> Result<(), AppError> {
+        let tx = self.conn.unchecked_transaction()?;
+        
+        for step in steps {
+            self.write_step(step)?;
+        }
+        
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Records a revision and saves the step, noting a status change. The
+    // caller provides the transaction. The parent is left alone; `move_step`
+    // changes it.
+    pub fn write_step(&self, step: &Step) -> Result<(), AppError> {
+        let (project_id, previous_status): (String, Option<String>) = self.conn.query_row(
+            "SELECT project_id, status FROM steps WHERE id = ?1 AND deleted_at IS NULL",
+            [&step.id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (status, completed) =
+            self.resolve_step_status(&project_id, step.status.as_deref(), step.completed, previous_status.as_deref())?;
+
+        let (start_date, due_date) = self.normalize_dates(step.start_date.as_deref(), step.due_date.as_deref())?;
+
+        Self::record_revision(&self.conn, "step", &step.id, &step.description, false)?;
+        let updated = self.conn.execute(
+            "UPDATE steps SET title = ?1, description = ?2, plain_text = ?3, order_index = ?4, completed = ?5, updated_at = ?6,
+             start_date = ?7, due_date = ?8 WHERE id = ?9 AND deleted_at IS NULL",
+            params![
+                &step.title,
+                &step.description,
+                step.plain_text.as_deref().unwrap_or(""),
+                step.order_index,
+                completed as i32,
+                &step.updated_at,
+                start_date,
+                due_date,
+                &step.id,
+            ],
+        )?;
+        ensure_found(updated)?;
+
+        if previous_status.as_deref() != Some(status.as_str()) {
+            self.change_step_status(&step.id, previous_status.as_deref(), &status, &Utc::now().to_rfc3339())?;
+        }
+        Ok(())
+    }
+
+    // Sets the step's status, keeping the rest of it. Returns the project's
+    // current step like `update_step`.
+    pub fn set_step_status(&self, step_id: &str, status: &str) -> Result<Option<String>, AppError> {
+        let mut step = self.get_step(step_id)?;
+        step.status = Some(status.to_string());
+        step.updated_at = Utc::now().to_rfc3339();
+        self.update_step(&step)
+    }
+
+    // The status a step gets and whether that makes it completed. A requested
+    // status must belong to the project, and wins unless it is the previous
+    // one and only `completed` was changed. Otherwise the previous status is
+    // kept while it agrees with `completed`, and the first status that does
+    // is picked when it doesn't.
+    fn resolve_step_status(
+        &self,
+        project_id: &str,
+        requested: Option<&str>,
+        completed: bool,
+        previous: Option<&str>,
+    ) -> Result<(String, bool), AppError> {
+        let statuses = self.get_step_statuses(Some(project_id))?;
+        let requested = match requested {
+            Some(requested) => Some(statuses.iter().find(|status| status.id == requested).ok_or_else(|| {
+                AppError::InvalidInput(format!("'{}' is not one of the project's step statuses", requested))
+            })?),
+            None => None,
+        };
+        let status = match requested {
+            Some(requested) if Some(requested.id.as_str()) != previous || requested.counts_as_done == completed => requested,
+            _ => statuses
+                .iter()
+                .find(|status| Some(status.id.as_str()) == previous && status.counts_as_done == completed)
+                .or_else(|| statuses.iter().find(|status| status.counts_as_done == completed))
+                .or(statuses.first())
+                .ok_or_else(|| AppError::InvalidInput("No step statuses are configured".to_string()))?,
+        };
+        Ok((status.id.clone(), status.counts_as_done))
+    }
+
+    fn change_step_status(&self, step_id: &str, from: Option<&str>, to: &str, changed_at: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE steps SET status = ?2, status_changed_at = ?3 WHERE id = ?1",
+            [step_id, to, changed_at],
+        )?;
+        self.conn.execute(
+            "INSERT INTO step_status_changes (step_id, from_status, to_status, changed_at) VALUES (?1, ?2, ?3, ?4)",
+            (step_id, from, to, changed_at),
+        )?;
+        Ok(())
+    }
+
+    // Oldest first
+    pub fn get_step_status_history(&self, step_id: &str) -> Result<Vec<StepStatusChange>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, step_id, from_status, to_status, changed_at FROM step_status_changes
+             WHERE step_id = ?1 ORDER BY changed_at, id",
+        )?;
+        let changes = stmt.query_map([step_id], |row| {
+            Ok(StepStatusChange {
+                id: row.get(0)?,
+                step_id: row.get(1)?,
+                from_status: row.get(2)?,
+                to_status: row.get(3)?,
+                changed_at: row.get(4)?,
+            })
+        })?;
+        changes.collect()
+    }
+
+    // The statuses a project's steps can have, in order. None gives the global set.
+    pub fn get_step_statuses(&self, project_id: Option<&str>) -> Result<Vec<StepStatus>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM step_statuses WHERE project_id IS ?1 ORDER BY order_index",
+            STEP_STATUS_COLUMNS
+        ))?;
+        let statuses = stmt.query_map([project_id], Self::row_to_step_status)?.collect::<Result<Vec<_>>>()?;
+        match project_id {
+            Some(_) if statuses.is_empty() => self.get_step_statuses(None),
+            _ => Ok(statuses),
+        }
+    }
+
+    fn row_to_step_status(row: &rusqlite::Row) -> Result<StepStatus> {
+        Ok(StepStatus {
+            id: row.get(0)?,
+            project_id: row.get(1)?,
+            name: row.get(2)?,
+            order_index: row.get(3)?,
+            counts_as_done: row.get::<_, i32>(4)? != 0,
+        })
+    }
+
+    // Replaces the project's statuses, or the global set for None, with
+    // `statuses` in that order. An empty list puts a project back on the
+    // global set. Returns the statuses now in effect.
+    pub fn set_step_statuses(&self, project_id: Option<&str>, statuses: &[StepStatus]) -> Result<Vec<StepStatus>, AppError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let statuses = self.write_step_statuses(project_id, statuses)?;
+        tx.commit()?;
+        Ok(statuses)
+    }
+
+    // `set_step_statuses` without the transaction. Steps left with a status
+    // that is gone move to the one with the same name, else to the first one
+    // with the same "counts as done", and `completed` follows any flag that
+    // changed.
+    pub fn write_step_statuses(&self, project_id: Option<&str>, statuses: &[StepStatus]) -> Result<Vec<StepStatus>, AppError> {
+        if let Some(project_id) = project_id {
+            self.get_project(project_id)?;
+        }
+        if !(statuses.is_empty() && project_id.is_some()) {
+            validate_step_statuses(statuses)?;
+        }
+
+        let mut stmt = self.conn.prepare(&format!("SELECT {} FROM step_statuses", STEP_STATUS_COLUMNS))?;
+        let previous: HashMap<String, StepStatus> = stmt
+            .query_map([], Self::row_to_step_status)?
+            .map(|status| status.map(|status| (status.id.clone(), status)))
+            .collect::<Result<_>>()?;
+
+        self.conn.execute("DELETE FROM step_statuses WHERE project_id IS ?1", [project_id])?;
+        for (order_index, status) in statuses.iter().enumerate() {
+            let id = if status.id.is_empty() { Uuid::new_v4().to_string() } else { status.id.clone() };
+            if previous.get(&id).is_some_and(|other| other.project_id.as_deref() != project_id) {
+                return Err(AppError::InvalidInput(format!(
+                    "The status id '{}' is already used by another status set",
+                    id
+                )));
+            }
+            self.conn.execute(
+                "INSERT INTO step_statuses (id, project_id, name, order_index, counts_as_done) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (&id, project_id, status.name.trim(), order_index as i32, status.counts_as_done as i32),
+            )?;
+        }
+
+        let effective = self.get_step_statuses(project_id)?;
+        let steps_sql = match project_id {
+            Some(_) => "SELECT id, status, completed FROM steps WHERE project_id = ?1",
+            None => "SELECT id, status, completed FROM steps WHERE project_id NOT IN (
+                         SELECT project_id FROM step_statuses WHERE project_id IS NOT NULL
+                     )",
+        };
+        let mut stmt = self.conn.prepare(steps_sql)?;
+        let steps = stmt
+            .query_map(params_from_iter(project_id), |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, bool>(2)?)))?
+            .collect::<Result<Vec<_>>>()?;
+
+        let now = Utc::now().to_rfc3339();
+        for (step_id, status, completed) in steps {
+            if effective.iter().any(|candidate| Some(&candidate.id) == status.as_ref()) {
+                continue;
+            }
+            let old = status.as_ref().and_then(|status| previous.get(status));
+            let counts_as_done = old.map_or(completed, |old| old.counts_as_done);
+            let replacement = effective
+                .iter()
+                .find(|candidate| old.is_some_and(|old| candidate.name.eq_ignore_ascii_case(&old.name)))
+                .or_else(|| effective.iter().find(|candidate| candidate.counts_as_done == counts_as_done))
+                .or(effective.first());
+            if let Some(replacement) = replacement {
+                self.change_step_status(&step_id, status.as_deref(), &replacement.id, &now)?;
+            }
+        }
+
+        self.conn.execute(
+            "UPDATE steps SET completed = (SELECT counts_as_done FROM step_statuses WHERE id = steps.status)
+             WHERE status IN (SELECT id FROM step_statuses)",
+            [],
+        )?;
+        Ok(effective)
+    }
+
+    // Moves the step and its attachments to the trash. The steps nested under
+    // it go too, sharing its `deleted_at` so they are restored with it, unless
+    // `keep_children` is set; then they take its place among its siblings.
+    pub fn delete_step(&self, step_id: &str, keep_children: bool) -> Result<()> {
+        let deleted_at = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+
+        if keep_children {
+            let step = self.get_step(step_id)?;
+            let children = self.sibling_ids(&step.project_id, Some(step_id))?;
+            if !children.is_empty() {
+                let mut siblings = self.sibling_ids(&step.project_id, step.parent_step_id.as_deref())?;
+                let position = siblings.iter().position(|id| id == step_id).unwrap_or(siblings.len());
+                siblings.splice(position..(position + 1).min(siblings.len()), children.iter().cloned());
+                for child in &children {
+                    tx.execute(
+                        "UPDATE steps SET parent_step_id = ?1 WHERE id = ?2",
+                        (&step.parent_step_id, child),
+                    )?;
+                }
+                self.renumber_steps(&siblings)?;
+            }
+        }
+
+        tx.execute(
+            &format!("UPDATE attachments SET deleted_at = ?2 WHERE deleted_at IS NULL AND ({})", STEP_ATTACHMENTS),
+            [step_id, &deleted_at],
+        )?;
+        let updated = tx.execute(
+            "UPDATE steps SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            [step_id, &deleted_at],
+        )?;
+        ensure_found(updated)?;
+        tx.execute(
+            &format!("UPDATE steps SET deleted_at = ?2 WHERE deleted_at IS NULL AND id IN ({})", STEP_SUBTREE),
+            [step_id, &deleted_at],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
     // Notes CRUD operations
     pub fn get_notes_by_project(&self, project_id: &str) -> Result<Vec<Note>> {
         let mut stmt = self.conn.prepare(
             "SELECT id, project_id, title, content, plain_text, created_at, updated_at, is_important 
-             FROM notes WHERE project_id = ?1 ORDER BY created_at DESC"
+             FROM notes WHERE project_id = ?1 AND deleted_at IS NULL ORDER BY created_at DESC"
         )?;
         
         let notes = stmt.query_map([project_id], |row| {
@@ -431,7 +1324,8 @@ impl Database {
         notes.collect()
     }
 
-    pub fn create_note(&self, note: &Note) -> Result<()> {
+    pub fn create_note(&self, note: &Note) -> Result<(), AppError> {
+        self.check_project_live(&note.project_id)?;
         self.conn.execute(
             "INSERT INTO notes (id, project_id, title, content, plain_text, created_at, updated_at, is_important) 
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
@@ -449,10 +1343,19 @@ impl Database {
         Ok(())
     }
 
-    pub fn update_note(&self, note: &Note) -> Result<()> {
-        self.conn.execute(
+    pub fn update_note(&self, note: &Note) -> Result<(), AppError> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.write_note(note)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Records a revision and saves the note. The caller provides the transaction.
+    pub fn write_note(&self, note: &Note) -> Result<(), AppError> {
+        Self::record_revision(&self.conn, "note", &note.id, &note.content, false)?;
+        let updated = self.conn.execute(
             "UPDATE notes SET title = ?1, content = ?2, plain_text = ?3, updated_at = ?4, is_important = ?5 
-             WHERE id = ?6",
+             WHERE id = ?6 AND deleted_at IS NULL",
             (
                 &note.title,
                 &note.content,
@@ -462,21 +1365,32 @@ impl Database {
                 &note.id,
             ),
         )?;
-        Ok(())
+        Ok(ensure_found(updated)?)
     }
 
+    // Moves the note and its attachments to the trash.
     pub fn delete_note(&self, note_id: &str) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM notes WHERE id = ?1",
-            [note_id],
+        let deleted_at = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute(
+            &format!("UPDATE attachments SET deleted_at = ?2 WHERE deleted_at IS NULL AND ({})", NOTE_ATTACHMENTS),
+            [note_id, &deleted_at],
+        )?;
+        let updated = tx.execute(
+            "UPDATE notes SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            [note_id, &deleted_at],
         )?;
+        ensure_found(updated)?;
+
+        tx.commit()?;
         Ok(())
     }
     
     pub fn get_important_note(&self, project_id: &str) -> Result<Option<Note>> {
         let mut stmt = self.conn.prepare(
             "SELECT id, project_id, title, content, plain_text, created_at, updated_at, is_important 
-             FROM notes WHERE project_id = ?1 AND is_important = 1 LIMIT 1"
+             FROM notes WHERE project_id = ?1 AND is_important = 1 AND deleted_at IS NULL LIMIT 1"
         )?;
         
         let mut notes = stmt.query_map([project_id], |row| {
@@ -496,73 +1410,880 @@ impl Database {
     }
     
     pub fn set_important_note(&self, project_id: &str, note_id: &str) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+
         // First, unset any existing important note for this project
-        self.conn.execute(
+        tx.execute(
             "UPDATE notes SET is_important = 0 WHERE project_id = ?1 AND is_important = 1",
             [project_id],
         )?;
         
-        // Then set the new important note
-        self.conn.execute(
-            "UPDATE notes SET is_important = 1 WHERE id = ?1 AND project_id = ?2",
+        // Then set the new important note; one in the trash can't be it
+        let updated = tx.execute(
+            "UPDATE notes SET is_important = 1 WHERE id = ?1 AND project_id = ?2 AND deleted_at IS NULL",
             [note_id, project_id],
         )?;
+        ensure_found(updated)?;
         
+        tx.commit()?;
         Ok(())
     }
 
-    // Image Attachments CRUD operations
-    pub fn get_image_attachments_by_content(&self, content_id: &str, content_type_enum: &str) -> Result<Vec<ImageAttachment>> {
+    // Attachments CRUD operations. `kind` narrows the list to images or files.
+    pub fn get_attachments_by_content(&self, content_id: &str, content_type_enum: &str, kind: Option<&str>) -> Result<Vec<Attachment>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, file_path, filename, content_type, content_id, content_type_enum, created_at 
-             FROM image_attachments WHERE content_id = ?1 AND content_type_enum = ?2 ORDER BY created_at ASC"
-        )?;
+            &format!(
+            "SELECT {} FROM attachments WHERE content_id = ?1 AND content_type_enum = ?2 AND (?3 IS NULL OR kind = ?3) AND deleted_at IS NULL ORDER BY created_at ASC",
+            ATTACHMENT_COLUMNS
+        ))?;
         
-        let attachments = stmt.query_map([content_id, content_type_enum], |row| {
-            Ok(ImageAttachment {
-                id: row.get(0)?,
-                file_path: row.get(1)?,
-                filename: row.get(2)?,
-                content_type: row.get(3)?,
-                content_id: row.get(4)?,
-                content_type_enum: row.get(5)?,
-                created_at: row.get(6)?,
-            })
+        let attachments = stmt.query_map(params![content_id, content_type_enum, kind], Self::row_to_attachment)?;
+
+        attachments.collect()
+    }
+
+    pub fn get_attachment(&self, attachment_id: &str) -> Result<Attachment> {
+        self.conn.query_row(
+            &format!(
+                "SELECT {} FROM attachments WHERE id = ?1 AND deleted_at IS NULL",
+                ATTACHMENT_COLUMNS
+            ),
+            [attachment_id],
+            Self::row_to_attachment,
+        )
+    }
+
+    fn row_to_attachment(row: &rusqlite::Row) -> Result<Attachment> {
+        Ok(Attachment {
+            id: row.get(0)?,
+            file_path: row.get(1)?,
+            filename: row.get(2)?,
+            content_type: row.get(3)?,
+            content_id: row.get(4)?,
+            content_type_enum: row.get(5)?,
+            created_at: row.get(6)?,
+            width: row.get(7)?,
+            height: row.get(8)?,
+            byte_size: row.get(9)?,
+            blob_hash: row.get(10)?,
+            transformed: row.get(11)?,
+            original_blob_hash: row.get(12)?,
+            kind: row.get(13)?,
+        })
+    }
+
+    // The owner must exist and not be in the trash. A trigger rejects missing
+    // owners too; checking here first gives a readable error.
+    pub fn create_attachment(&self, attachment: &Attachment) -> Result<(), AppError> {
+        let table = attachment_owner_table(&attachment.content_type_enum).ok_or_else(|| {
+            AppError::InvalidInput(format!("Unknown attachment owner type: {}", attachment.content_type_enum))
         })?;
+        let owner_exists: bool = self.conn.query_row(
+            &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1 AND deleted_at IS NULL)", table),
+            [&attachment.content_id],
+            |row| row.get(0),
+        )?;
+        if !owner_exists {
+            return Err(AppError::InvalidInput(format!(
+                "Cannot attach to {} {}: it does not exist",
+                attachment.content_type_enum, attachment.content_id
+            )));
+        }
+
+        self.conn.execute(
+            "INSERT INTO attachments (id, file_path, filename, content_type, content_id, content_type_enum, created_at, width, height, byte_size, blob_hash, transformed, original_blob_hash, kind) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                attachment.id,
+                attachment.file_path,
+                attachment.filename,
+                attachment.content_type,
+                attachment.content_id,
+                attachment.content_type_enum,
+                attachment.created_at,
+                attachment.width,
+                attachment.height,
+                attachment.byte_size,
+                attachment.blob_hash,
+                attachment.transformed,
+                attachment.original_blob_hash,
+                attachment.kind,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Returns the files no longer needed: the attachment's thumbnails and its
+    // blob if this was the last reference to it.
+    pub fn delete_attachment(&self, attachment_id: &str) -> Result<Vec<String>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut file_paths = self.query_attachment_paths("id = ?1", attachment_id)?;
+
+        let deleted = tx.execute(
+            "DELETE FROM attachments WHERE id = ?1",
+            [attachment_id],
+        )?;
+        ensure_found(deleted)?;
+        file_paths.extend(self.take_unreferenced_blobs()?);
+
+        tx.commit()?;
+        Ok(file_paths)
+    }
+
+    // Includes trashed attachments, for maintenance tasks that cover every file.
+    pub fn get_all_attachments(&self) -> Result<Vec<Attachment>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM attachments ORDER BY created_at ASC", ATTACHMENT_COLUMNS)
+        )?;
+        
+        let attachments = stmt.query_map([], Self::row_to_attachment)?;
 
         attachments.collect()
     }
 
-    pub fn create_image_attachment(&self, attachment: &ImageAttachment) -> Result<()> {
+    // Attachments uploaded before dimensions and sizes were recorded
+    pub fn get_attachments_without_metadata(&self) -> Result<Vec<Attachment>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM attachments WHERE byte_size IS NULL", ATTACHMENT_COLUMNS)
+        )?;
+        let attachments = stmt.query_map([], Self::row_to_attachment)?;
+        attachments.collect()
+    }
+
+    pub fn update_attachment_metadata(&self, attachment_id: &str, width: Option<u32>, height: Option<u32>, byte_size: u64) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE attachments SET width = ?1, height = ?2, byte_size = ?3 WHERE id = ?4",
+            params![width, height, byte_size, attachment_id],
+        )?;
+        ensure_found(updated)
+    }
+
+    // A blob-backed attachment shares its file with the blob and every other
+    // attachment of that blob, so they are all pointed at the new path.
+    pub fn update_attachment_path(&self, attachment_id: &str, file_path: &str) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let blob_hash: Option<String> = self.conn.query_row(
+            "SELECT blob_hash FROM attachments WHERE id = ?1",
+            [attachment_id],
+            |row| row.get(0),
+        )?;
+        match blob_hash {
+            Some(blob_hash) => {
+                self.conn.execute(
+                    "UPDATE attachment_blobs SET file_path = ?1 WHERE hash = ?2",
+                    [file_path, &blob_hash],
+                )?;
+                self.conn.execute(
+                    "UPDATE attachments SET file_path = ?1 WHERE blob_hash = ?2",
+                    [file_path, &blob_hash],
+                )?;
+            }
+            None => {
+                self.conn.execute(
+                    "UPDATE attachments SET file_path = ?1 WHERE id = ?2",
+                    [file_path, attachment_id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // File paths owned by the matching attachments alone: their thumbnails and
+    // files not yet moved into the blob store. Blobs are released separately by
+    // `take_unreferenced_blobs`, as other attachments may still share them.
+    fn query_attachment_paths(&self, condition: &str, id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT file_path FROM attachments WHERE blob_hash IS NULL AND ({0})
+             UNION ALL
+             SELECT t.file_path FROM image_thumbnails t
+                JOIN attachments ON attachments.id = t.attachment_id
+                WHERE {0}",
+            condition
+        ))?;
+        let paths = stmt.query_map([id], |row| row.get(0))?;
+        paths.collect()
+    }
+
+    // Attachment blobs
+    pub fn get_blob(&self, hash: &str) -> Result<Option<AttachmentBlob>> {
+        self.conn
+            .query_row(
+                "SELECT hash, file_path, byte_size, ref_count, created_at FROM attachment_blobs WHERE hash = ?1",
+                [hash],
+                |row| {
+                    Ok(AttachmentBlob {
+                        hash: row.get(0)?,
+                        file_path: row.get(1)?,
+                        byte_size: row.get(2)?,
+                        ref_count: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // New blobs start unreferenced; inserting an attachment row that points at
+    // one takes the first reference.
+    pub fn create_blob(&self, blob: &AttachmentBlob) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO attachment_blobs (hash, file_path, byte_size, ref_count, created_at) VALUES (?1, ?2, ?3, 0, ?4)",
+            params![blob.hash, blob.file_path, blob.byte_size, blob.created_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_attachment_blob(&self, attachment_id: &str, hash: &str, file_path: &str) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE attachments SET blob_hash = ?1, file_path = ?2 WHERE id = ?3",
+            [hash, file_path, attachment_id],
+        )?;
+        ensure_found(updated)
+    }
+
+    // Removes blobs whose last reference is gone and returns their file paths
+    // so the caller can delete them from disk.
+    pub fn take_unreferenced_blobs(&self) -> Result<Vec<String>> {
+        let file_paths: Vec<String> = {
+            let mut stmt = self.conn.prepare("SELECT file_path FROM attachment_blobs WHERE ref_count <= 0")?;
+            let paths = stmt.query_map([], |row| row.get(0))?;
+            paths.collect::<Result<_>>()?
+        };
+        self.conn.execute("DELETE FROM attachment_blobs WHERE ref_count <= 0", [])?;
+        Ok(file_paths)
+    }
+
+    // Includes trashed attachments, so their files move into the store too
+    pub fn get_attachments_without_blob(&self) -> Result<Vec<Attachment>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM attachments WHERE blob_hash IS NULL", ATTACHMENT_COLUMNS)
+        )?;
+        let attachments = stmt.query_map([], Self::row_to_attachment)?;
+        attachments.collect()
+    }
+
+    pub fn get_orphaned_attachments(&self) -> Result<Vec<Attachment>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM attachments WHERE {}",
+            ATTACHMENT_COLUMNS, ORPHANED_ATTACHMENTS
+        ))?;
+        let attachments = stmt.query_map([], Self::row_to_attachment)?;
+        attachments.collect()
+    }
+
+    // Every file path the database points at: attachments, blobs and thumbnails
+    pub fn get_referenced_file_paths(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path FROM attachments
+             UNION SELECT file_path FROM attachment_blobs
+             UNION SELECT file_path FROM image_thumbnails"
+        )?;
+        let paths = stmt.query_map([], |row| row.get(0))?;
+        paths.collect()
+    }
+
+    pub fn count_attachments_with_path(&self, file_path: &str) -> Result<i64> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM attachments WHERE file_path = ?1",
+            [file_path],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get_attachment_storage_report(&self) -> Result<AttachmentStorageReport> {
+        let (attachments, referenced_bytes): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(b.byte_size), 0)
+             FROM attachments a JOIN attachment_blobs b ON b.hash = a.blob_hash",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (files, stored_bytes): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(byte_size), 0) FROM attachment_blobs",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(AttachmentStorageReport {
+            attachments,
+            files,
+            referenced_bytes,
+            stored_bytes,
+            saved_bytes: referenced_bytes - stored_bytes,
+        })
+    }
+
+    // Thumbnails
+    pub fn save_thumbnail(&self, thumbnail: &Thumbnail) -> Result<()> {
         self.conn.execute(
-            "INSERT INTO image_attachments (id, file_path, filename, content_type, content_id, content_type_enum, created_at) 
+            "INSERT OR REPLACE INTO image_thumbnails (attachment_id, size, file_path, content_type, width, height, created_at)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            [
-                &attachment.id,
-                &attachment.file_path,
-                &attachment.filename,
-                &attachment.content_type,
-                &attachment.content_id,
-                &attachment.content_type_enum,
-                &attachment.created_at,
+            params![
+                thumbnail.attachment_id,
+                thumbnail.size,
+                thumbnail.file_path,
+                thumbnail.content_type,
+                thumbnail.width,
+                thumbnail.height,
+                thumbnail.created_at,
             ],
         )?;
         Ok(())
     }
 
-    pub fn delete_image_attachment(&self, attachment_id: &str) -> Result<()> {
+    // The smallest thumbnail at least `size` pixels across, or the largest one
+    // when none is big enough. Thumbnails of trashed attachments aren't served.
+    pub fn get_thumbnail(&self, attachment_id: &str, size: u32) -> Result<Thumbnail> {
+        self.conn.query_row(
+            "SELECT t.attachment_id, t.size, t.file_path, t.content_type, t.width, t.height, t.created_at
+             FROM image_thumbnails t
+             JOIN attachments a ON a.id = t.attachment_id
+             WHERE t.attachment_id = ?1 AND a.deleted_at IS NULL
+             ORDER BY t.size < ?2, CASE WHEN t.size >= ?2 THEN t.size ELSE -t.size END
+             LIMIT 1",
+            params![attachment_id, size],
+            Self::row_to_thumbnail,
+        )
+    }
+
+    fn row_to_thumbnail(row: &rusqlite::Row) -> Result<Thumbnail> {
+        Ok(Thumbnail {
+            attachment_id: row.get(0)?,
+            size: row.get(1)?,
+            file_path: row.get(2)?,
+            content_type: row.get(3)?,
+            width: row.get(4)?,
+            height: row.get(5)?,
+            created_at: row.get(6)?,
+        })
+    }
+
+    // Attachments that still need thumbnails generated
+    pub fn get_attachments_without_thumbnails(&self) -> Result<Vec<Attachment>> {
+        let mut stmt = self.conn.prepare(
+            &format!(
+                "SELECT {} FROM attachments a
+                 WHERE deleted_at IS NULL AND kind = 'image'
+                    AND NOT EXISTS (SELECT 1 FROM image_thumbnails t WHERE t.attachment_id = a.id)
+                 ORDER BY created_at DESC",
+                ATTACHMENT_COLUMNS
+            )
+        )?;
+
+        let attachments = stmt.query_map([], Self::row_to_attachment)?;
+
+        attachments.collect()
+    }
+
+    pub fn delete_attachments_by_content(&self, content_id: &str, content_type_enum: &str) -> Result<()> {
         self.conn.execute(
-            "DELETE FROM image_attachments WHERE id = ?1",
+            "DELETE FROM attachments WHERE content_id = ?1 AND content_type_enum = ?2",
+            [content_id, content_type_enum],
+        )?;
+        Ok(())
+    }
+
+    // When the project, step or note was last updated and whether it's in the
+    // trash. None if there is no such item.
+    pub fn get_updated_at(&self, kind: &str, id: &str) -> Result<Option<(String, bool)>, AppError> {
+        let table = match kind {
+            "project" => "projects",
+            "step" => "steps",
+            "note" => "notes",
+            _ => return Err(AppError::InvalidInput(format!("Unknown item kind: {}", kind))),
+        };
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT updated_at, deleted_at IS NOT NULL FROM {} WHERE id = ?1", table),
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    pub fn attachment_exists(&self, attachment_id: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM attachments WHERE id = ?1)",
             [attachment_id],
+            |row| row.get(0),
+        )
+    }
+
+    // Trash operations
+    pub fn list_trash(&self) -> Result<Vec<TrashItem>> {
+        // Children trashed together with their project or parent step are listed
+        // under that only
+        let mut stmt = self.conn.prepare(
+            "SELECT 'project', id, id, name, deleted_at FROM projects WHERE deleted_at IS NOT NULL
+             UNION ALL
+             SELECT 'step', s.id, s.project_id, s.title, s.deleted_at FROM steps s
+                JOIN projects p ON p.id = s.project_id
+                LEFT JOIN steps parent ON parent.id = s.parent_step_id
+                WHERE s.deleted_at IS NOT NULL AND (p.deleted_at IS NULL OR p.deleted_at != s.deleted_at)
+                    AND (parent.deleted_at IS NULL OR parent.deleted_at != s.deleted_at)
+             UNION ALL
+             SELECT 'note', n.id, n.project_id, n.title, n.deleted_at FROM notes n
+                JOIN projects p ON p.id = n.project_id
+                WHERE n.deleted_at IS NOT NULL AND (p.deleted_at IS NULL OR p.deleted_at != n.deleted_at)
+             ORDER BY 5 DESC"
+        )?;
+
+        let items = stmt.query_map([], |row| {
+            Ok(TrashItem {
+                kind: row.get(0)?,
+                id: row.get(1)?,
+                project_id: row.get(2)?,
+                title: row.get(3)?,
+                deleted_at: row.get(4)?,
+            })
+        })?;
+
+        items.collect()
+    }
+
+    // Restores a trashed item and everything that was trashed along with it.
+    // Restoring a step or note whose project is in the trash restores the
+    // project too, and likewise for a step's trashed parent steps.
+    pub fn restore_item(&self, kind: &str, id: &str) -> Result<(), AppError> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        match kind {
+            "project" => self.restore_project(id)?,
+            "step" => self.restore_step(id)?,
+            "note" => {
+                let (project_id, deleted_at): (String, Option<String>) = tx.query_row(
+                    "SELECT project_id, deleted_at FROM notes WHERE id = ?1",
+                    [id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+
+                self.restore_project(&project_id)?;
+
+                if let Some(deleted_at) = deleted_at {
+                    tx.execute(
+                        &format!("UPDATE attachments SET deleted_at = NULL WHERE deleted_at = ?2 AND ({})", NOTE_ATTACHMENTS),
+                        [id, &deleted_at],
+                    )?;
+                    tx.execute("UPDATE notes SET deleted_at = NULL WHERE id = ?1", [id])?;
+                }
+            }
+            _ => return Err(AppError::InvalidInput(format!("Unknown trash item kind: {}", kind))),
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn restore_step(&self, step_id: &str) -> Result<()> {
+        let (project_id, parent_step_id, deleted_at): (String, Option<String>, Option<String>) = self.conn.query_row(
+            "SELECT project_id, parent_step_id, deleted_at FROM steps WHERE id = ?1",
+            [step_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
         )?;
+
+        self.restore_project(&project_id)?;
+        if let Some(parent_step_id) = parent_step_id {
+            self.restore_step(&parent_step_id)?;
+        }
+
+        if let Some(deleted_at) = deleted_at {
+            self.conn.execute(
+                &format!("UPDATE attachments SET deleted_at = NULL WHERE deleted_at = ?2 AND ({})", STEP_ATTACHMENTS),
+                [step_id, &deleted_at],
+            )?;
+            self.conn.execute(
+                &format!("UPDATE steps SET deleted_at = NULL WHERE deleted_at = ?2 AND id IN ({})", STEP_SUBTREE),
+                [step_id, &deleted_at],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn restore_project(&self, project_id: &str) -> Result<()> {
+        let deleted_at: Option<String> = self.conn.query_row(
+            "SELECT deleted_at FROM projects WHERE id = ?1",
+            [project_id],
+            |row| row.get(0),
+        )?;
+
+        if let Some(deleted_at) = deleted_at {
+            self.conn.execute(
+                &format!("UPDATE attachments SET deleted_at = NULL WHERE deleted_at = ?2 AND ({})", PROJECT_ATTACHMENTS),
+                [project_id, &deleted_at],
+            )?;
+            self.conn.execute(
+                "UPDATE steps SET deleted_at = NULL WHERE project_id = ?1 AND deleted_at = ?2",
+                [project_id, &deleted_at],
+            )?;
+            self.conn.execute(
+                "UPDATE notes SET deleted_at = NULL WHERE project_id = ?1 AND deleted_at = ?2",
+                [project_id, &deleted_at],
+            )?;
+            self.conn.execute(
+                "UPDATE projects SET deleted_at = NULL WHERE id = ?1",
+                [project_id],
+            )?;
+        }
         Ok(())
     }
 
-    pub fn delete_image_attachments_by_content(&self, content_id: &str, content_type_enum: &str) -> Result<()> {
+    // Permanently deletes a trashed item. Steps and notes cascade through their
+    // foreign keys, and attachment rows through triggers. Returns the file paths
+    // no longer referenced so the caller can delete them from disk.
+    pub fn purge_item(&self, kind: &str, id: &str) -> Result<Vec<String>, AppError> {
+        let (table, attachments) = match kind {
+            "project" => ("projects", PROJECT_ATTACHMENTS),
+            "step" => ("steps", STEP_ATTACHMENTS),
+            "note" => ("notes", NOTE_ATTACHMENTS),
+            _ => return Err(AppError::InvalidInput(format!("Unknown trash item kind: {}", kind))),
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut file_paths = self.query_attachment_paths(attachments, id)?;
+
+        let deleted = tx.execute(
+            &format!("DELETE FROM {} WHERE id = ?1 AND deleted_at IS NOT NULL", table),
+            [id],
+        )?;
+        ensure_found(deleted)?;
+        file_paths.extend(self.take_unreferenced_blobs()?);
+
+        tx.commit()?;
+        Ok(file_paths)
+    }
+
+    // Purges everything that has been in the trash longer than the retention period.
+    pub fn purge_expired_trash(&self, retention_days: i64) -> Result<Vec<String>, AppError> {
+        let cutoff = (Utc::now() - Duration::days(retention_days)).to_rfc3339();
+        let mut file_paths = Vec::new();
+
+        for (kind, table) in [("project", "projects"), ("step", "steps"), ("note", "notes")] {
+            let ids: Vec<String> = {
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT id FROM {} WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
+                    table
+                ))?;
+                let ids = stmt.query_map([&cutoff], |row| row.get(0))?;
+                ids.collect::<Result<_>>()?
+            };
+
+            for id in ids {
+                // Already removed if its project was purged first
+                match self.purge_item(kind, &id) {
+                    Ok(paths) => file_paths.extend(paths),
+                    Err(AppError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(file_paths)
+    }
+
+    // Settings
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
         self.conn.execute(
-            "DELETE FROM image_attachments WHERE content_id = ?1 AND content_type_enum = ?2",
-            [content_id, content_type_enum],
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_setting(&self, key: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM settings WHERE key = ?1", [key])?;
+        Ok(())
+    }
+
+    pub fn get_trash_retention_days(&self) -> Result<i64> {
+        Ok(self
+            .get_setting(TRASH_RETENTION_DAYS_KEY)?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS))
+    }
+
+    pub fn get_time_zone(&self) -> Result<Zone> {
+        Ok(Zone::from_name(self.get_setting(TIME_ZONE_KEY)?.as_deref()))
+    }
+
+    pub fn get_max_upload_bytes(&self) -> Result<u64> {
+        Ok(self
+            .get_setting(MAX_UPLOAD_BYTES_KEY)?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES))
+    }
+
+    pub fn get_scan_attachments_on_startup(&self) -> Result<bool> {
+        Ok(self.get_setting(SCAN_ATTACHMENTS_ON_STARTUP_KEY)?.as_deref() == Some("true"))
+    }
+
+    // Metadata is stripped unless turned off; everything else is opt-in
+    pub fn get_image_processing_settings(&self) -> Result<ImageProcessingSettings> {
+        Ok(ImageProcessingSettings {
+            strip_metadata: self.get_setting(STRIP_IMAGE_METADATA_KEY)?.as_deref() != Some("false"),
+            max_dimension: self.get_setting(MAX_IMAGE_DIMENSION_KEY)?.and_then(|value| value.parse().ok()),
+            max_bytes: self.get_setting(MAX_IMAGE_BYTES_KEY)?.and_then(|value| value.parse().ok()),
+            keep_original: self.get_setting(KEEP_ORIGINAL_IMAGES_KEY)?.as_deref() == Some("true"),
+        })
+    }
+
+    pub fn set_image_processing_settings(&self, settings: &ImageProcessingSettings) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.set_setting(STRIP_IMAGE_METADATA_KEY, &settings.strip_metadata.to_string())?;
+        self.set_setting(KEEP_ORIGINAL_IMAGES_KEY, &settings.keep_original.to_string())?;
+        match settings.max_dimension {
+            Some(max) => self.set_setting(MAX_IMAGE_DIMENSION_KEY, &max.to_string())?,
+            None => self.delete_setting(MAX_IMAGE_DIMENSION_KEY)?,
+        }
+        match settings.max_bytes {
+            Some(max) => self.set_setting(MAX_IMAGE_BYTES_KEY, &max.to_string())?,
+            None => self.delete_setting(MAX_IMAGE_BYTES_KEY)?,
+        }
+        tx.commit()
+    }
+
+    // Backups are on by default; attachments are left out unless asked for
+    pub fn get_backup_settings(&self) -> Result<BackupSettings> {
+        let number = |key: &str, default: u32| -> Result<u32> {
+            Ok(self.get_setting(key)?.and_then(|value| value.parse().ok()).unwrap_or(default))
+        };
+        Ok(BackupSettings {
+            enabled: self.get_setting(BACKUPS_ENABLED_KEY)?.as_deref() != Some("false"),
+            interval_hours: number(BACKUP_INTERVAL_HOURS_KEY, DEFAULT_BACKUP_INTERVAL_HOURS)?,
+            keep_hourly: number(BACKUP_KEEP_HOURLY_KEY, DEFAULT_BACKUP_KEEP_HOURLY)?,
+            keep_daily: number(BACKUP_KEEP_DAILY_KEY, DEFAULT_BACKUP_KEEP_DAILY)?,
+            include_attachments: self.get_setting(BACKUP_INCLUDE_ATTACHMENTS_KEY)?.as_deref() == Some("true"),
+        })
+    }
+
+    pub fn set_backup_settings(&self, settings: &BackupSettings) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.set_setting(BACKUPS_ENABLED_KEY, &settings.enabled.to_string())?;
+        self.set_setting(BACKUP_INTERVAL_HOURS_KEY, &settings.interval_hours.to_string())?;
+        self.set_setting(BACKUP_KEEP_HOURLY_KEY, &settings.keep_hourly.to_string())?;
+        self.set_setting(BACKUP_KEEP_DAILY_KEY, &settings.keep_daily.to_string())?;
+        self.set_setting(BACKUP_INCLUDE_ATTACHMENTS_KEY, &settings.include_attachments.to_string())?;
+        tx.commit()
+    }
+
+    // Revision history
+    fn revision_source(item_type: &str) -> Result<(&'static str, &'static str), AppError> {
+        match item_type {
+            "step" => Ok(("steps", "description")),
+            "note" => Ok(("notes", "content")),
+            _ => Err(AppError::InvalidInput(format!("Unknown revision item type: {}", item_type))),
+        }
+    }
+
+    // Stores the item's current content as a revision before it is overwritten
+    // with `new_content`. Unchanged content is skipped, and so is a save within
+    // REVISION_COALESCE_SECONDS of the previous revision unless `force` is set.
+    fn record_revision(conn: &Connection, item_type: &str, item_id: &str, new_content: &str, force: bool) -> Result<(), AppError> {
+        let (table, column) = Self::revision_source(item_type)?;
+
+        let current: Option<(String, String)> = conn
+            .query_row(
+                &format!("SELECT COALESCE({}, ''), COALESCE(plain_text, '') FROM {} WHERE id = ?1", column, table),
+                [item_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let (content, plain_text) = match current {
+            Some(current) if current.0 != new_content => current,
+            _ => return Ok(()),
+        };
+
+        let now = Utc::now();
+        if !force {
+            let last_created_at: Option<String> = conn
+                .query_row(
+                    "SELECT created_at FROM revisions WHERE item_type = ?1 AND item_id = ?2
+                     ORDER BY created_at DESC LIMIT 1",
+                    [item_type, item_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            let within_window = last_created_at
+                .and_then(|created_at| chrono::DateTime::parse_from_rfc3339(&created_at).ok())
+                .map(|created_at| now.signed_duration_since(created_at) < Duration::seconds(REVISION_COALESCE_SECONDS))
+                .unwrap_or(false);
+            if within_window {
+                return Ok(());
+            }
+        }
+
+        conn.execute(
+            "INSERT INTO revisions (id, item_type, item_id, content, plain_text, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            [&Uuid::new_v4().to_string(), item_type, item_id, &content, &plain_text, &now.to_rfc3339()],
         )?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn list_revisions(&self, item_type: &str, item_id: &str) -> Result<Vec<Revision>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, item_type, item_id, content, plain_text, created_at
+             FROM revisions WHERE item_type = ?1 AND item_id = ?2 ORDER BY created_at DESC"
+        )?;
+
+        let revisions = stmt.query_map([item_type, item_id], Self::row_to_revision)?;
+        revisions.collect()
+    }
+
+    pub fn get_revision(&self, revision_id: &str) -> Result<Revision> {
+        self.conn.query_row(
+            "SELECT id, item_type, item_id, content, plain_text, created_at
+             FROM revisions WHERE id = ?1",
+            [revision_id],
+            Self::row_to_revision,
+        )
+    }
+
+    fn row_to_revision(row: &rusqlite::Row) -> Result<Revision> {
+        Ok(Revision {
+            id: row.get(0)?,
+            item_type: row.get(1)?,
+            item_id: row.get(2)?,
+            content: row.get(3)?,
+            plain_text: row.get(4)?,
+            created_at: row.get(5)?,
+        })
+    }
+
+    // Puts the revision's content back on its step or note. The content being
+    // replaced is saved as a revision first, so a restore can itself be undone.
+    pub fn restore_revision(&self, revision_id: &str) -> Result<(), AppError> {
+        let revision = self.get_revision(revision_id)?;
+        let (table, column) = Self::revision_source(&revision.item_type)?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        Self::record_revision(&tx, &revision.item_type, &revision.item_id, &revision.content, true)?;
+        let updated = tx.execute(
+            &format!("UPDATE {} SET {} = ?1, plain_text = ?2, updated_at = ?3 WHERE id = ?4", table, column),
+            [&revision.content, &revision.plain_text, &Utc::now().to_rfc3339(), &revision.item_id],
+        )?;
+        ensure_found(updated)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Diffs the plain text of two revisions. Without `to_revision_id` the
+    // revision is compared against the item's current content.
+    pub fn diff_revisions(&self, from_revision_id: &str, to_revision_id: Option<&str>) -> Result<Vec<DiffLine>, AppError> {
+        let from = self.get_revision(from_revision_id)?;
+        let to_plain_text = match to_revision_id {
+            Some(id) => self.get_revision(id)?.plain_text,
+            None => {
+                let (table, _) = Self::revision_source(&from.item_type)?;
+                self.conn.query_row(
+                    &format!("SELECT COALESCE(plain_text, '') FROM {} WHERE id = ?1", table),
+                    [&from.item_id],
+                    |row| row.get(0),
+                )?
+            }
+        };
+
+        Ok(diff::diff_lines(&from.plain_text, &to_plain_text))
+    }
+}
+
+// Steps by the parent they are listed under, None for the top level. Steps
+// whose parent isn't in the list count as top-level.
+fn group_by_parent(steps: Vec<Step>) -> HashMap<Option<String>, Vec<Step>> {
+    let ids: HashSet<String> = steps.iter().map(|step| step.id.clone()).collect();
+    let mut children: HashMap<Option<String>, Vec<Step>> = HashMap::new();
+    for step in steps {
+        let parent = step.parent_step_id.clone().filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(step);
+    }
+    children
+}
+
+// Orders steps depth-first, each followed by the steps nested under it, keeping
+// the given order among siblings
+fn tree_order(steps: Vec<Step>) -> Vec<Step> {
+    let count = steps.len();
+    let mut children = group_by_parent(steps);
+
+    fn visit(parent: Option<String>, children: &mut HashMap<Option<String>, Vec<Step>>, ordered: &mut Vec<Step>) {
+        for step in children.remove(&parent).unwrap_or_default() {
+            let id = step.id.clone();
+            ordered.push(step);
+            visit(Some(id), children, ordered);
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(count);
+    visit(None, &mut children, &mut ordered);
+    // Only reachable with a cycle in the data, which `move_step` prevents
+    for (_, steps) in children {
+        ordered.extend(steps);
+    }
+    ordered
+}
+
+fn build_step_tree(parent: Option<String>, children: &mut HashMap<Option<String>, Vec<Step>>) -> Vec<StepNode> {
+    children
+        .remove(&parent)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|step| {
+            let nested = build_step_tree(Some(step.id.clone()), children);
+            let done = step.completed || (!nested.is_empty() && nested.iter().all(|child| child.done));
+            StepNode { step, done, children: nested }
+        })
+        .collect()
+}
+
+fn with_state(steps: Vec<Step>, dependencies: &[StepDependency], states: &mut HashMap<String, StepState>) -> Vec<StepWithState> {
+    steps
+        .into_iter()
+        .map(|step| StepWithState {
+            state: states.remove(&step.id).unwrap_or(StepState::Ready),
+            depends_on: dependencies
+                .iter()
+                .filter(|dependency| dependency.step_id == step.id)
+                .map(|dependency| dependency.depends_on_step_id.clone())
+                .collect(),
+            step,
+        })
+        .collect()
+}
+
+// A set needs names that tell its statuses apart, and a status on either side
+// of "counts as done" so steps can be both completed and reopened
+fn validate_step_statuses(statuses: &[StepStatus]) -> Result<(), AppError> {
+    let mut names = HashSet::new();
+    let mut ids = HashSet::new();
+    for status in statuses {
+        let name = status.name.trim();
+        if name.is_empty() {
+            return Err(AppError::InvalidInput("A step status needs a name".to_string()));
+        }
+        if !names.insert(name.to_lowercase()) {
+            return Err(AppError::InvalidInput(format!("There is more than one status named '{}'", name)));
+        }
+        if !status.id.is_empty() && !ids.insert(status.id.as_str()) {
+            return Err(AppError::InvalidInput(format!("The status id '{}' is used twice", status.id)));
+        }
+    }
+    if !statuses.iter().any(|status| status.counts_as_done) || statuses.iter().all(|status| status.counts_as_done) {
+        return Err(AppError::InvalidInput(
+            "Step statuses need at least one status that counts as done and one that doesn't".to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+// Line-based diff using the longest common subsequence. The shared prefix and
+// suffix are stripped first, and the changed region in between is split with
+// Hirschberg's algorithm so memory stays linear in the number of lines.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let prefix = old_lines
+        .iter()
+        .zip(new_lines.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut result: Vec<DiffLine> = old_lines[..prefix]
+        .iter()
+        .map(|text| line(DiffOp::Equal, text))
+        .collect();
+    diff_region(
+        &old_lines[prefix..old_lines.len() - suffix],
+        &new_lines[prefix..new_lines.len() - suffix],
+        &mut result,
+    );
+    result.extend(
+        old_lines[old_lines.len() - suffix..]
+            .iter()
+            .map(|text| line(DiffOp::Equal, text)),
+    );
+
+    result
+}
+
+fn line(op: DiffOp, text: &str) -> DiffLine {
+    DiffLine { op, text: text.to_string() }
+}
+
+// Splits `old` in half and finds where `new` splits so that both halves
+// together keep a longest common subsequence, then diffs each half.
+fn diff_region(old: &[&str], new: &[&str], result: &mut Vec<DiffLine>) {
+    if old.is_empty() || new.is_empty() {
+        result.extend(old.iter().map(|text| line(DiffOp::Delete, text)));
+        result.extend(new.iter().map(|text| line(DiffOp::Insert, text)));
+        return;
+    }
+    if old.len() == 1 {
+        match new.iter().position(|text| *text == old[0]) {
+            Some(k) => {
+                result.extend(new[..k].iter().map(|text| line(DiffOp::Insert, text)));
+                result.push(line(DiffOp::Equal, old[0]));
+                result.extend(new[k + 1..].iter().map(|text| line(DiffOp::Insert, text)));
+            }
+            None => {
+                result.push(line(DiffOp::Delete, old[0]));
+                result.extend(new.iter().map(|text| line(DiffOp::Insert, text)));
+            }
+        }
+        return;
+    }
+
+    let mid = old.len() / 2;
+    let forward = lcs_row(old[..mid].iter(), new);
+    let new_reversed: Vec<&str> = new.iter().rev().copied().collect();
+    let backward = lcs_row(old[mid..].iter().rev(), &new_reversed);
+
+    // The earliest best split keeps deletions ahead of insertions
+    let mut split = 0;
+    for j in 1..=new.len() {
+        if forward[j] + backward[new.len() - j] > forward[split] + backward[new.len() - split] {
+            split = j;
+        }
+    }
+
+    diff_region(&old[..mid], &new[..split], result);
+    diff_region(&old[mid..], &new[split..], result);
+}
+
+// The last row of the LCS table: row[j] is the LCS length of `old` and new[..j]
+fn lcs_row<'a>(old: impl Iterator<Item = &'a &'a str>, new: &[&str]) -> Vec<usize> {
+    let mut row = vec![0; new.len() + 1];
+    for old_line in old {
+        let mut diagonal = 0;
+        for (j, new_line) in new.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if old_line == new_line { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+    row
+}
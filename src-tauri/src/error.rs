@@ -0,0 +1,134 @@
+use rusqlite::ErrorCode;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io;
+
+// Error returned by every command. It reaches the frontend as
+// `{ kind, message, details, transient }`, where `kind` is one of the stable codes from
+// `AppError::kind` so the UI can react without parsing messages.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    ConstraintViolation(String),
+    InvalidInput(String),
+    // The database is busy or locked; retrying later may succeed
+    Busy(String),
+    DiskFull(String),
+    PermissionDenied(String),
+    // A path resolved outside the directory it is confined to
+    AccessDenied(String),
+    // The database was written by a newer build, whose schema this one can't know
+    SchemaTooNew { version: i32, supported: i32 },
+    Io(io::Error),
+    Database(rusqlite::Error),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::ConstraintViolation(_) => "constraint_violation",
+            AppError::InvalidInput(_) => "invalid_input",
+            AppError::Busy(_) => "busy",
+            AppError::DiskFull(_) => "disk_full",
+            AppError::PermissionDenied(_) => "permission_denied",
+            AppError::AccessDenied(_) => "access_denied",
+            AppError::SchemaTooNew { .. } => "schema_too_new",
+            AppError::Io(_) => "io",
+            AppError::Database(_) => "database",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, AppError::Busy(_))
+    }
+
+    fn details(&self) -> Option<String> {
+        match self {
+            AppError::Io(e) => Some(format!("{:?}", e.kind())),
+            AppError::Database(e) => e.sqlite_error_code().map(|code| format!("{:?}", code)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::ConstraintViolation(message)
+            | AppError::InvalidInput(message)
+            | AppError::Busy(message)
+            | AppError::DiskFull(message)
+            | AppError::PermissionDenied(message)
+            | AppError::AccessDenied(message)
+            | AppError::Internal(message) => write!(f, "{}", message),
+            AppError::SchemaTooNew { version, supported } => write!(
+                f,
+                "Database schema version {} is newer than this build supports (version {})",
+                version, supported
+            ),
+            AppError::Io(e) => write!(f, "{}", e),
+            AppError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AppError", 4)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("details", &self.details())?;
+        state.serialize_field("transient", &self.is_transient())?;
+        state.end()
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        match &e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Item not found".to_string()),
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                ErrorCode::ConstraintViolation => AppError::ConstraintViolation(e.to_string()),
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => AppError::Busy(e.to_string()),
+                ErrorCode::DiskFull => AppError::DiskFull(e.to_string()),
+                ErrorCode::PermissionDenied | ErrorCode::ReadOnly => AppError::PermissionDenied(e.to_string()),
+                _ => AppError::Database(e),
+            },
+            _ => AppError::Database(e),
+        }
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => AppError::NotFound(e.to_string()),
+            io::ErrorKind::PermissionDenied => AppError::PermissionDenied(e.to_string()),
+            io::ErrorKind::StorageFull => AppError::DiskFull(e.to_string()),
+            _ => AppError::Io(e),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => e.into(),
+            zip::result::ZipError::FileNotFound => AppError::NotFound("File not found in archive".to_string()),
+            e => AppError::InvalidInput(format!("Invalid archive: {}", e)),
+        }
+    }
+}
+
+impl From<tauri::Error> for AppError {
+    fn from(e: tauri::Error) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
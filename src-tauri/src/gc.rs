@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::database::{Database, Attachment};
+use crate::error::AppError;
+use crate::storage::StoragePaths;
+use crate::AppState;
+
+// Files this recent may belong to an upload or thumbnail whose row isn't
+// written yet, so they are never reported as orphaned.
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrphanFile {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentIssue {
+    pub id: String,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "contentId")]
+    pub content_id: String,
+    #[serde(rename = "contentTypeEnum")]
+    pub content_type_enum: String,
+    // As recorded at upload; unknown for older attachments
+    #[serde(rename = "byteSize")]
+    pub byte_size: Option<u64>,
+}
+
+impl From<Attachment> for AttachmentIssue {
+    fn from(attachment: Attachment) -> Self {
+        AttachmentIssue {
+            id: attachment.id,
+            file_path: attachment.file_path,
+            content_id: attachment.content_id,
+            content_type_enum: attachment.content_type_enum,
+            byte_size: attachment.byte_size,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AttachmentScanReport {
+    // Files under the attachments root that no row points at
+    #[serde(rename = "orphanFiles")]
+    pub orphan_files: Vec<OrphanFile>,
+    #[serde(rename = "orphanFileBytes")]
+    pub orphan_file_bytes: u64,
+    // Rows whose file is gone
+    #[serde(rename = "missingFiles")]
+    pub missing_files: Vec<AttachmentIssue>,
+    #[serde(rename = "missingFileBytes")]
+    pub missing_file_bytes: u64,
+    // Rows whose step, note or project no longer exists
+    #[serde(rename = "orphanRows")]
+    pub orphan_rows: Vec<AttachmentIssue>,
+    #[serde(rename = "orphanRowBytes")]
+    pub orphan_row_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentCleanReport {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    // What was found, and removed unless this is a dry run
+    pub scan: AttachmentScanReport,
+    #[serde(rename = "removedFiles")]
+    pub removed_files: usize,
+    #[serde(rename = "removedRows")]
+    pub removed_rows: usize,
+    #[serde(rename = "freedBytes")]
+    pub freed_bytes: u64,
+}
+
+impl AttachmentScanReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} orphaned files ({} bytes), {} rows with missing files, {} rows without an owner ({} bytes)",
+            self.orphan_files.len(),
+            self.orphan_file_bytes,
+            self.missing_files.len(),
+            self.orphan_rows.len(),
+            self.orphan_row_bytes
+        )
+    }
+}
+
+fn list_files(dir: &Path, files: &mut Vec<(PathBuf, fs::Metadata)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            list_files(&entry.path(), files)?;
+        } else {
+            files.push((entry.path(), metadata));
+        }
+    }
+    Ok(())
+}
+
+// The rows a scan compares the attachments root against
+struct ScanRows {
+    referenced: HashSet<PathBuf>,
+    attachments: Vec<Attachment>,
+    orphan_rows: Vec<Attachment>,
+}
+
+fn read_scan_rows(db: &Database, storage: &StoragePaths) -> Result<ScanRows, AppError> {
+    Ok(ScanRows {
+        referenced: db
+            .get_referenced_file_paths()?
+            .iter()
+            .map(|file_path| storage.resolve_attachment_path(file_path))
+            .collect(),
+        attachments: db.get_all_attachments()?,
+        orphan_rows: db.get_orphaned_attachments()?,
+    })
+}
+
+fn scan_files(rows: ScanRows, storage: &StoragePaths) -> Result<AttachmentScanReport, AppError> {
+    let mut report = AttachmentScanReport::default();
+    let attachments_dir = storage.attachments_dir();
+
+    let mut files = Vec::new();
+    if attachments_dir.exists() {
+        list_files(&attachments_dir, &mut files)?;
+    }
+    let cutoff = SystemTime::now() - ORPHAN_GRACE_PERIOD;
+    for (path, metadata) in files {
+        let recent = metadata.modified().map_or(true, |modified| modified > cutoff);
+        if rows.referenced.contains(&path) || recent {
+            continue;
+        }
+        report.orphan_file_bytes += metadata.len();
+        report.orphan_files.push(OrphanFile {
+            path: path.strip_prefix(&attachments_dir).unwrap_or(&path).to_string_lossy().to_string(),
+            size: metadata.len(),
+        });
+    }
+
+    for attachment in rows.attachments {
+        if !storage.resolve_attachment_path(&attachment.file_path).exists() {
+            report.missing_file_bytes += attachment.byte_size.unwrap_or(0);
+            report.missing_files.push(attachment.into());
+        }
+    }
+
+    for attachment in rows.orphan_rows {
+        report.orphan_row_bytes += attachment.byte_size.unwrap_or(0);
+        report.orphan_rows.push(attachment.into());
+    }
+
+    Ok(report)
+}
+
+// Compares the attachments root against the database. Must be called with the
+// database locked so uploads and deletes can't interleave with the scan.
+pub fn scan_attachments(db: &Database, storage: &StoragePaths) -> Result<AttachmentScanReport, AppError> {
+    scan_files(read_scan_rows(db, storage)?, storage)
+}
+
+// Removes orphaned files and deletes rows that have no file or no owner.
+// Rows with a missing file are dropped rather than kept, so run
+// `repair_attachment_paths` first if the files may have been moved.
+pub fn clean_attachments(db: &Database, storage: &StoragePaths, dry_run: bool) -> Result<AttachmentCleanReport, AppError> {
+    let scan = scan_attachments(db, storage)?;
+    let mut report = AttachmentCleanReport {
+        dry_run,
+        scan,
+        removed_files: 0,
+        removed_rows: 0,
+        freed_bytes: 0,
+    };
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut file_paths: Vec<String> = report.scan.orphan_files.iter().map(|file| file.path.clone()).collect();
+
+    let mut row_ids: Vec<&str> = report.scan.missing_files.iter().map(|issue| issue.id.as_str()).collect();
+    for issue in &report.scan.orphan_rows {
+        if !row_ids.contains(&issue.id.as_str()) {
+            row_ids.push(&issue.id);
+        }
+    }
+    for id in row_ids {
+        file_paths.extend(db.delete_attachment(id)?);
+        report.removed_rows += 1;
+    }
+    file_paths.extend(db.take_unreferenced_blobs()?);
+
+    for file_path in &file_paths {
+        let result = storage.checked_attachment_path(file_path).and_then(|path| {
+            let size = fs::metadata(&path)?.len();
+            fs::remove_file(&path)?;
+            Ok(size)
+        });
+        match result {
+            Ok(size) => {
+                report.removed_files += 1;
+                report.freed_bytes += size;
+            }
+            Err(AppError::NotFound(_)) => {}
+            Err(e) => println!("Failed to remove attachment file {}: {}", file_path, e),
+        }
+    }
+
+    println!(
+        "Attachment cleanup: removed {} files and {} rows, freed {} bytes",
+        report.removed_files, report.removed_rows, report.freed_bytes
+    );
+    Ok(report)
+}
+
+// Logs a scan summary at startup when enabled in settings. Nothing is removed,
+// so the database is only locked while the rows are read and not for the walk
+// over the files; an attachment deleted in between may show up as missing.
+pub fn spawn_startup_scan<R: Runtime>(app: AppHandle<R>) {
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let (rows, storage) = {
+            let db = state.db.lock().unwrap();
+            match db.get_scan_attachments_on_startup() {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => {
+                    println!("Failed to read attachment scan setting: {}", e);
+                    return;
+                }
+            }
+            let storage = state.storage.lock().unwrap().clone();
+            match read_scan_rows(&db, &storage) {
+                Ok(rows) => (rows, storage),
+                Err(e) => {
+                    println!("Attachment scan failed: {}", e);
+                    return;
+                }
+            }
+        };
+
+        match scan_files(rows, &storage) {
+            Ok(report) => println!("Attachment scan: {}", report.summary()),
+            Err(e) => println!("Attachment scan failed: {}", e),
+        }
+    });
+}
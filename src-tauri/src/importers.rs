@@ -0,0 +1,531 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::database::{Database, Note, Project, Step};
+use crate::error::AppError;
+use crate::AppState;
+
+// The same palette the project list picks from when a project is added
+const PROJECT_GRADIENTS: [&str; 5] = [
+    "from-cyan-400 to-yellow-400",
+    "from-purple-400 to-pink-400",
+    "from-green-400 to-blue-400",
+    "from-red-400 to-yellow-400",
+    "from-indigo-400 to-purple-400",
+];
+
+// Header names tried, in order, when the mapping doesn't name a column
+const TITLE_COLUMNS: [&str; 6] = ["title", "name", "task", "content", "summary", "card name"];
+const DESCRIPTION_COLUMNS: [&str; 5] = ["description", "desc", "details", "body", "card description"];
+const COMPLETED_COLUMNS: [&str; 6] = ["completed", "done", "status", "state", "checked", "complete"];
+const PROJECT_COLUMNS: [&str; 4] = ["project", "list", "board", "list name"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportFormat {
+    Csv,
+    // Projects with steps and notes, as a list, a `projects` field or one project
+    Json,
+    // A board exported with Trello's "Export as JSON"
+    Trello,
+}
+
+// Which CSV columns hold what, by header name. Columns left out are guessed
+// from common header names. `projectName` names the project for rows without
+// a project column and defaults to the file's name.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImportMapping {
+    pub project: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<String>,
+    // Rows whose value here is "note" become notes instead of steps
+    #[serde(rename = "rowType")]
+    pub row_type: Option<String>,
+    #[serde(rename = "projectName")]
+    pub project_name: Option<String>,
+    pub delimiter: Option<char>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ParsedStep {
+    pub title: String,
+    pub description: String,
+    #[serde(rename = "plainText")]
+    pub plain_text: String,
+    pub completed: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ParsedNote {
+    pub title: String,
+    pub content: String,
+    #[serde(rename = "plainText")]
+    pub plain_text: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ParsedProject {
+    pub name: String,
+    pub description: String,
+    pub steps: Vec<ParsedStep>,
+    pub notes: Vec<ParsedNote>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub projects: Vec<ParsedProject>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportFileReport {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    pub projects: Vec<ParsedProject>,
+    pub warnings: Vec<String>,
+    // Ids of the created projects; empty for a dry run
+    #[serde(rename = "projectIds")]
+    pub project_ids: Vec<String>,
+}
+
+// Turns a file from another tool into the projects to create. `default_name`
+// is used for a project the file doesn't name.
+pub trait Parser {
+    fn parse(&self, bytes: &[u8], mapping: &ImportMapping, default_name: &str) -> Result<ParsedImport, AppError>;
+}
+
+struct CsvParser;
+struct JsonParser;
+struct TrelloParser;
+
+fn parser_for(format: ImportFormat) -> &'static dyn Parser {
+    match format {
+        ImportFormat::Csv => &CsvParser,
+        ImportFormat::Json => &JsonParser,
+        ImportFormat::Trello => &TrelloParser,
+    }
+}
+
+// Parses the file and, unless it's a dry run, creates everything it describes
+// in one transaction. The parsed projects are returned either way so a dry
+// run can be shown as a preview.
+pub fn import_file(
+    state: &AppState,
+    path: &Path,
+    format: ImportFormat,
+    mapping: &ImportMapping,
+    dry_run: bool,
+) -> Result<ImportFileReport, AppError> {
+    let bytes = fs::read(path)?;
+    let default_name = mapping
+        .project_name
+        .clone()
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "Imported project".to_string());
+
+    let parsed = parser_for(format).parse(&bytes, mapping, &default_name)?;
+    if parsed.projects.is_empty() {
+        return Err(AppError::InvalidInput("Nothing to import was found in the file".to_string()));
+    }
+
+    let project_ids = if dry_run {
+        Vec::new()
+    } else {
+        let db = state.db.lock().unwrap();
+        let project_ids = db.with_transaction(|| create_projects(&db, &parsed.projects))?;
+        println!("Imported {} projects from {}", project_ids.len(), path.display());
+        project_ids
+    };
+
+    Ok(ImportFileReport {
+        dry_run,
+        projects: parsed.projects,
+        warnings: parsed.warnings,
+        project_ids,
+    })
+}
+
+fn create_projects(db: &Database, projects: &[ParsedProject]) -> Result<Vec<String>, AppError> {
+    let existing = db.get_all_projects()?.len();
+    let now = Utc::now().to_rfc3339();
+    let mut project_ids = Vec::new();
+
+    for (index, parsed) in projects.iter().enumerate() {
+        let project_id = Uuid::new_v4().to_string();
+        let step_ids: Vec<String> = parsed.steps.iter().map(|_| Uuid::new_v4().to_string()).collect();
+        let current_step_id = parsed
+            .steps
+            .iter()
+            .position(|step| !step.completed)
+            .map(|position| step_ids[position].clone());
+
+        db.create_project(&Project {
+            id: project_id.clone(),
+            name: parsed.name.clone(),
+            description: parsed.description.clone(),
+            created_at: now.clone(),
+            updated_at: now.clone(),
+            gradient: PROJECT_GRADIENTS[(existing + index) % PROJECT_GRADIENTS.len()].to_string(),
+            current_step_id,
+            start_date: None,
+            due_date: None,
+        })?;
+
+        for (order_index, (step, id)) in parsed.steps.iter().zip(step_ids).enumerate() {
+            db.create_step(&Step {
+                id,
+                project_id: project_id.clone(),
+                title: step.title.clone(),
+                description: step.description.clone(),
+                plain_text: Some(step.plain_text.clone()),
+                order_index: order_index as i32,
+                completed: step.completed,
+                created_at: now.clone(),
+                updated_at: now.clone(),
+                parent_step_id: None,
+                status: None,
+                status_changed_at: None,
+                start_date: None,
+                due_date: None,
+            })?;
+        }
+
+        for note in &parsed.notes {
+            db.create_note(&Note {
+                id: Uuid::new_v4().to_string(),
+                project_id: project_id.clone(),
+                title: note.title.clone(),
+                content: note.content.clone(),
+                plain_text: note.plain_text.clone(),
+                created_at: now.clone(),
+                updated_at: now.clone(),
+                is_important: false,
+            })?;
+        }
+
+        project_ids.push(project_id);
+    }
+    Ok(project_ids)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Plain text as the editor stores it: a paragraph per line
+fn text_to_html(text: &str) -> String {
+    let text = text.trim();
+    if text.is_empty() {
+        return String::new();
+    }
+    text.lines()
+        .map(|line| match line.trim_end() {
+            "" => "<p><br></p>".to_string(),
+            line => format!("<p>{}</p>", escape_html(line)),
+        })
+        .collect()
+}
+
+fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "true" | "yes" | "y" | "1" | "x" | "done" | "complete" | "completed" | "closed" | "checked" | "resolved"
+    )
+}
+
+fn step_from_text(title: &str, description: &str, completed: bool) -> ParsedStep {
+    ParsedStep {
+        title: title.trim().to_string(),
+        description: text_to_html(description),
+        plain_text: description.trim().to_string(),
+        completed,
+    }
+}
+
+fn note_from_text(title: &str, content: &str) -> ParsedNote {
+    ParsedNote {
+        title: title.trim().to_string(),
+        content: text_to_html(content),
+        plain_text: content.trim().to_string(),
+    }
+}
+
+// Groups items into projects by name, keeping the order names first appear in
+#[derive(Default)]
+struct ProjectGroups {
+    projects: Vec<ParsedProject>,
+    by_name: HashMap<String, usize>,
+}
+
+impl ProjectGroups {
+    fn get(&mut self, name: &str) -> &mut ParsedProject {
+        let index = *self.by_name.entry(name.to_string()).or_insert_with(|| {
+            self.projects.push(ParsedProject { name: name.to_string(), ..Default::default() });
+            self.projects.len() - 1
+        });
+        &mut self.projects[index]
+    }
+}
+
+impl Parser for CsvParser {
+    fn parse(&self, bytes: &[u8], mapping: &ImportMapping, default_name: &str) -> Result<ParsedImport, AppError> {
+        let delimiter = match mapping.delimiter {
+            None => b',',
+            Some(delimiter) if delimiter.is_ascii() => delimiter as u8,
+            Some(delimiter) => {
+                return Err(AppError::InvalidInput(format!("{:?} can't be used as the delimiter", delimiter)));
+            }
+        };
+        // Spreadsheet apps often start UTF-8 files with a byte order mark
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(bytes);
+
+        let headers: Vec<String> = reader.headers().map_err(csv_error)?.iter().map(str::to_string).collect();
+        let find = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name.trim()));
+        let column = |mapped: &Option<String>, guesses: &[&str]| -> Result<Option<usize>, AppError> {
+            match mapped {
+                Some(name) => find(name)
+                    .map(Some)
+                    .ok_or_else(|| AppError::InvalidInput(format!("The file has no \"{}\" column", name))),
+                None => Ok(guesses.iter().find_map(|guess| find(guess))),
+            }
+        };
+
+        let title = column(&mapping.title, &TITLE_COLUMNS)?.ok_or_else(|| {
+            AppError::InvalidInput(format!(
+                "Choose the column holding step titles; the file has {}",
+                headers.join(", ")
+            ))
+        })?;
+        let description = column(&mapping.description, &DESCRIPTION_COLUMNS)?;
+        let completed = column(&mapping.completed, &COMPLETED_COLUMNS)?;
+        let project = column(&mapping.project, &PROJECT_COLUMNS)?;
+        let row_type = column(&mapping.row_type, &[])?;
+
+        let mut groups = ProjectGroups::default();
+        let mut warnings = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.map_err(csv_error)?;
+            let field = |column: Option<usize>| column.and_then(|column| record.get(column)).unwrap_or_default();
+            // Row 1 is the header
+            let row = index + 2;
+
+            let title = field(Some(title));
+            if title.is_empty() {
+                warnings.push(format!("Row {} has no title and was skipped", row));
+                continue;
+            }
+            let project_name = match field(project) {
+                "" => default_name,
+                name => name,
+            };
+            let project = groups.get(project_name);
+            if field(row_type).eq_ignore_ascii_case("note") {
+                project.notes.push(note_from_text(title, field(description)));
+            } else {
+                project.steps.push(step_from_text(title, field(description), is_truthy(field(completed))));
+            }
+        }
+
+        Ok(ParsedImport { projects: groups.projects, warnings })
+    }
+}
+
+fn csv_error(e: csv::Error) -> AppError {
+    match e.position() {
+        Some(position) => AppError::InvalidInput(format!("Couldn't read line {} of the CSV file: {}", position.line(), e)),
+        None => AppError::InvalidInput(format!("Couldn't read the CSV file: {}", e)),
+    }
+}
+
+fn parse_json(bytes: &[u8]) -> Result<Value, AppError> {
+    serde_json::from_slice(bytes).map_err(|e| AppError::InvalidInput(format!("The file isn't valid JSON: {}", e)))
+}
+
+// The first of `keys` holding a string
+fn json_str<'a>(value: &'a Value, keys: &[&str]) -> &'a str {
+    keys.iter().find_map(|key| value.get(key)?.as_str()).unwrap_or_default()
+}
+
+fn json_array<'a>(value: &'a Value, keys: &[&str]) -> &'a [Value] {
+    keys.iter()
+        .find_map(|key| value.get(key)?.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn json_completed(value: &Value) -> bool {
+    ["completed", "done", "checked", "status", "state"].iter().any(|key| match value.get(key) {
+        Some(Value::Bool(completed)) => *completed,
+        Some(Value::String(status)) => is_truthy(status),
+        Some(Value::Number(number)) => number.as_i64() == Some(1),
+        _ => false,
+    })
+}
+
+impl Parser for JsonParser {
+    fn parse(&self, bytes: &[u8], _mapping: &ImportMapping, default_name: &str) -> Result<ParsedImport, AppError> {
+        let root = parse_json(bytes)?;
+        let projects: Vec<&Value> = match &root {
+            Value::Array(projects) => projects.iter().collect(),
+            Value::Object(object) if object.contains_key("projects") => json_array(&root, &["projects"]).iter().collect(),
+            Value::Object(_) => vec![&root],
+            _ => return Err(AppError::InvalidInput("Expected a project or a list of projects".to_string())),
+        };
+
+        let mut warnings = Vec::new();
+        let mut parsed = Vec::new();
+        for (index, project) in projects.into_iter().enumerate() {
+            if !project.is_object() {
+                warnings.push(format!("Project {} isn't an object and was skipped", index + 1));
+                continue;
+            }
+            let name = match json_str(project, &["name", "title"]).trim() {
+                "" => default_name,
+                name => name,
+            };
+            let mut parsed_project = ParsedProject {
+                name: name.to_string(),
+                description: text_to_html(json_str(project, &["description", "desc"])),
+                ..Default::default()
+            };
+
+            for step in json_array(project, &["steps", "tasks", "items"]) {
+                let parsed_step = match step {
+                    Value::String(title) => step_from_text(title, "", false),
+                    _ => step_from_text(
+                        json_str(step, &["title", "name", "content"]),
+                        json_str(step, &["description", "desc", "notes"]),
+                        json_completed(step),
+                    ),
+                };
+                if parsed_step.title.is_empty() {
+                    warnings.push(format!("A step in \"{}\" has no title and was skipped", name));
+                } else {
+                    parsed_project.steps.push(parsed_step);
+                }
+            }
+
+            for (note_index, note) in json_array(project, &["notes"]).iter().enumerate() {
+                let fallback_title = format!("Note {}", note_index + 1);
+                let parsed_note = match note {
+                    Value::String(content) => note_from_text(&fallback_title, content),
+                    _ => {
+                        let title = match json_str(note, &["title", "name"]).trim() {
+                            "" => fallback_title.as_str(),
+                            title => title,
+                        };
+                        note_from_text(title, json_str(note, &["content", "body", "text"]))
+                    }
+                };
+                parsed_project.notes.push(parsed_note);
+            }
+
+            parsed.push(parsed_project);
+        }
+
+        Ok(ParsedImport { projects: parsed, warnings })
+    }
+}
+
+// A board becomes one project and its open cards its steps, ordered by list
+// and then by position. Cards are done if marked complete or kept in a list
+// named like "Done", and their checklists are appended as checklists.
+impl Parser for TrelloParser {
+    fn parse(&self, bytes: &[u8], _mapping: &ImportMapping, default_name: &str) -> Result<ParsedImport, AppError> {
+        let board = parse_json(bytes)?;
+        let Some(cards) = board.get("cards").and_then(Value::as_array) else {
+            return Err(AppError::InvalidInput("This isn't a Trello board export".to_string()));
+        };
+        let position = |value: &Value| value.get("pos").and_then(Value::as_f64).unwrap_or(0.0);
+        let closed = |value: &Value| value.get("closed").and_then(Value::as_bool).unwrap_or(false);
+
+        // List id to (position, done, closed)
+        let lists: HashMap<&str, (f64, bool, bool)> = json_array(&board, &["lists"])
+            .iter()
+            .map(|list| {
+                let name = json_str(list, &["name"]).to_lowercase();
+                let done = name.contains("done") || name.contains("complete");
+                (json_str(list, &["id"]), (position(list), done, closed(list)))
+            })
+            .collect();
+
+        let mut checklists: Vec<&Value> = json_array(&board, &["checklists"]).iter().collect();
+        checklists.sort_by(|a, b| position(a).total_cmp(&position(b)));
+
+        let mut archived = 0;
+        let mut open_cards = Vec::new();
+        for card in cards {
+            let list = lists.get(json_str(card, &["idList"])).copied().unwrap_or((f64::MAX, false, false));
+            if closed(card) || list.2 {
+                archived += 1;
+                continue;
+            }
+            open_cards.push((list, card));
+        }
+        open_cards.sort_by(|(a_list, a), (b_list, b)| {
+            a_list.0.total_cmp(&b_list.0).then(position(a).total_cmp(&position(b)))
+        });
+
+        let name = match json_str(&board, &["name"]).trim() {
+            "" => default_name,
+            name => name,
+        };
+        let mut project = ParsedProject {
+            name: name.to_string(),
+            description: text_to_html(json_str(&board, &["desc"])),
+            ..Default::default()
+        };
+
+        for ((_, list_done, _), card) in open_cards {
+            let completed = list_done || card.get("dueComplete").and_then(Value::as_bool).unwrap_or(false);
+            let mut step = step_from_text(json_str(card, &["name"]), json_str(card, &["desc"]), completed);
+            if step.title.is_empty() {
+                continue;
+            }
+
+            let card_id = json_str(card, &["id"]);
+            for checklist in checklists.iter().filter(|checklist| json_str(checklist, &["idCard"]) == card_id) {
+                let mut items: Vec<&Value> = json_array(checklist, &["checkItems"]).iter().collect();
+                items.sort_by(|a, b| position(a).total_cmp(&position(b)));
+                let checklist_name = json_str(checklist, &["name"]);
+
+                step.description.push_str(&format!("<p><strong>{}</strong></p><ol>", escape_html(checklist_name)));
+                step.plain_text.push_str(&format!("\n\n{}", checklist_name));
+                for item in items {
+                    let checked = json_str(item, &["state"]) == "complete";
+                    let item_name = json_str(item, &["name"]);
+                    step.description.push_str(&format!(
+                        "<li data-list=\"{}\">{}</li>",
+                        if checked { "checked" } else { "unchecked" },
+                        escape_html(item_name)
+                    ));
+                    step.plain_text.push_str(&format!("\n{}", item_name));
+                }
+                step.description.push_str("</ol>");
+            }
+            step.plain_text = step.plain_text.trim().to_string();
+            project.steps.push(step);
+        }
+
+        let mut warnings = Vec::new();
+        if archived > 0 {
+            warnings.push(format!("{} archived cards were left out", archived));
+        }
+        Ok(ParsedImport { projects: vec![project], warnings })
+    }
+}
@@ -1,234 +1,775 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod archive;
+mod backup;
+mod blobs;
 mod database;
+mod diff;
+mod error;
+mod gc;
+mod importers;
+mod markdown;
+mod media;
+mod migrations;
+mod planning;
+mod protocol;
+mod schedule;
+mod storage;
+mod thumbnails;
 
-use database::{Database, Project, Step, Note, ImageAttachment};
+use archive::{ExportReport, ImportMode, ImportReport};
+use backup::{BackupInfo, BackupKind};
+use database::{Attachment, AttachmentStorageReport, BackupSettings, Database, Project, Step, StepDependency, StepNode, StepStatus, StepSort, StepStatusChange, StepWithState, Note, ImageProcessingSettings, Thumbnail, SearchFilters, SearchHit, TrashItem, Revision, MAX_UPLOAD_BYTES_KEY, SCAN_ATTACHMENTS_ON_STARTUP_KEY, TIME_ZONE_KEY, TRASH_RETENTION_DAYS_KEY, ATTACHMENT_KIND_FILE, ATTACHMENT_KIND_IMAGE};
+use diff::DiffLine;
+use error::AppError;
+use gc::{AttachmentCleanReport, AttachmentScanReport};
+use importers::{ImportFileReport, ImportFormat, ImportMapping};
+use markdown::{MarkdownExport, MarkdownLayout};
+use schedule::{AgendaItem, AgendaRange, DueWindow};
+use storage::{RepairReport, StorageInfo, StoragePaths};
 use std::sync::Mutex;
 use std::fs;
 use std::path::Path;
 use tauri::{Manager, State};
+use tauri_plugin_shell::ShellExt;
 use uuid::Uuid;
 use chrono::Utc;
 
 struct AppState {
     db: Mutex<Database>,
+    storage: Mutex<StoragePaths>,
+}
+
+// Best effort: the rows are already gone, so a file we fail to remove is only
+// logged rather than failing the delete.
+fn remove_attachment_files(storage: &StoragePaths, file_paths: &[String]) {
+    for file_path in file_paths {
+        let result = storage
+            .checked_attachment_path(file_path)
+            .and_then(|path| fs::remove_file(path).map_err(AppError::from));
+        match result {
+            Ok(()) | Err(AppError::NotFound(_)) => {}
+            Err(e) => println!("Failed to remove attachment file {}: {}", file_path, e),
+        }
+    }
+}
+
+// Items stay in the trash for the retention period, which can run out while
+// the app is open, so expired ones are purged at startup and then every hour.
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+fn purge_expired_trash(state: &AppState) -> Result<(), AppError> {
+    let file_paths = {
+        let db = state.db.lock().unwrap();
+        db.purge_expired_trash(db.get_trash_retention_days()?)?
+    };
+    remove_attachment_files(&state.storage.lock().unwrap(), &file_paths);
+    Ok(())
+}
+
+fn spawn_trash_purger<R: tauri::Runtime>(app: tauri::AppHandle<R>) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = purge_expired_trash(&app.state::<AppState>()) {
+            println!("Failed to purge expired trash: {}", e);
+        }
+        std::thread::sleep(TRASH_PURGE_INTERVAL);
+    });
 }
 
 #[tauri::command]
-fn get_all_projects(state: State<AppState>) -> Result<Vec<Project>, String> {
+fn get_all_projects(state: State<AppState>) -> Result<Vec<Project>, AppError> {
     let db = state.db.lock().unwrap();
-    db.get_all_projects().map_err(|e| e.to_string())
+    db.get_all_projects().map_err(AppError::from)
 }
 
 #[tauri::command]
-fn create_project(project: Project, state: State<AppState>) -> Result<(), String> {
+fn create_project(project: Project, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.create_project(&project).map_err(|e| e.to_string())
+    db.create_project(&project)
 }
 
 #[tauri::command]
-fn update_project(project: Project, state: State<AppState>) -> Result<(), String> {
+fn update_project(project: Project, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.update_project(&project).map_err(|e| e.to_string())
+    db.update_project(&project)
 }
 
 #[tauri::command]
-fn delete_project(project_id: String, state: State<AppState>) -> Result<(), String> {
+fn delete_project(project_id: String, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.delete_project(&project_id).map_err(|e| e.to_string())
+    db.delete_project(&project_id).map_err(AppError::from)
 }
 
 #[tauri::command]
-fn get_all_steps(state: State<AppState>) -> Result<Vec<Step>, String> {
+fn get_all_steps(sort: Option<StepSort>, state: State<AppState>) -> Result<Vec<Step>, AppError> {
     let db = state.db.lock().unwrap();
-    db.get_all_steps().map_err(|e| e.to_string())
+    let mut steps = db.get_all_steps()?;
+    if sort == Some(StepSort::DueDate) {
+        db.sort_by_due_date(&mut steps, |step| step.due_date.as_deref())?;
+    }
+    Ok(steps)
+}
+
+#[tauri::command]
+fn get_steps_by_project(project_id: String, sort: Option<StepSort>, state: State<AppState>) -> Result<Vec<StepWithState>, AppError> {
+    let db = state.db.lock().unwrap();
+    let mut steps = db.get_steps_with_state(&project_id)?;
+    if sort == Some(StepSort::DueDate) {
+        db.sort_by_due_date(&mut steps, |step| step.step.due_date.as_deref())?;
+    }
+    Ok(steps)
 }
 
 #[tauri::command]
-fn get_steps_by_project(project_id: String, state: State<AppState>) -> Result<Vec<Step>, String> {
+fn create_step(step: Step, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.get_steps_by_project(&project_id).map_err(|e| e.to_string())
+    db.create_step(&step)
 }
 
+// Returns the project's current step, which moves on when the current step is completed
 #[tauri::command]
-fn create_step(step: Step, state: State<AppState>) -> Result<(), String> {
+fn update_step(step: Step, state: State<AppState>) -> Result<Option<String>, AppError> {
     let db = state.db.lock().unwrap();
-    db.create_step(&step).map_err(|e| e.to_string())
+    db.update_step(&step)
 }
 
+// Returns the project's current step like `update_step`
 #[tauri::command]
-fn update_step(step: Step, state: State<AppState>) -> Result<(), String> {
+fn set_step_status(step_id: String, status: String, state: State<AppState>) -> Result<Option<String>, AppError> {
     let db = state.db.lock().unwrap();
-    db.update_step(&step).map_err(|e| e.to_string())
+    db.set_step_status(&step_id, &status)
 }
 
 #[tauri::command]
-fn update_steps_batch(steps: Vec<Step>, state: State<AppState>) -> Result<(), String> {
+fn get_step_status_history(step_id: String, state: State<AppState>) -> Result<Vec<StepStatusChange>, AppError> {
     let db = state.db.lock().unwrap();
-    db.update_steps_batch(&steps).map_err(|e| e.to_string())
+    db.get_step_status_history(&step_id).map_err(AppError::from)
 }
 
+// `project_id` None is the global set
 #[tauri::command]
-fn update_project_current_step(project_id: String, step_id: Option<String>, state: State<AppState>) -> Result<(), String> {
+fn get_step_statuses(project_id: Option<String>, state: State<AppState>) -> Result<Vec<StepStatus>, AppError> {
     let db = state.db.lock().unwrap();
-    db.update_project_current_step(&project_id, step_id.as_deref()).map_err(|e| e.to_string())
+    db.get_step_statuses(project_id.as_deref()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_step_statuses(project_id: Option<String>, statuses: Vec<StepStatus>, state: State<AppState>) -> Result<Vec<StepStatus>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.set_step_statuses(project_id.as_deref(), &statuses)
+}
+
+#[tauri::command]
+fn update_steps_batch(steps: Vec<Step>, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.update_steps_batch(&steps)
+}
+
+#[tauri::command]
+fn update_project_current_step(project_id: String, step_id: Option<String>, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.update_project_current_step(&project_id, step_id.as_deref()).map_err(AppError::from)
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn delete_step(stepId: String, state: State<AppState>) -> Result<(), String> {
+fn delete_step(stepId: String, keepChildren: Option<bool>, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.delete_step(&stepId, keepChildren.unwrap_or(false)).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_step_tree(project_id: String, state: State<AppState>) -> Result<Vec<StepNode>, AppError> {
     let db = state.db.lock().unwrap();
-    db.delete_step(&stepId).map_err(|e| e.to_string())
+    db.get_step_tree(&project_id).map_err(AppError::from)
+}
+
+// `parent_step_id` None moves the step to the top level
+#[tauri::command]
+fn move_step(step_id: String, parent_step_id: Option<String>, index: usize, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.move_step(&step_id, parent_step_id.as_deref(), index)
+}
+
+#[tauri::command]
+fn get_due_items(window: DueWindow, state: State<AppState>) -> Result<Vec<AgendaItem>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_due_items(window).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_agenda(range: AgendaRange, state: State<AppState>) -> Result<Vec<AgendaItem>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_agenda(&range.from, &range.to)
+}
+
+// None when days follow the system's time zone
+#[tauri::command]
+fn get_time_zone(state: State<AppState>) -> Result<Option<String>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_setting(TIME_ZONE_KEY).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_time_zone(time_zone: Option<String>, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    match time_zone {
+        Some(name) => {
+            if name.parse::<chrono_tz::Tz>().is_err() {
+                return Err(AppError::InvalidInput(format!("Unknown time zone '{}'", name)));
+            }
+            db.set_setting(TIME_ZONE_KEY, &name).map_err(AppError::from)
+        }
+        None => db.delete_setting(TIME_ZONE_KEY).map_err(AppError::from),
+    }
+}
+
+#[tauri::command]
+fn get_step_dependencies(project_id: String, state: State<AppState>) -> Result<Vec<StepDependency>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_step_dependencies(&project_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn add_step_dependency(step_id: String, depends_on_step_id: String, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.add_step_dependency(&step_id, &depends_on_step_id)
+}
+
+#[tauri::command]
+fn remove_step_dependency(step_id: String, depends_on_step_id: String, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.remove_step_dependency(&step_id, &depends_on_step_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_next_steps(project_id: String, state: State<AppState>) -> Result<Vec<StepWithState>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_next_steps(&project_id).map_err(AppError::from)
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn get_notes_by_project(projectId: String, state: State<AppState>) -> Result<Vec<Note>, String> {
+fn get_notes_by_project(projectId: String, state: State<AppState>) -> Result<Vec<Note>, AppError> {
     let db = state.db.lock().unwrap();
-    db.get_notes_by_project(&projectId).map_err(|e| e.to_string())
+    db.get_notes_by_project(&projectId).map_err(AppError::from)
 }
 
 #[tauri::command]
-fn create_note(note: Note, state: State<AppState>) -> Result<(), String> {
+fn create_note(note: Note, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.create_note(&note).map_err(|e| e.to_string())
+    db.create_note(&note)
 }
 
 #[tauri::command]
-fn update_note(note: Note, state: State<AppState>) -> Result<(), String> {
+fn update_note(note: Note, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.update_note(&note).map_err(|e| e.to_string())
+    db.update_note(&note)
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn delete_note(noteId: String, state: State<AppState>) -> Result<(), String> {
+fn delete_note(noteId: String, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.delete_note(&noteId).map_err(|e| e.to_string())
+    db.delete_note(&noteId).map_err(AppError::from)
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn get_important_note(projectId: String, state: State<AppState>) -> Result<Option<Note>, String> {
+fn get_important_note(projectId: String, state: State<AppState>) -> Result<Option<Note>, AppError> {
     let db = state.db.lock().unwrap();
-    db.get_important_note(&projectId).map_err(|e| e.to_string())
+    db.get_important_note(&projectId).map_err(AppError::from)
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn set_important_note(projectId: String, noteId: String, state: State<AppState>) -> Result<(), String> {
+fn set_important_note(projectId: String, noteId: String, state: State<AppState>) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.set_important_note(&projectId, &noteId).map_err(|e| e.to_string())
+    db.set_important_note(&projectId, &noteId).map_err(AppError::from)
 }
 
 #[tauri::command]
-fn upload_image(
-    image_data: Vec<u8>,
-    filename: String,
-    content_type: String,
+fn search_everything(query: String, filters: Option<SearchFilters>, state: State<AppState>) -> Result<Vec<SearchHit>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.search(&query, &filters.unwrap_or_default()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn list_trash(state: State<AppState>) -> Result<Vec<TrashItem>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.list_trash().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn restore_item(kind: String, id: String, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.restore_item(&kind, &id)
+}
+
+#[tauri::command]
+fn purge_item(kind: String, id: String, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    let file_paths = db.purge_item(&kind, &id)?;
+    remove_attachment_files(&state.storage.lock().unwrap(), &file_paths);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_trash_retention_days(state: State<AppState>) -> Result<i64, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_trash_retention_days().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_trash_retention_days(days: i64, state: State<AppState>) -> Result<(), AppError> {
+    if days < 1 {
+        return Err(AppError::InvalidInput("Retention period must be at least one day".to_string()));
+    }
+    let db = state.db.lock().unwrap();
+    db.set_setting(TRASH_RETENTION_DAYS_KEY, &days.to_string()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_max_upload_bytes(state: State<AppState>) -> Result<u64, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_max_upload_bytes().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_max_upload_bytes(bytes: u64, state: State<AppState>) -> Result<(), AppError> {
+    if bytes == 0 {
+        return Err(AppError::InvalidInput("The upload limit must be at least one byte".to_string()));
+    }
+    let db = state.db.lock().unwrap();
+    db.set_setting(MAX_UPLOAD_BYTES_KEY, &bytes.to_string()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_image_processing_settings(state: State<AppState>) -> Result<ImageProcessingSettings, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_image_processing_settings().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_image_processing_settings(settings: ImageProcessingSettings, state: State<AppState>) -> Result<(), AppError> {
+    if settings.max_dimension == Some(0) || settings.max_bytes == Some(0) {
+        return Err(AppError::InvalidInput("Image size limits must be greater than zero".to_string()));
+    }
+    let db = state.db.lock().unwrap();
+    db.set_image_processing_settings(&settings).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn list_revisions(item_type: String, item_id: String, state: State<AppState>) -> Result<Vec<Revision>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.list_revisions(&item_type, &item_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_revision(revision_id: String, state: State<AppState>) -> Result<Revision, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_revision(&revision_id).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn restore_revision(revision_id: String, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.restore_revision(&revision_id)
+}
+
+#[tauri::command]
+fn diff_revisions(from_revision_id: String, to_revision_id: Option<String>, state: State<AppState>) -> Result<Vec<DiffLine>, AppError> {
+    let db = state.db.lock().unwrap();
+    db.diff_revisions(&from_revision_id, to_revision_id.as_deref())
+}
+
+// Stores an upload as an image when its bytes are one and as a plain file
+// otherwise, unless `images_only` is set, in which case non-images are rejected.
+fn store_upload(
+    state: &AppState,
+    data: Vec<u8>,
+    filename: &str,
+    content_type: &str,
     content_id: String,
     content_type_enum: String,
-    app: tauri::AppHandle,
-    state: State<AppState>
-) -> Result<ImageAttachment, String> {
-    // Generate unique ID for the image
-    let image_id = Uuid::new_v4().to_string();
-    
-    // Determine attachment folder path
-    let attachment_dir = if cfg!(debug_assertions) {
-        // Development: use project folder
-        std::path::PathBuf::from("/Users/mahmutsalman/Documents/MyCodingProjects/Projects/Efficinecy apps/ProjectSteps/attachmentSources/images")
-    } else {
-        // Production: use Application Support
-        let app_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-        app_dir.join("attachmentSources").join("images")
+    images_only: bool,
+) -> Result<Attachment, AppError> {
+    let (max_bytes, settings) = {
+        let db = state.db.lock().unwrap();
+        (db.get_max_upload_bytes()?, db.get_image_processing_settings()?)
     };
+    if data.len() as u64 > max_bytes {
+        return Err(AppError::InvalidInput(format!(
+            "The file is {} bytes, above the {} byte upload limit",
+            data.len(),
+            max_bytes
+        )));
+    }
     
-    // Create directory if it doesn't exist
-    fs::create_dir_all(&attachment_dir).map_err(|e| e.to_string())?;
-    
-    // Generate unique filename with extension
-    let file_extension = Path::new(&filename)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("png");
-    let unique_filename = format!("{}_{}.{}", image_id, filename.replace(".", "_"), file_extension);
-    let file_path = attachment_dir.join(&unique_filename);
-    
-    // Write image data to file
-    fs::write(&file_path, image_data).map_err(|e| e.to_string())?;
-    
-    // Create image attachment record
-    let attachment = ImageAttachment {
-        id: image_id,
-        file_path: file_path.to_string_lossy().to_string(),
-        filename: unique_filename,
-        content_type,
+    let mut attachment = Attachment {
+        id: Uuid::new_v4().to_string(),
+        file_path: String::new(),
+        filename: String::new(),
+        content_type: String::new(),
         content_id,
         content_type_enum,
         created_at: Utc::now().to_rfc3339(),
+        width: None,
+        height: None,
+        byte_size: None,
+        blob_hash: None,
+        transformed: false,
+        original_blob_hash: None,
+        kind: String::new(),
     };
     
-    // Save to database
-    let db = state.db.lock().unwrap();
-    db.create_image_attachment(&attachment).map_err(|e| e.to_string())?;
+    // The stored type and extension of an image come from the bytes, not the caller
+    let (data, extension, original) = match media::inspect_image(&data) {
+        Ok(info) => {
+            if !media::content_type_matches(content_type, info.content_type) {
+                return Err(AppError::InvalidInput(format!(
+                    "{} was sent as {} but contains {}",
+                    filename, content_type, info.content_type
+                )));
+            }
+            
+            // Strip metadata and downscale as configured, holding on to the
+            // original bytes only if they are to be kept
+            let original = settings.keep_original.then(|| (data.clone(), info.extension));
+            let processed = media::process_upload(data, info, &settings)?;
+            
+            attachment.kind = ATTACHMENT_KIND_IMAGE.to_string();
+            attachment.content_type = processed.info.content_type.to_string();
+            attachment.width = Some(processed.info.width);
+            attachment.height = Some(processed.info.height);
+            attachment.transformed = processed.transformed;
+            let original = original.filter(|_| processed.transformed);
+            (processed.data, processed.info.extension.to_string(), original)
+        }
+        Err(e) if images_only => return Err(e),
+        Err(_) => {
+            let extension = media::file_extension(filename);
+            attachment.kind = ATTACHMENT_KIND_FILE.to_string();
+            attachment.content_type = media::file_content_type(&extension, content_type);
+            (data, extension, None)
+        }
+    };
+    attachment.filename = format!("{}.{}", media::sanitize_file_stem(filename), extension);
+    attachment.byte_size = Some(data.len() as u64);
+    
+    let storage = state.storage.lock().unwrap().clone();
+    
+    // Identical content shares one file in the blob store
+    {
+        let db = state.db.lock().unwrap();
+        let blob = blobs::store_blob(&db, &storage, &data, &extension)?;
+        let original_blob = match original {
+            Some((data, extension)) => Some(blobs::store_blob(&db, &storage, &data, extension)?),
+            None => None,
+        };
+        attachment.file_path = blob.file_path;
+        attachment.blob_hash = Some(blob.hash);
+        attachment.original_blob_hash = original_blob.map(|blob| blob.hash);
+        
+        if let Err(e) = db.create_attachment(&attachment) {
+            // A blob created just for this upload has no other reference
+            remove_attachment_files(&storage, &db.take_unreferenced_blobs().unwrap_or_default());
+            return Err(e);
+        }
+    }
+    
+    // The attachment is usable without thumbnails; the backfill retries on next start
+    if attachment.kind == ATTACHMENT_KIND_IMAGE {
+        if let Err(e) = thumbnails::generate_and_save(state, &attachment) {
+            println!("Failed to generate thumbnails for {}: {}", attachment.id, e);
+        }
+    }
     
+    attachment.file_path = storage
+        .resolve_attachment_path(&attachment.file_path)
+        .to_string_lossy()
+        .to_string();
     Ok(attachment)
 }
 
+#[tauri::command]
+fn upload_attachment(
+    file_data: Vec<u8>,
+    filename: String,
+    content_type: String,
+    content_id: String,
+    content_type_enum: String,
+    state: State<AppState>
+) -> Result<Attachment, AppError> {
+    store_upload(&state, file_data, &filename, &content_type, content_id, content_type_enum, false)
+}
+
+#[tauri::command]
+fn upload_image(
+    image_data: Vec<u8>,
+    filename: String,
+    content_type: String,
+    content_id: String,
+    content_type_enum: String,
+    state: State<AppState>
+) -> Result<Attachment, AppError> {
+    store_upload(&state, image_data, &filename, &content_type, content_id, content_type_enum, true)
+}
+
+#[tauri::command]
+fn get_attachments(
+    content_id: String,
+    content_type_enum: String,
+    kind: Option<String>,
+    state: State<AppState>
+) -> Result<Vec<Attachment>, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    let mut attachments = db.get_attachments_by_content(&content_id, &content_type_enum, kind.as_deref())?;
+    for attachment in &mut attachments {
+        attachment.file_path = storage
+            .resolve_attachment_path(&attachment.file_path)
+            .to_string_lossy()
+            .to_string();
+    }
+    Ok(attachments)
+}
+
 #[tauri::command]
 fn get_image_attachments(
     content_id: String,
     content_type_enum: String,
     state: State<AppState>
-) -> Result<Vec<ImageAttachment>, String> {
+) -> Result<Vec<Attachment>, AppError> {
+    get_attachments(content_id, content_type_enum, Some(ATTACHMENT_KIND_IMAGE.to_string()), state)
+}
+
+// Opens a copy in the system's default app. Attachment files are shared by
+// content hash, so the stored file itself must never be edited in place;
+// changes made in the other app are not brought back. Opening from Rust isn't
+// held to the shell scope the webview is limited by.
+#[tauri::command]
+#[allow(deprecated)]
+fn open_attachment(attachment_id: String, app: tauri::AppHandle, state: State<AppState>) -> Result<(), AppError> {
+    let (attachment, path) = {
+        let db = state.db.lock().unwrap();
+        let storage = state.storage.lock().unwrap();
+        let attachment = db.get_attachment(&attachment_id)?;
+        let path = storage.checked_attachment_path(&attachment.file_path)?;
+        (attachment, path)
+    };
+    
+    let copy_dir = std::env::temp_dir().join("projectsteps-open").join(&attachment.id);
+    fs::create_dir_all(&copy_dir)?;
+    let copy = copy_dir.join(&attachment.filename);
+    fs::copy(&path, &copy)?;
+    app.shell()
+        .open(copy.to_string_lossy(), None)
+        .map_err(|e| AppError::Internal(format!("Failed to open {}: {}", attachment.filename, e)))
+}
+
+#[tauri::command]
+fn delete_attachment(
+    attachment_id: String,
+    state: State<AppState>
+) -> Result<(), AppError> {
     let db = state.db.lock().unwrap();
-    db.get_image_attachments_by_content(&content_id, &content_type_enum)
-        .map_err(|e| e.to_string())
+    let storage = state.storage.lock().unwrap();
+    // Trashed attachments go with their owner instead
+    db.get_attachment(&attachment_id)?;
+    
+    let file_paths = db.delete_attachment(&attachment_id)?;
+    remove_attachment_files(&storage, &file_paths);
+    
+    Ok(())
 }
 
 #[tauri::command]
 fn delete_image_attachment(
     attachment_id: String,
-    file_path: String,
     state: State<AppState>
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    delete_attachment(attachment_id, state)
+}
+
+#[tauri::command]
+fn get_attachment_storage_report(state: State<AppState>) -> Result<AttachmentStorageReport, AppError> {
     let db = state.db.lock().unwrap();
-    
-    // Delete file
-    if Path::new(&file_path).exists() {
-        fs::remove_file(&file_path).map_err(|e| e.to_string())?;
+    db.get_attachment_storage_report().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn scan_attachments(state: State<AppState>) -> Result<AttachmentScanReport, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    gc::scan_attachments(&db, &storage)
+}
+
+#[tauri::command]
+fn clean_attachments(dry_run: bool, state: State<AppState>) -> Result<AttachmentCleanReport, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    gc::clean_attachments(&db, &storage, dry_run)
+}
+
+#[tauri::command]
+fn get_scan_attachments_on_startup(state: State<AppState>) -> Result<bool, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_scan_attachments_on_startup().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_scan_attachments_on_startup(enabled: bool, state: State<AppState>) -> Result<(), AppError> {
+    let db = state.db.lock().unwrap();
+    db.set_setting(SCAN_ATTACHMENTS_ON_STARTUP_KEY, &enabled.to_string()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn list_backups(state: State<AppState>) -> Result<Vec<BackupInfo>, AppError> {
+    let storage = state.storage.lock().unwrap().clone();
+    backup::list_backups(&storage)
+}
+
+// Includes the attachments if the backup settings say so, unless told otherwise
+#[tauri::command]
+fn create_backup_now(include_attachments: Option<bool>, state: State<AppState>) -> Result<BackupInfo, AppError> {
+    let include_attachments = match include_attachments {
+        Some(include) => include,
+        None => state.db.lock().unwrap().get_backup_settings()?.include_attachments,
+    };
+    backup::create_backup(&state, BackupKind::Manual, include_attachments)
+}
+
+// Returns the safety copy of the database taken before restoring
+#[tauri::command]
+fn restore_backup(file_name: String, state: State<AppState>) -> Result<BackupInfo, AppError> {
+    backup::restore_backup(&state, &file_name)
+}
+
+#[tauri::command]
+fn delete_backup(file_name: String, state: State<AppState>) -> Result<(), AppError> {
+    let storage = state.storage.lock().unwrap().clone();
+    backup::delete_backup(&storage, &file_name)
+}
+
+#[tauri::command]
+fn get_backup_settings(state: State<AppState>) -> Result<BackupSettings, AppError> {
+    let db = state.db.lock().unwrap();
+    db.get_backup_settings().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn set_backup_settings(settings: BackupSettings, state: State<AppState>) -> Result<(), AppError> {
+    if settings.interval_hours == 0 {
+        return Err(AppError::InvalidInput("The backup interval must be at least one hour".to_string()));
     }
-    
-    // Delete from database
-    db.delete_image_attachment(&attachment_id).map_err(|e| e.to_string())
+    let db = state.db.lock().unwrap();
+    db.set_backup_settings(&settings).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn export_workspace(path: String, state: State<AppState>) -> Result<ExportReport, AppError> {
+    archive::export(&state, None, Path::new(&path))
+}
+
+#[tauri::command]
+fn export_project(project_id: String, path: String, state: State<AppState>) -> Result<ExportReport, AppError> {
+    archive::export(&state, Some(&project_id), Path::new(&path))
+}
+
+// Remap gives everything new ids; merge keeps ids and overwrites older copies
+#[tauri::command]
+fn import_archive(path: String, mode: Option<ImportMode>, state: State<AppState>) -> Result<ImportReport, AppError> {
+    archive::import(&state, Path::new(&path), mode.unwrap_or(ImportMode::Remap))
+}
+
+// Only previews what would be created unless `dry_run` is false
+#[tauri::command]
+fn import_file(path: String, format: ImportFormat, mapping: Option<ImportMapping>, dry_run: Option<bool>, state: State<AppState>) -> Result<ImportFileReport, AppError> {
+    importers::import_file(&state, Path::new(&path), format, &mapping.unwrap_or_default(), dry_run.unwrap_or(true))
+}
+
+// Writes a single .md file unless a folder layout is asked for
+#[tauri::command]
+fn export_project_markdown(project_id: String, path: String, layout: Option<MarkdownLayout>, state: State<AppState>) -> Result<MarkdownExport, AppError> {
+    markdown::export_project(&state, &project_id, Path::new(&path), layout.unwrap_or(MarkdownLayout::File))
+}
+
+#[tauri::command]
+fn get_thumbnail(attachment_id: String, size: u32, state: State<AppState>) -> Result<Thumbnail, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    let mut thumbnail = db.get_thumbnail(&attachment_id, size)?;
+    thumbnail.file_path = storage
+        .resolve_attachment_path(&thumbnail.file_path)
+        .to_string_lossy()
+        .to_string();
+    Ok(thumbnail)
+}
+
+#[tauri::command]
+fn get_image_file_data(attachment_id: String, state: State<AppState>) -> Result<Vec<u8>, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    let attachment = db.get_attachment(&attachment_id)?;
+    let file_path = storage.checked_attachment_path(&attachment.file_path)?;
+    fs::read(&file_path).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_storage_info(state: State<AppState>) -> Result<StorageInfo, AppError> {
+    Ok(state.storage.lock().unwrap().info())
+}
+
+#[tauri::command]
+fn relocate_data_dir(new_dir: String, state: State<AppState>) -> Result<StorageInfo, AppError> {
+    let mut db = state.db.lock().unwrap();
+    let mut storage = state.storage.lock().unwrap();
+    storage::relocate(&mut db, &mut storage, Path::new(&new_dir))?;
+    Ok(storage.info())
 }
 
 #[tauri::command]
-fn get_image_file_data(file_path: String) -> Result<Vec<u8>, String> {
-    fs::read(&file_path).map_err(|e| e.to_string())
+fn repair_attachment_paths(search_dir: Option<String>, state: State<AppState>) -> Result<RepairReport, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+    storage::repair_attachment_paths(&db, &storage, search_dir.as_deref().map(Path::new))
 }
 
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .register_uri_scheme_protocol(protocol::ATTACHMENT_SCHEME, protocol::handle_attachment_request)
         .setup(|app| {
-            let db_path = if cfg!(debug_assertions) {
-                // Development: use specific project folder path
-                std::path::PathBuf::from("/Users/mahmutsalman/Documents/MyCodingProjects/Projects/Efficinecy apps/ProjectSteps/local.db")
-            } else {
-                // Production: use Application Support
-                let app_dir = app.path().app_data_dir().expect("Failed to get app data dir");
-                std::fs::create_dir_all(&app_dir).expect("Failed to create app data dir");
-                app_dir.join("projectsteps.db")
-            };
-            
+            let args: Vec<String> = std::env::args().collect();
+            let config_dir = app.path().app_config_dir().expect("Failed to get app config dir");
+            let default_dir = app.path().app_data_dir().expect("Failed to get app data dir");
+            let storage = StoragePaths::resolve(
+                &args,
+                std::env::var(storage::DATA_DIR_ENV).ok(),
+                config_dir.join(storage::CONFIG_FILENAME),
+                default_dir,
+            );
+            std::fs::create_dir_all(&storage.data_dir).expect("Failed to create data dir");
+
+            let db_path = storage.db_path();
+            println!("Data directory: {} ({:?})", storage.data_dir.display(), storage.source);
             println!("Database path: {}", db_path.display());
             let database = Database::new(&db_path).expect("Failed to initialize database");
+
+            if let Err(e) = blobs::migrate_existing(&database, &storage) {
+                println!("Failed to move attachments into the blob store: {}", e);
+            }
             
             app.manage(AppState {
                 db: Mutex::new(database),
+                storage: Mutex::new(storage),
             });
+            media::spawn_metadata_backfill(app.handle().clone());
+            thumbnails::spawn_backfill(app.handle().clone());
+            gc::spawn_startup_scan(app.handle().clone());
+            backup::spawn_scheduler(app.handle().clone());
+            spawn_trash_purger(app.handle().clone());
             
             Ok(())
         })
@@ -242,18 +783,70 @@ fn main() {
             create_step,
             update_step,
             update_steps_batch,
+            set_step_status,
+            get_step_status_history,
+            get_step_statuses,
+            set_step_statuses,
             update_project_current_step,
             delete_step,
+            get_step_tree,
+            move_step,
+            get_step_dependencies,
+            get_due_items,
+            get_agenda,
+            get_time_zone,
+            set_time_zone,
+            add_step_dependency,
+            remove_step_dependency,
+            get_next_steps,
             get_notes_by_project,
             create_note,
             update_note,
             delete_note,
             get_important_note,
             set_important_note,
+            search_everything,
+            list_trash,
+            restore_item,
+            purge_item,
+            get_trash_retention_days,
+            set_trash_retention_days,
+            get_max_upload_bytes,
+            set_max_upload_bytes,
+            get_image_processing_settings,
+            set_image_processing_settings,
+            list_revisions,
+            get_revision,
+            restore_revision,
+            diff_revisions,
+            upload_attachment,
+            get_attachments,
+            open_attachment,
+            delete_attachment,
             upload_image,
             get_image_attachments,
             delete_image_attachment,
-            get_image_file_data
+            get_image_file_data,
+            get_thumbnail,
+            get_attachment_storage_report,
+            scan_attachments,
+            clean_attachments,
+            get_scan_attachments_on_startup,
+            set_scan_attachments_on_startup,
+            list_backups,
+            create_backup_now,
+            restore_backup,
+            delete_backup,
+            get_backup_settings,
+            set_backup_settings,
+            export_workspace,
+            export_project,
+            import_archive,
+            export_project_markdown,
+            import_file,
+            get_storage_info,
+            relocate_data_dir,
+            repair_attachment_paths
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
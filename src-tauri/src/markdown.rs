@@ -0,0 +1,894 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::partial_path;
+use crate::database::{Attachment, Database, Note, StepNode, ATTACHMENT_KIND_IMAGE};
+use crate::error::AppError;
+use crate::protocol::ATTACHMENT_SCHEME;
+use crate::storage::StoragePaths;
+use crate::AppState;
+
+// What a folder export holds
+const FOLDER_DOCUMENT: &str = "README.md";
+const FOLDER_IMAGES_DIR: &str = "images";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MarkdownLayout {
+    // `path` is the .md file; images go in a `<name>_images` folder beside it
+    File,
+    // `path` is a new or empty folder that gets README.md and images/
+    Folder,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarkdownExport {
+    // The markdown file that was written
+    pub path: String,
+    pub images: usize,
+    #[serde(rename = "missingImages")]
+    pub missing_images: Vec<String>,
+}
+
+// Renders the project as one readable document: the important note, the
+// description, the steps as a checklist and the remaining notes. Images are
+// copied next to it and linked relatively.
+pub fn export_project(state: &AppState, project_id: &str, path: &Path, layout: MarkdownLayout) -> Result<MarkdownExport, AppError> {
+    let (document_path, images_dir, link_dir) = match layout {
+        MarkdownLayout::File => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .filter(|stem| !stem.is_empty())
+                .ok_or_else(|| AppError::InvalidInput(format!("{} is not a file name", path.display())))?;
+            let link_dir = format!("{}_images", stem);
+            (path.to_path_buf(), path.with_file_name(&link_dir), link_dir)
+        }
+        MarkdownLayout::Folder => {
+            if is_non_empty_dir(path)? {
+                return Err(AppError::InvalidInput(format!("{} is not empty", path.display())));
+            }
+            (path.join(FOLDER_DOCUMENT), path.join(FOLDER_IMAGES_DIR), FOLDER_IMAGES_DIR.to_string())
+        }
+    };
+
+    let (document, images) = {
+        let db = state.db.lock().unwrap();
+        let storage = state.storage.lock().unwrap();
+        let mut images = ImageFiles::new(&db, &storage, link_dir);
+        let document = render_project(&db, project_id, &mut images)?;
+        (document, ImageCopies { copies: images.copies, missing: images.missing })
+    };
+
+    // Images never overwrite files the user already has there
+    if !images.copies.is_empty() && is_non_empty_dir(&images_dir)? {
+        return Err(AppError::InvalidInput(format!("{} is not empty", images_dir.display())));
+    }
+
+    // Attachment files never change once written, so they are copied unlocked
+    if let Some(parent) = document_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if !images.copies.is_empty() {
+        fs::create_dir_all(&images_dir)?;
+    }
+    for (source, name) in &images.copies {
+        fs::copy(source, images_dir.join(name))?;
+    }
+
+    let partial = partial_path(&document_path);
+    if let Err(e) = fs::write(&partial, document) {
+        let _ = fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    fs::rename(&partial, &document_path)?;
+
+    Ok(MarkdownExport {
+        path: document_path.to_string_lossy().to_string(),
+        images: images.copies.len(),
+        missing_images: images.missing,
+    })
+}
+
+fn is_non_empty_dir(path: &Path) -> Result<bool, AppError> {
+    Ok(path.exists() && fs::read_dir(path)?.next().is_some())
+}
+
+fn render_project(db: &Database, project_id: &str, images: &mut ImageFiles) -> Result<String, AppError> {
+    let project = db.get_project(project_id)?;
+    let important = db.get_important_note(project_id)?;
+    let steps = db.get_step_tree(project_id)?;
+    let notes = db.get_notes_by_project(project_id)?;
+
+    let mut sections = vec![format!("# {}", escape_inline(fallback(&project.name, "Untitled project")))];
+
+    if let Some(note) = &important {
+        let content = render_content(db, &note.content, &note.id, &["note"], 3, images)?;
+        let mut quote = format!("> **Important: {}**", escape_inline(fallback(&note.title, "Untitled note")));
+        if !content.is_empty() {
+            quote.push_str("\n>\n");
+            quote.push_str(&prefix_lines(&content, "> ", ">"));
+        }
+        sections.push(quote);
+    }
+
+    let description = render_content(db, &project.description, &project.id, &["project", "project_description"], 1, images)?;
+    if !description.is_empty() {
+        sections.push(description);
+    }
+
+    if !steps.is_empty() {
+        let mut items = Vec::new();
+        render_steps(db, &steps, 0, images, &mut items)?;
+        sections.push(format!("## Steps\n\n{}", items.join("\n\n")));
+    }
+
+    let important_id = important.as_ref().map(|note| note.id.as_str());
+    let notes: Vec<&Note> = notes.iter().filter(|note| Some(note.id.as_str()) != important_id).collect();
+    if !notes.is_empty() {
+        let mut parts = vec!["## Notes".to_string()];
+        for note in notes {
+            parts.push(format!("### {}", escape_inline(fallback(&note.title, "Untitled note"))));
+            let content = render_content(db, &note.content, &note.id, &["note"], 3, images)?;
+            if !content.is_empty() {
+                parts.push(content);
+            }
+        }
+        sections.push(parts.join("\n\n"));
+    }
+
+    Ok(sections.join("\n\n") + "\n")
+}
+
+// Checklist items for the steps, with nested steps indented under their parent
+fn render_steps(db: &Database, steps: &[StepNode], depth: usize, images: &mut ImageFiles, items: &mut Vec<String>) -> Result<(), AppError> {
+    let indentation = "    ".repeat(depth);
+    for node in steps {
+        let mut item = format!(
+            "{}- [{}] **{}**",
+            indentation,
+            if node.done { "x" } else { " " },
+            escape_inline(fallback(&node.step.title, "Untitled step"))
+        );
+        let description = render_content(db, &node.step.description, &node.step.id, &["step"], 2, images)?;
+        if !description.is_empty() {
+            item.push_str("\n\n");
+            item.push_str(&prefix_lines(&description, &format!("{}    ", indentation), ""));
+        }
+        items.push(item);
+        render_steps(db, &node.children, depth + 1, images, items)?;
+    }
+    Ok(())
+}
+
+// Converts an editor's HTML and lists the owner's images that it doesn't show
+// inline, which is how pasted images are kept
+fn render_content(
+    db: &Database,
+    html: &str,
+    owner_id: &str,
+    content_type_enums: &[&str],
+    heading_offset: usize,
+    images: &mut ImageFiles,
+) -> Result<String, AppError> {
+    let mut blocks = Vec::new();
+    let body = html_to_markdown(html, heading_offset, &mut |src| images.link_src(src));
+    if !body.is_empty() {
+        blocks.push(body);
+    }
+
+    for content_type_enum in content_type_enums {
+        for attachment in db.get_attachments_by_content(owner_id, content_type_enum, Some(ATTACHMENT_KIND_IMAGE))? {
+            if images.links.contains_key(&attachment.id) {
+                continue;
+            }
+            if let Some(link) = images.link(&attachment) {
+                blocks.push(format!("![{}]({})", escape_inline(&attachment.filename), link));
+            }
+        }
+    }
+    Ok(blocks.join("\n\n"))
+}
+
+fn fallback<'a>(text: &'a str, default: &'a str) -> &'a str {
+    if text.trim().is_empty() {
+        default
+    } else {
+        text.trim()
+    }
+}
+
+fn prefix_lines(text: &str, prefix: &str, empty_prefix: &str) -> String {
+    text.lines()
+        .map(|line| if line.is_empty() { empty_prefix.to_string() } else { format!("{}{}", prefix, line) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Angle brackets keep link targets with spaces or parentheses intact
+fn link_target(target: &str) -> String {
+    if target.contains([' ', '(', ')']) {
+        format!("<{}>", target.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        target.to_string()
+    }
+}
+
+struct ImageCopies {
+    copies: Vec<(PathBuf, String)>,
+    missing: Vec<String>,
+}
+
+// Names image files as they are linked; the copying happens once the locks
+// are released
+struct ImageFiles<'a> {
+    db: &'a Database,
+    storage: &'a StoragePaths,
+    link_dir: String,
+    // Attachment id to its link, None when its file is missing
+    links: HashMap<String, Option<String>>,
+    // Attachments sharing a blob share one copied file
+    names_by_source: HashMap<PathBuf, String>,
+    names: HashSet<String>,
+    copies: Vec<(PathBuf, String)>,
+    missing: Vec<String>,
+}
+
+impl<'a> ImageFiles<'a> {
+    fn new(db: &'a Database, storage: &'a StoragePaths, link_dir: String) -> ImageFiles<'a> {
+        ImageFiles {
+            db,
+            storage,
+            link_dir,
+            links: HashMap::new(),
+            names_by_source: HashMap::new(),
+            names: HashSet::new(),
+            copies: Vec::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    fn link(&mut self, attachment: &Attachment) -> Option<String> {
+        if let Some(link) = self.links.get(&attachment.id) {
+            return link.clone();
+        }
+
+        let link = match self.storage.checked_attachment_path(&attachment.file_path) {
+            Ok(source) => {
+                let name = match self.names_by_source.get(&source) {
+                    Some(name) => name.clone(),
+                    None => {
+                        let name = self.unique_name(attachment, &source);
+                        self.names_by_source.insert(source.clone(), name.clone());
+                        self.copies.push((source, name.clone()));
+                        name
+                    }
+                };
+                Some(link_target(&format!("{}/{}", self.link_dir, name)))
+            }
+            Err(_) => {
+                self.missing.push(attachment.id.clone());
+                None
+            }
+        };
+        self.links.insert(attachment.id.clone(), link.clone());
+        link
+    }
+
+    // App attachment URLs are swapped for the copied file; other web images
+    // are kept as they are, and anything else (such as data URIs) is dropped
+    fn link_src(&mut self, src: &str) -> Option<String> {
+        if let Some(id) = attachment_id_from_src(src) {
+            return match self.db.get_attachment(id) {
+                Ok(attachment) => self.link(&attachment),
+                Err(_) => {
+                    self.missing.push(id.to_string());
+                    None
+                }
+            };
+        }
+        (src.starts_with("http://") || src.starts_with("https://")).then(|| link_target(src))
+    }
+
+    fn unique_name(&mut self, attachment: &Attachment, source: &Path) -> String {
+        let mut base: String = Path::new(&attachment.filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+            .collect();
+        if base.trim_matches('-').is_empty() {
+            base = "image".to_string();
+        }
+        // The stored file's extension matches its contents after any conversion
+        let extension = source
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "bin".to_string());
+
+        let mut name = format!("{}.{}", base, extension);
+        let mut counter = 2;
+        while !self.names.insert(name.clone()) {
+            name = format!("{}-{}.{}", base, counter, extension);
+            counter += 1;
+        }
+        name
+    }
+}
+
+fn attachment_id_from_src(src: &str) -> Option<&str> {
+    [format!("{}://localhost/", ATTACHMENT_SCHEME), format!("{}.localhost/", ATTACHMENT_SCHEME)]
+        .iter()
+        .find_map(|host| src.find(host.as_str()).map(|start| &src[start + host.len()..]))
+        .and_then(|rest| rest.split(['/', '?', '#']).next())
+        .filter(|id| !id.is_empty())
+}
+
+enum Token {
+    Text(String),
+    Start { name: String, attrs: Vec<(String, String)> },
+    End(String),
+}
+
+// Splits HTML into tags and text. Quill's output is simple enough that this
+// doesn't need to handle anything beyond plain elements, entities and comments.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if let Some(end) = tag_end(rest) {
+            if let Some(token) = parse_tag(&rest[1..end]) {
+                tokens.push(token);
+            }
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let next = rest.char_indices().skip(1).find(|&(_, c)| c == '<').map_or(rest.len(), |(i, _)| i);
+        tokens.push(Token::Text(decode_entities(&rest[..next])));
+        rest = &rest[next..];
+    }
+    tokens
+}
+
+// The index of the `>` closing a tag at the start of `html`, skipping any in
+// quoted attribute values
+fn tag_end(html: &str) -> Option<usize> {
+    let mut chars = html.char_indices();
+    if chars.next()?.1 != '<' {
+        return None;
+    }
+    match chars.next()?.1 {
+        c if c.is_ascii_alphabetic() || c == '/' || c == '!' => {}
+        _ => return None,
+    }
+    let mut quote = None;
+    for (i, c) in chars {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_tag(inner: &str) -> Option<Token> {
+    if let Some(name) = inner.strip_prefix('/') {
+        return Some(Token::End(name.trim().to_ascii_lowercase()));
+    }
+    if inner.starts_with('!') {
+        return None;
+    }
+
+    let inner = inner.trim_end_matches('/');
+    let name_end = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+    let name = inner[..name_end].to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut rest = inner[name_end..].trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remainder) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => match after[1..].find(quote) {
+                    Some(end) => (&after[1..end + 1], &after[end + 2..]),
+                    None => (&after[1..], ""),
+                },
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = remainder.trim_start();
+        }
+        if !key.is_empty() {
+            attrs.push((key, value));
+        }
+    }
+    Some(Token::Start { name, attrs })
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').filter(|&end| end <= 10).map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// Backslash-escapes the characters Markdown would read as formatting
+fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let chars: Vec<char> = text.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let escape = match c {
+            '\\' | '*' | '`' | '[' | ']' | '<' => true,
+            // Underscores inside words, as in snake_case, are left alone
+            '_' => {
+                let before = i > 0 && chars[i - 1].is_alphanumeric();
+                let after = chars.get(i + 1).is_some_and(|c| c.is_alphanumeric());
+                !(before && after)
+            }
+            _ => false,
+        };
+        if escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Keeps a block's text from starting like a heading, quote or list item
+fn escape_block_start(text: &str) -> String {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let rest = &text[digits..];
+    let marker = if digits > 0 {
+        rest.starts_with(['.', ')'])
+    } else {
+        text.starts_with(['#', '>', '-', '+', '=']) || text.starts_with("~~~")
+    };
+    if !marker {
+        return text.to_string();
+    }
+    let at = if digits > 0 { digits } else { 0 };
+    format!("{}\\{}", &text[..at], &text[at..])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DivKind {
+    Block,
+    CodeContainer,
+    CodeLine,
+}
+
+struct Block {
+    text: String,
+    list_item: bool,
+}
+
+struct Converter<'a> {
+    blocks: Vec<Block>,
+    // Inline text of the open block; `\n` marks a line break
+    line: String,
+    prefix: String,
+    continuation: String,
+    list_item: bool,
+    heading_offset: usize,
+    quote_depth: usize,
+    lists: Vec<String>,
+    counters: Vec<usize>,
+    divs: Vec<DivKind>,
+    code: Option<String>,
+    inline_code: bool,
+    marks: Vec<(String, usize)>,
+    links: Vec<(String, usize)>,
+    resolve_image: &'a mut dyn FnMut(&str) -> Option<String>,
+}
+
+// Converts Quill's HTML to Markdown. Headings are pushed down by
+// `heading_offset` levels so they nest under the document's own headings, and
+// `resolve_image` turns an image's src into the link to write, or None to
+// drop it. Underline, colors and sizes have no Markdown form and are dropped.
+pub fn html_to_markdown(html: &str, heading_offset: usize, resolve_image: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    let mut converter = Converter {
+        blocks: Vec::new(),
+        line: String::new(),
+        prefix: String::new(),
+        continuation: String::new(),
+        list_item: false,
+        heading_offset,
+        quote_depth: 0,
+        lists: Vec::new(),
+        counters: Vec::new(),
+        divs: Vec::new(),
+        code: None,
+        inline_code: false,
+        marks: Vec::new(),
+        links: Vec::new(),
+        resolve_image,
+    };
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => converter.text(&text),
+            Token::Start { name, attrs } => converter.start(&name, &attrs),
+            Token::End(name) => converter.end(&name),
+        }
+    }
+    converter.finish_code();
+    converter.flush();
+
+    let mut markdown = String::new();
+    let mut previous_list_item = false;
+    for block in converter.blocks {
+        if !markdown.is_empty() {
+            markdown.push_str(if previous_list_item && block.list_item { "\n" } else { "\n\n" });
+        }
+        markdown.push_str(&block.text);
+        previous_list_item = block.list_item;
+    }
+    markdown
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+fn has_class(attrs: &[(String, String)], class: &str) -> bool {
+    attr(attrs, "class").is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+}
+
+impl Converter<'_> {
+    fn quote_prefix(&self) -> String {
+        "> ".repeat(self.quote_depth)
+    }
+
+    fn open_block(&mut self, prefix: String, continuation: String, list_item: bool) {
+        self.flush();
+        self.prefix = prefix;
+        self.continuation = continuation;
+        self.list_item = list_item;
+    }
+
+    // Ends the open block; whatever follows is a plain paragraph until the
+    // next block element says otherwise
+    fn flush(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let lines: Vec<&str> = line.split('\n').map(str::trim).filter(|line| !line.is_empty()).collect();
+        if !lines.is_empty() {
+            let first = escape_block_start(lines[0]);
+            let mut text = format!("{}{}", self.prefix, first);
+            for line in &lines[1..] {
+                text.push_str("\\\n");
+                text.push_str(&self.continuation);
+                text.push_str(&escape_block_start(line));
+            }
+            self.blocks.push(Block { text, list_item: self.list_item });
+        }
+        self.prefix = self.quote_prefix();
+        self.continuation = self.quote_prefix();
+        self.list_item = false;
+        self.marks.clear();
+        self.links.clear();
+    }
+
+    fn start_code(&mut self) {
+        self.flush();
+        self.code = Some(String::new());
+    }
+
+    fn finish_code(&mut self) {
+        if let Some(code) = self.code.take() {
+            let code = code.trim_end_matches('\n');
+            let fence = if code.contains("```") { "~~~~" } else { "```" };
+            let text = format!("{}\n{}\n{}", fence, code, fence);
+            let quote = self.quote_prefix();
+            self.blocks.push(Block { text: prefix_lines(&text, &quote, quote.trim_end()), list_item: false });
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some(code) = &mut self.code {
+            code.push_str(text);
+            return;
+        }
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.starts_with(char::is_whitespace) && !self.line.is_empty() && !self.line.ends_with([' ', '\n']) {
+            self.line.push(' ');
+        }
+        if self.inline_code {
+            self.line.push_str(&collapsed);
+        } else {
+            self.line.push_str(&escape_inline(&collapsed));
+        }
+        if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+            self.line.push(' ');
+        }
+    }
+
+    fn start(&mut self, name: &str, attrs: &[(String, String)]) {
+        if let Some(code) = &mut self.code {
+            match name {
+                "br" => code.push('\n'),
+                "div" => self.divs.push(DivKind::CodeLine),
+                _ => {}
+            }
+            return;
+        }
+
+        match name {
+            // A paragraph directly inside a list item continues it
+            "p" if !(self.list_item && self.line.trim().is_empty()) => {
+                let quote = self.quote_prefix();
+                self.open_block(quote.clone(), quote, false);
+            }
+            "div" => {
+                if has_class(attrs, "ql-code-block-container") {
+                    self.divs.push(DivKind::CodeContainer);
+                    self.start_code();
+                } else {
+                    self.divs.push(DivKind::Block);
+                    self.flush();
+                }
+            }
+            "pre" => self.start_code(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = (name[1..].parse::<usize>().unwrap_or(1) + self.heading_offset).min(6);
+                let prefix = format!("{}{} ", self.quote_prefix(), "#".repeat(level));
+                let quote = self.quote_prefix();
+                self.open_block(prefix, quote, false);
+            }
+            "blockquote" => {
+                self.flush();
+                self.quote_depth += 1;
+                let quote = self.quote_prefix();
+                self.open_block(quote.clone(), quote, false);
+            }
+            "ul" | "ol" => {
+                self.flush();
+                self.lists.push(name.to_string());
+            }
+            "li" => {
+                // Quill 2 keeps every item in one <ol>, marking the kind with
+                // data-list and the nesting with an ql-indent-N class
+                let kind = attr(attrs, "data-list").unwrap_or(match self.lists.last().map(String::as_str) {
+                    Some("ol") => "ordered",
+                    _ => "bullet",
+                });
+                let class_indent = attr(attrs, "class")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .find_map(|class| class.strip_prefix("ql-indent-")?.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let indent = self.lists.len().saturating_sub(1) + class_indent;
+
+                self.counters.truncate(indent + 1);
+                self.counters.resize(indent + 1, 0);
+                let marker = match kind {
+                    "ordered" => {
+                        self.counters[indent] += 1;
+                        format!("{}. ", self.counters[indent])
+                    }
+                    "checked" => "- [x] ".to_string(),
+                    "unchecked" => "- [ ] ".to_string(),
+                    _ => "- ".to_string(),
+                };
+                if kind != "ordered" {
+                    self.counters[indent] = 0;
+                }
+
+                let indentation = "    ".repeat(indent);
+                let prefix = format!("{}{}{}", self.quote_prefix(), indentation, marker);
+                let continuation = format!("{}{}{}", self.quote_prefix(), indentation, " ".repeat(marker.len()));
+                self.open_block(prefix, continuation, true);
+            }
+            "br" if !self.line.trim().is_empty() => self.line.push('\n'),
+            "strong" | "b" => self.open_mark("**"),
+            "em" | "i" => self.open_mark("*"),
+            "s" | "strike" | "del" => self.open_mark("~~"),
+            "code" => {
+                self.open_mark("`");
+                self.inline_code = true;
+            }
+            "a" => {
+                let href = attr(attrs, "href").unwrap_or_default().to_string();
+                self.links.push((href, self.line.len()));
+            }
+            "img" => {
+                let src = attr(attrs, "src").unwrap_or_default();
+                if let Some(link) = (self.resolve_image)(src) {
+                    let alt = escape_inline(attr(attrs, "alt").unwrap_or_default());
+                    self.line.push_str(&format!("![{}]({})", alt, link));
+                }
+            }
+            "iframe" => {
+                if let Some(src) = attr(attrs, "src").filter(|src| !src.is_empty()) {
+                    self.flush();
+                    self.line.push_str(&format!("[Video]({})", link_target(src)));
+                    self.flush();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if self.code.is_some() {
+            match name {
+                "pre" => self.finish_code(),
+                "div" => match self.divs.pop() {
+                    Some(DivKind::CodeContainer) => self.finish_code(),
+                    _ => {
+                        let code = self.code.as_mut().unwrap();
+                        if !code.ends_with('\n') {
+                            code.push('\n');
+                        }
+                    }
+                },
+                _ => {}
+            }
+            return;
+        }
+
+        match name {
+            "p" | "li" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => self.flush(),
+            "div" => {
+                self.divs.pop();
+                self.flush();
+            }
+            "blockquote" => {
+                self.flush();
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+                self.prefix = self.quote_prefix();
+                self.continuation = self.quote_prefix();
+            }
+            "ul" | "ol" => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.counters.clear();
+                }
+            }
+            "strong" | "b" => self.close_mark("**"),
+            "em" | "i" => self.close_mark("*"),
+            "s" | "strike" | "del" => self.close_mark("~~"),
+            "code" => {
+                self.inline_code = false;
+                self.close_mark("`");
+            }
+            "a" => {
+                if let Some((href, start)) = self.links.pop() {
+                    if href.is_empty() || start > self.line.len() {
+                        return;
+                    }
+                    let text = self.line.split_off(start);
+                    let text = if text.trim().is_empty() { escape_inline(&href) } else { text.trim().to_string() };
+                    self.line.push_str(&format!("[{}]({})", text, link_target(&href)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open_mark(&mut self, mark: &str) {
+        self.marks.push((mark.to_string(), self.line.len()));
+        self.line.push_str(mark);
+    }
+
+    // Drops empty pairs and moves trailing spaces outside the closing mark,
+    // since `**bold **` doesn't render as bold
+    fn close_mark(&mut self, mark: &str) {
+        let Some(index) = self.marks.iter().rposition(|(open, _)| open == mark) else {
+            return;
+        };
+        let (_, start) = self.marks.remove(index);
+        if start + mark.len() > self.line.len() {
+            return;
+        }
+        if self.line[start + mark.len()..].trim().is_empty() {
+            self.line.truncate(start);
+            return;
+        }
+        let trimmed_len = self.line.trim_end().len();
+        let trailing = self.line.split_off(trimmed_len);
+        self.line.push_str(mark);
+        self.line.push_str(&trailing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        html_to_markdown(html, 1, &mut |_| None)
+    }
+
+    #[test]
+    fn tags_keep_quoted_brackets_and_comments_are_dropped() {
+        let tokens = tokenize(r#"<p title="a > b">x<!-- <b>hidden</b> --> 1 &lt; 2</p>"#);
+        assert!(matches!(
+            &tokens[0],
+            Token::Start { name, attrs } if name == "p" && attrs == &[("title".to_string(), "a > b".to_string())]
+        ));
+        assert!(matches!(&tokens[1], Token::Text(text) if text == "x"));
+        assert!(matches!(&tokens[2], Token::Text(text) if text == " 1 < 2"));
+        assert!(matches!(&tokens[3], Token::End(name) if name == "p"));
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn list_items_nest_by_their_indent_class() {
+        let html = concat!(
+            r#"<ol><li data-list="bullet">a</li><li data-list="bullet" class="ql-indent-1">b</li>"#,
+            r#"<li data-list="ordered" class="ql-indent-1">c</li><li data-list="ordered" class="ql-indent-1">d</li>"#,
+            r#"<li data-list="checked">e</li><li data-list="ordered">f</li></ol>"#,
+        );
+        assert_eq!(convert(html), "- a\n    - b\n    1. c\n    2. d\n- [x] e\n1. f");
+    }
+
+    #[test]
+    fn code_blocks_keep_their_lines_unescaped() {
+        let html = concat!(
+            r#"<div class="ql-code-block-container" spellcheck="false"><div class="ql-code-block">fn main() {</div>"#,
+            r#"<div class="ql-code-block">    a &lt; b &amp;&amp; *c*</div><div class="ql-code-block">}</div></div><p>after</p>"#,
+        );
+        assert_eq!(convert(html), "```\nfn main() {\n    a < b && *c*\n}\n```\n\nafter");
+        assert_eq!(convert(r#"<pre>let x = "```";</pre>"#), "~~~~\nlet x = \"```\";\n~~~~");
+    }
+
+    #[test]
+    fn links_wrap_their_text_and_fall_back_to_the_address() {
+        let html = r#"<p>See <a href="https://example.com/a?b=1&amp;c=2">the <strong>site</strong></a> or <a href="https://x.y">  </a></p>"#;
+        assert_eq!(convert(html), "See [the **site**](https://example.com/a?b=1&c=2) or [https://x.y](https://x.y)");
+    }
+
+    #[test]
+    fn nested_marks_close_around_multibyte_text() {
+        let html = "<p>héllo <strong>wörld <em>ünd </em></strong>ende <em></em><s>gone</s></p>";
+        assert_eq!(convert(html), "héllo **wörld *ünd*** ende ~~gone~~");
+    }
+}
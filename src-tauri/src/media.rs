@@ -0,0 +1,438 @@
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::fs;
+use std::io::Cursor;
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::database::ImageProcessingSettings;
+use crate::error::AppError;
+use crate::AppState;
+
+// Formats accepted as image attachments; anything else is rejected on upload
+const ACCEPTED_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+    ImageFormat::Bmp,
+];
+
+// Quality used whenever an image is re-encoded as JPEG
+const JPEG_QUALITY: u8 = 85;
+
+// How many times an image is shrunk further to get under the byte limit
+const MAX_SHRINK_ATTEMPTS: u32 = 5;
+
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Identifies an upload from its magic bytes rather than the filename and
+// content type the caller claims. Only the header is decoded.
+pub fn inspect_image(data: &[u8]) -> Result<ImageInfo, AppError> {
+    let format = image::guess_format(data)
+        .ok()
+        .filter(|format| ACCEPTED_FORMATS.contains(format))
+        .ok_or_else(|| {
+            AppError::InvalidInput("The file is not a supported image (PNG, JPEG, GIF, WebP or BMP)".to_string())
+        })?;
+
+    let (width, height) = ImageReader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|e| AppError::InvalidInput(format!("The image could not be read: {}", e)))?;
+
+    Ok(ImageInfo {
+        format,
+        content_type: format.to_mime_type(),
+        extension: format.extensions_str()[0],
+        width,
+        height,
+    })
+}
+
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub info: ImageInfo,
+    // The stored bytes differ from the ones uploaded
+    pub transformed: bool,
+}
+
+// Applies the image processing settings to an upload. Metadata is removed
+// without re-encoding where the format allows it; the image is only decoded
+// and re-encoded when it has to be downscaled or an EXIF orientation has to be
+// baked in before the tag is dropped. Animated GIFs are stored untouched, as
+// re-encoding would keep only the first frame.
+pub fn process_upload(data: Vec<u8>, info: ImageInfo, settings: &ImageProcessingSettings) -> Result<ProcessedImage, AppError> {
+    if info.format == ImageFormat::Gif && is_animated_gif(&data) {
+        return Ok(ProcessedImage { data, info, transformed: false });
+    }
+
+    let too_large = settings.max_dimension.is_some_and(|max| info.width > max || info.height > max)
+        || settings.max_bytes.is_some_and(|max| data.len() as u64 > max);
+    let orientation = read_orientation(&data, info.format);
+    let needs_reencode = too_large || (settings.strip_metadata && orientation != Orientation::NoTransforms);
+
+    if needs_reencode {
+        let processed = reencode(&data, info.format, settings)?;
+        return Ok(ProcessedImage {
+            info: inspect_image(&processed)?,
+            data: processed,
+            transformed: true,
+        });
+    }
+
+    if settings.strip_metadata {
+        let stripped = match info.format {
+            ImageFormat::Jpeg => strip_jpeg_metadata(&data),
+            ImageFormat::Png => strip_png_metadata(&data),
+            ImageFormat::WebP => strip_webp_metadata(&data),
+            ImageFormat::Gif => strip_gif_metadata(&data),
+            _ => None,
+        };
+        if let Some(stripped) = stripped.filter(|stripped| stripped.len() != data.len()) {
+            return Ok(ProcessedImage { data: stripped, info, transformed: true });
+        }
+    }
+
+    Ok(ProcessedImage { data, info, transformed: false })
+}
+
+// Only decodes as far as the second frame
+fn is_animated_gif(data: &[u8]) -> bool {
+    GifDecoder::new(Cursor::new(data)).is_ok_and(|decoder| decoder.into_frames().take(2).count() > 1)
+}
+
+fn read_orientation(data: &[u8], format: ImageFormat) -> Orientation {
+    ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .and_then(|mut decoder| decoder.orientation())
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+// Decodes the image upright, shrinks it to fit `max_dimension` and then keeps
+// shrinking until it fits `max_bytes`. PNG and JPEG keep their format; other
+// formats become PNG when they have transparency and JPEG otherwise.
+fn reencode(data: &[u8], format: ImageFormat, settings: &ImageProcessingSettings) -> Result<Vec<u8>, AppError> {
+    let decode_error = |e: image::ImageError| AppError::InvalidInput(format!("The image could not be decoded: {}", e));
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .map_err(decode_error)?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(decode_error)?;
+    image.apply_orientation(orientation);
+
+    let output_format = match format {
+        ImageFormat::Png | ImageFormat::Jpeg => format,
+        _ if image.color().has_alpha() => ImageFormat::Png,
+        _ => ImageFormat::Jpeg,
+    };
+
+    if let Some(max) = settings.max_dimension {
+        if image.width() > max || image.height() > max {
+            image = image.resize(max, max, FilterType::Lanczos3);
+        }
+    }
+
+    let mut attempt = 0;
+    loop {
+        let encoded = encode(&image, output_format)?;
+        attempt += 1;
+        let fits = settings.max_bytes.is_none_or(|max| encoded.len() as u64 <= max);
+        if fits || attempt >= MAX_SHRINK_ATTEMPTS || image.width() <= 1 || image.height() <= 1 {
+            return Ok(encoded);
+        }
+        image = image.resize(image.width() * 4 / 5, image.height() * 4 / 5, FilterType::Lanczos3);
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, AppError> {
+    let mut data = Vec::new();
+    let result = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY)),
+        _ => image.write_to(&mut Cursor::new(&mut data), format),
+    };
+    result.map_err(|e| AppError::Internal(format!("Failed to encode image: {}", e)))?;
+    Ok(data)
+}
+
+// Drops the APP1 (EXIF, XMP), APP13 (IPTC) and comment segments that precede
+// the image data. Returns None if the segment structure can't be followed.
+fn strip_jpeg_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    loop {
+        if pos + 2 > data.len() || data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // Fill byte before a marker
+            0xFF => pos += 1,
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                stripped.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+            }
+            // Start of scan: everything after it is image data
+            0xDA => {
+                stripped.extend_from_slice(&data[pos..]);
+                return Some(stripped);
+            }
+            _ => {
+                let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+                let end = pos + 2 + len;
+                if len < 2 || end > data.len() {
+                    return None;
+                }
+                if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+                    stripped.extend_from_slice(&data[pos..end]);
+                }
+                pos = end;
+            }
+        }
+    }
+}
+
+// Drops the eXIf and text chunks, which is where PNG keeps metadata.
+fn strip_png_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE_LEN: usize = 8;
+    if data.len() < SIGNATURE_LEN {
+        return None;
+    }
+
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..SIGNATURE_LEN]);
+    let mut pos = SIGNATURE_LEN;
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk_type = data.get(pos + 4..pos + 8)?;
+        // Length, type, data and CRC
+        let end = pos.checked_add(12 + len)?;
+        if end > data.len() {
+            return None;
+        }
+        if !matches!(chunk_type, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt") {
+            stripped.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    Some(stripped)
+}
+
+// Drops the EXIF and XMP chunks of the RIFF container, clears their flags in
+// the VP8X header and fixes up the RIFF size.
+fn strip_webp_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    const HEADER_LEN: usize = 12;
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+    if data.len() < HEADER_LEN || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..HEADER_LEN]);
+    let mut pos = HEADER_LEN;
+    while pos < data.len() {
+        let fourcc = data.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length
+        let end = pos.checked_add(8 + len + len % 2)?.min(data.len());
+        if pos + 8 + len > data.len() {
+            return None;
+        }
+        match fourcc {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = stripped.len();
+                stripped.extend_from_slice(&data[pos..end]);
+                if let Some(flags) = stripped.get_mut(start + 8) {
+                    *flags &= !(EXIF_FLAG | XMP_FLAG);
+                }
+            }
+            _ => stripped.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+
+    let riff_size = u32::try_from(stripped.len() - 8).ok()?;
+    stripped[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(stripped)
+}
+
+// Drops the comment and application extensions, which is where GIF keeps
+// text and XMP. The looping extension is an application one too, but only
+// matters to animations, which aren't stripped.
+fn strip_gif_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    const HEADER_LEN: usize = 13;
+    const COLOR_TABLE_FLAG: u8 = 0x80;
+    if data.len() < HEADER_LEN || !data.starts_with(b"GIF") {
+        return None;
+    }
+    let color_table_len = |flags: u8| if flags & COLOR_TABLE_FLAG != 0 { 3 << ((flags & 0x07) + 1) } else { 0 };
+    // Data sub-blocks run up to an empty one
+    let skip_sub_blocks = |mut pos: usize| -> Option<usize> {
+        loop {
+            let len = *data.get(pos)? as usize;
+            pos += 1 + len;
+            if len == 0 {
+                return Some(pos);
+            }
+        }
+    };
+
+    let mut pos = HEADER_LEN + color_table_len(data[10]);
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(data.get(..pos)?);
+    loop {
+        match *data.get(pos)? {
+            // Extension: introducer, label, then sub-blocks
+            0x21 => {
+                let label = *data.get(pos + 1)?;
+                let end = skip_sub_blocks(pos + 2)?;
+                if !matches!(label, 0xFE | 0xFF) {
+                    stripped.extend_from_slice(data.get(pos..end)?);
+                }
+                pos = end;
+            }
+            // Image: descriptor, optional color table, LZW code size, then sub-blocks
+            0x2C => {
+                let flags = *data.get(pos + 9)?;
+                let end = skip_sub_blocks(pos + 10 + color_table_len(flags) + 1)?;
+                stripped.extend_from_slice(data.get(pos..end)?);
+                pos = end;
+            }
+            // Trailer
+            0x3B => {
+                stripped.push(0x3B);
+                return Some(stripped);
+            }
+            _ => return None,
+        }
+    }
+}
+
+// `image/jpg` is a common misspelling of `image/jpeg` and isn't a mismatch.
+// Neither is a blank or generic type, which only says the sender didn't know.
+pub fn content_type_matches(claimed: &str, sniffed: &str) -> bool {
+    let claimed = claimed.trim().to_ascii_lowercase();
+    claimed.is_empty()
+        || claimed == "application/octet-stream"
+        || claimed == sniffed
+        || (claimed == "image/jpg" && sniffed == "image/jpeg")
+}
+
+// Keeps the original name readable in the stored filename while dropping its
+// extension and anything that isn't safe in a path.
+pub fn sanitize_file_stem(filename: &str) -> String {
+    let stem = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+        .unwrap_or_default();
+    let stem: String = stem
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if stem.is_empty() { "attachment".to_string() } else { stem }
+}
+
+// The extension a non-image file is stored under, taken from its name
+pub fn file_extension(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .filter(|extension| {
+            !extension.is_empty()
+                && extension.len() <= 10
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    extension.unwrap_or_else(|| "bin".to_string())
+}
+
+// Non-image files can't be sniffed reliably, so the type comes from the
+// extension for the formats we know and from the caller otherwise.
+pub fn file_content_type(extension: &str, claimed: &str) -> String {
+    let known = match extension {
+        "pdf" => Some("application/pdf"),
+        "txt" | "log" => Some("text/plain"),
+        "md" => Some("text/markdown"),
+        "csv" => Some("text/csv"),
+        "json" => Some("application/json"),
+        "xml" => Some("application/xml"),
+        "html" | "htm" => Some("text/html"),
+        "zip" => Some("application/zip"),
+        "gz" => Some("application/gzip"),
+        "tar" => Some("application/x-tar"),
+        "7z" => Some("application/x-7z-compressed"),
+        _ => None,
+    };
+    match known {
+        Some(content_type) => content_type.to_string(),
+        None if claimed.contains('/') => claimed.trim().to_ascii_lowercase(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+// Fills in the size and dimensions of attachments uploaded before they were
+// recorded. Files that can't be read are left for the next start.
+pub fn spawn_metadata_backfill<R: Runtime>(app: AppHandle<R>) {
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let pending = match state.db.lock().unwrap().get_attachments_without_metadata() {
+            Ok(pending) => pending,
+            Err(e) => {
+                println!("Failed to list attachments for metadata backfill: {}", e);
+                return;
+            }
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut updated = 0;
+        for attachment in &pending {
+            let storage = state.storage.lock().unwrap().clone();
+            let path = match storage.checked_attachment_path(&attachment.file_path) {
+                Ok(path) => path,
+                Err(e) => {
+                    println!("Skipping metadata for {}: {}", attachment.id, e);
+                    continue;
+                }
+            };
+            let byte_size = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) => {
+                    println!("Skipping metadata for {}: {}", attachment.id, e);
+                    continue;
+                }
+            };
+            let dimensions = image::image_dimensions(&path).ok();
+
+            let db = state.db.lock().unwrap();
+            match db.update_attachment_metadata(
+                &attachment.id,
+                dimensions.map(|(width, _)| width),
+                dimensions.map(|(_, height)| height),
+                byte_size,
+            ) {
+                Ok(()) => updated += 1,
+                Err(e) => println!("Failed to record metadata for {}: {}", attachment.id, e),
+            }
+        }
+        println!("Attachment metadata backfill: {} of {} attachments", updated, pending.len());
+    });
+}
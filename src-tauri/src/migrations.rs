@@ -0,0 +1,907 @@
+use rusqlite::{ffi, Connection, Error, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+
+// A single schema change. Migrations are applied in order, each inside its own
+// transaction, and new databases are built by running every migration from zero.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub up: fn(&Connection) -> Result<()>,
+}
+
+// Append new migrations to the end of this list; never edit or reorder
+// migrations that have already shipped.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create projects and steps tables",
+        up: create_projects_and_steps,
+    },
+    Migration {
+        version: 2,
+        description: "Track the current step of a project",
+        up: add_project_current_step,
+    },
+    Migration {
+        version: 3,
+        description: "Create notes table",
+        up: create_notes,
+    },
+    Migration {
+        version: 4,
+        description: "Store plain text for step descriptions",
+        up: add_step_plain_text,
+    },
+    Migration {
+        version: 5,
+        description: "Create image attachments table",
+        up: create_image_attachments,
+    },
+    Migration {
+        version: 6,
+        description: "Allow marking a note as important",
+        up: add_note_is_important,
+    },
+    Migration {
+        version: 7,
+        description: "Create full-text search index",
+        up: create_search_index,
+    },
+    Migration {
+        version: 8,
+        description: "Cascade attachment deletes and remove orphaned rows",
+        up: cascade_attachments_and_repair_orphans,
+    },
+    Migration {
+        version: 9,
+        description: "Soft delete with a trash bin and a settings table",
+        up: add_trash_and_settings,
+    },
+    Migration {
+        version: 10,
+        description: "Revision history for step descriptions and notes",
+        up: create_revisions,
+    },
+    Migration {
+        version: 11,
+        description: "Store attachment paths relative to the attachments root",
+        up: make_attachment_paths_relative,
+    },
+    Migration {
+        version: 12,
+        description: "Create image thumbnails table",
+        up: create_image_thumbnails,
+    },
+    Migration {
+        version: 13,
+        description: "Record image dimensions and file size",
+        up: add_image_metadata,
+    },
+    Migration {
+        version: 14,
+        description: "Content-addressed attachment blobs with reference counts",
+        up: create_attachment_blobs,
+    },
+    Migration {
+        version: 15,
+        description: "Track transformed images and their kept originals",
+        up: add_image_transform_tracking,
+    },
+    Migration {
+        version: 16,
+        description: "Generalize image attachments into attachments with a kind",
+        up: generalize_attachments,
+    },
+    Migration {
+        version: 17,
+        description: "Allow attachments on projects and validate attachment owners",
+        up: validate_attachment_owners,
+    },
+    Migration {
+        version: 18,
+        description: "Nest steps under parent steps",
+        up: add_step_parent,
+    },
+    Migration {
+        version: 19,
+        description: "Create step dependencies table",
+        up: create_step_dependencies,
+    },
+    Migration {
+        version: 20,
+        description: "Configurable step statuses with a history of changes",
+        up: add_step_statuses,
+    },
+    Migration {
+        version: 21,
+        description: "Start and due dates on projects and steps",
+        up: add_schedule_dates,
+    },
+];
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn get_schema_version(conn: &Connection) -> Result<i32> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY
+        )",
+        [],
+    )?;
+
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn set_schema_version(conn: &Connection, version: i32) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO schema_version (version) VALUES (?1)",
+        [version],
+    )?;
+    Ok(())
+}
+
+// Brings the database up to `latest_version()`. Refuses to touch a database
+// written by a newer build, since we can't know what its schema looks like.
+pub fn run(conn: &Connection) -> Result<(), AppError> {
+    let current_version = get_schema_version(conn)?;
+    let latest = latest_version();
+
+    if current_version > latest {
+        return Err(AppError::SchemaTooNew { version: current_version, supported: latest });
+    }
+
+    if current_version == latest {
+        println!("Database is up to date (version {})", current_version);
+        return Ok(());
+    }
+
+    println!("Applying database migrations from version {} to {}", current_version, latest);
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        println!("  {:>3}: {}", migration.version, migration.description);
+        let tx = conn.unchecked_transaction()?;
+        let violations = foreign_key_violations(&tx)?;
+        (migration.up)(&tx)?;
+        let added = foreign_key_violations(&tx)?
+            .into_iter()
+            .find(|(table, count)| violations.get(table).is_none_or(|before| count > before));
+        if let Some((table, _)) = added {
+            return Err(Error::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_CONSTRAINT_FOREIGNKEY),
+                Some(format!("Migration {} left rows in {} pointing at missing rows", migration.version, table)),
+            )
+            .into());
+        }
+        set_schema_version(&tx, migration.version)?;
+        tx.commit()?;
+        remove_queued_files(conn)?;
+    }
+
+    println!("Database migrations completed");
+    Ok(())
+}
+
+// Migrations run with foreign keys off, so SQLite won't stop one from leaving
+// dangling references behind; this is checked before committing instead.
+// Databases from before the orphan cleanup in version 8 may already have
+// some, so only violations a migration adds count against it. They're counted
+// per table since rebuilding one doesn't keep its rowids.
+fn foreign_key_violations(conn: &Connection) -> Result<HashMap<String, usize>> {
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let mut rows = stmt.query([])?;
+    let mut violations = HashMap::new();
+    while let Some(row) = rows.next()? {
+        *violations.entry(row.get(0)?).or_insert(0) += 1;
+    }
+    Ok(violations)
+}
+
+// Files can't be deleted inside a migration, which may still roll back and
+// leave rows pointing at them. Migrations queue the paths returned by `select`
+// in a temporary table instead, which rolls back along with everything else,
+// and they're removed once the migration has committed. Returns how many.
+fn queue_file_removals(conn: &Connection, select: &str) -> Result<usize> {
+    conn.execute_batch("CREATE TEMP TABLE IF NOT EXISTS pending_file_removals (file_path TEXT NOT NULL)")?;
+    conn.execute(&format!("INSERT INTO temp.pending_file_removals (file_path) {}", select), [])
+}
+
+fn remove_queued_files(conn: &Connection) -> Result<()> {
+    let queued = conn.query_row(
+        "SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'pending_file_removals'",
+        [],
+        |row| row.get::<_, i64>(0),
+    )?;
+    if queued == 0 {
+        return Ok(());
+    }
+
+    let file_paths: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT file_path FROM temp.pending_file_removals")?;
+        let paths = stmt.query_map([], |row| row.get(0))?;
+        paths.collect::<Result<_>>()?
+    };
+    conn.execute_batch("DROP TABLE temp.pending_file_removals")?;
+
+    for file_path in &file_paths {
+        if Path::new(file_path).exists() {
+            if let Err(e) = fs::remove_file(file_path) {
+                println!("Failed to remove orphaned attachment {}: {}", file_path, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn create_projects_and_steps(conn: &Connection) -> Result<()> {
+    // IF NOT EXISTS: databases created before schema versioning already have these tables
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS projects (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            gradient TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS steps (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            description TEXT,
+            order_index INTEGER NOT NULL,
+            completed INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );"
+    )
+}
+
+fn add_project_current_step(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE projects ADD COLUMN current_step_id TEXT", [])?;
+    Ok(())
+}
+
+fn create_notes(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS notes (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            plain_text TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_step_plain_text(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE steps ADD COLUMN plain_text TEXT", [])?;
+    Ok(())
+}
+
+fn create_image_attachments(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS image_attachments (
+            id TEXT PRIMARY KEY,
+            file_path TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            content_id TEXT NOT NULL,
+            content_type_enum TEXT NOT NULL CHECK (content_type_enum IN ('step', 'note', 'project_description')),
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_note_is_important(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE notes ADD COLUMN is_important INTEGER DEFAULT 0", [])?;
+    Ok(())
+}
+
+// Full-text index over project names/descriptions, step titles/plain text
+// and note titles/plain text. Triggers keep it in sync with the source tables.
+fn create_search_index(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE search_index USING fts5(
+            kind UNINDEXED,
+            item_id UNINDEXED,
+            project_id UNINDEXED,
+            title,
+            body,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER projects_search_insert AFTER INSERT ON projects BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('project', new.id, new.id, new.name, COALESCE(new.description, ''));
+        END;
+        CREATE TRIGGER projects_search_update AFTER UPDATE OF name, description ON projects BEGIN
+            UPDATE search_index SET title = new.name, body = COALESCE(new.description, '')
+            WHERE kind = 'project' AND item_id = old.id;
+        END;
+        CREATE TRIGGER projects_search_delete AFTER DELETE ON projects BEGIN
+            DELETE FROM search_index WHERE kind = 'project' AND item_id = old.id;
+        END;
+
+        CREATE TRIGGER steps_search_insert AFTER INSERT ON steps BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('step', new.id, new.project_id, new.title, COALESCE(new.plain_text, ''));
+        END;
+        CREATE TRIGGER steps_search_update AFTER UPDATE OF project_id, title, plain_text ON steps BEGIN
+            UPDATE search_index SET project_id = new.project_id, title = new.title, body = COALESCE(new.plain_text, '')
+            WHERE kind = 'step' AND item_id = old.id;
+        END;
+        CREATE TRIGGER steps_search_delete AFTER DELETE ON steps BEGIN
+            DELETE FROM search_index WHERE kind = 'step' AND item_id = old.id;
+        END;
+
+        CREATE TRIGGER notes_search_insert AFTER INSERT ON notes BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('note', new.id, new.project_id, new.title, COALESCE(new.plain_text, ''));
+        END;
+        CREATE TRIGGER notes_search_update AFTER UPDATE OF project_id, title, plain_text ON notes BEGIN
+            UPDATE search_index SET project_id = new.project_id, title = new.title, body = COALESCE(new.plain_text, '')
+            WHERE kind = 'note' AND item_id = old.id;
+        END;
+        CREATE TRIGGER notes_search_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM search_index WHERE kind = 'note' AND item_id = old.id;
+        END;
+
+        INSERT INTO search_index (kind, item_id, project_id, title, body)
+            SELECT 'project', id, id, name, COALESCE(description, '') FROM projects;
+        INSERT INTO search_index (kind, item_id, project_id, title, body)
+            SELECT 'step', id, project_id, title, COALESCE(plain_text, '') FROM steps;
+        INSERT INTO search_index (kind, item_id, project_id, title, body)
+            SELECT 'note', id, project_id, title, COALESCE(plain_text, '') FROM notes;"
+    )
+}
+
+// Attachments reference their owner through `content_id`/`content_type_enum`,
+// which can't be a foreign key, so triggers do the cascading instead. Rows
+// orphaned before foreign keys were enforced are removed along with their files.
+fn cascade_attachments_and_repair_orphans(conn: &Connection) -> Result<()> {
+    // The triggers are created last so they don't delete the orphaned steps' and
+    // notes' attachment rows before their files are queued for removal
+    conn.execute_batch(
+        "DELETE FROM steps WHERE project_id NOT IN (SELECT id FROM projects);
+        DELETE FROM notes WHERE project_id NOT IN (SELECT id FROM projects);"
+    )?;
+
+    let orphan_condition = "(content_type_enum = 'project_description' AND content_id NOT IN (SELECT id FROM projects))
+         OR (content_type_enum = 'step' AND content_id NOT IN (SELECT id FROM steps))
+         OR (content_type_enum = 'note' AND content_id NOT IN (SELECT id FROM notes))";
+
+    let orphaned = queue_file_removals(
+        conn,
+        &format!("SELECT file_path FROM image_attachments WHERE {}", orphan_condition),
+    )?;
+
+    conn.execute(
+        &format!("DELETE FROM image_attachments WHERE {}", orphan_condition),
+        [],
+    )?;
+    println!("Removed {} orphaned image attachments", orphaned);
+
+    conn.execute_batch(
+        "CREATE TRIGGER projects_attachments_delete AFTER DELETE ON projects BEGIN
+            DELETE FROM image_attachments
+            WHERE content_type_enum = 'project_description' AND content_id = old.id;
+        END;
+        CREATE TRIGGER steps_attachments_delete AFTER DELETE ON steps BEGIN
+            DELETE FROM image_attachments
+            WHERE content_type_enum = 'step' AND content_id = old.id;
+        END;
+        CREATE TRIGGER notes_attachments_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM image_attachments
+            WHERE content_type_enum = 'note' AND content_id = old.id;
+        END;"
+    )
+}
+
+fn add_trash_and_settings(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE projects ADD COLUMN deleted_at TEXT;
+        ALTER TABLE steps ADD COLUMN deleted_at TEXT;
+        ALTER TABLE notes ADD COLUMN deleted_at TEXT;
+        ALTER TABLE image_attachments ADD COLUMN deleted_at TEXT;
+
+        CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+
+        -- Trashed items drop out of search results and come back on restore
+        CREATE TRIGGER projects_search_trash AFTER UPDATE OF deleted_at ON projects
+        WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+            DELETE FROM search_index WHERE kind = 'project' AND item_id = old.id;
+        END;
+        CREATE TRIGGER projects_search_restore AFTER UPDATE OF deleted_at ON projects
+        WHEN old.deleted_at IS NOT NULL AND new.deleted_at IS NULL BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('project', new.id, new.id, new.name, COALESCE(new.description, ''));
+        END;
+
+        CREATE TRIGGER steps_search_trash AFTER UPDATE OF deleted_at ON steps
+        WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+            DELETE FROM search_index WHERE kind = 'step' AND item_id = old.id;
+        END;
+        CREATE TRIGGER steps_search_restore AFTER UPDATE OF deleted_at ON steps
+        WHEN old.deleted_at IS NOT NULL AND new.deleted_at IS NULL BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('step', new.id, new.project_id, new.title, COALESCE(new.plain_text, ''));
+        END;
+
+        CREATE TRIGGER notes_search_trash AFTER UPDATE OF deleted_at ON notes
+        WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+            DELETE FROM search_index WHERE kind = 'note' AND item_id = old.id;
+        END;
+        CREATE TRIGGER notes_search_restore AFTER UPDATE OF deleted_at ON notes
+        WHEN old.deleted_at IS NOT NULL AND new.deleted_at IS NULL BEGIN
+            INSERT INTO search_index (kind, item_id, project_id, title, body)
+            VALUES ('note', new.id, new.project_id, new.title, COALESCE(new.plain_text, ''));
+        END;"
+    )
+}
+
+fn create_revisions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE revisions (
+            id TEXT PRIMARY KEY,
+            item_type TEXT NOT NULL CHECK (item_type IN ('step', 'note')),
+            item_id TEXT NOT NULL,
+            content TEXT NOT NULL,
+            plain_text TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        CREATE INDEX idx_revisions_item ON revisions (item_type, item_id, created_at);
+
+        CREATE TRIGGER steps_revisions_delete AFTER DELETE ON steps BEGIN
+            DELETE FROM revisions WHERE item_type = 'step' AND item_id = old.id;
+        END;
+        CREATE TRIGGER notes_revisions_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM revisions WHERE item_type = 'note' AND item_id = old.id;
+        END;"
+    )
+}
+
+// Attachments were written to `<data dir>/attachmentSources/images/`, so any
+// absolute path containing that layout is rewritten to the part after it.
+// Paths outside the known layout are left absolute and still resolve as-is.
+fn make_attachment_paths_relative(conn: &Connection) -> Result<()> {
+    let mut rewritten = 0;
+    for root in ["/attachmentSources/images/", "\\attachmentSources\\images\\"] {
+        rewritten += conn.execute(
+            "UPDATE image_attachments
+             SET file_path = substr(file_path, instr(file_path, ?1) + length(?1))
+             WHERE instr(file_path, ?1) > 0",
+            [root],
+        )?;
+    }
+    println!("Rewrote {} attachment paths as relative", rewritten);
+    Ok(())
+}
+
+// Thumbnail files live under the attachments root as well, so `file_path` is
+// relative to it just like the original's.
+fn create_image_thumbnails(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE image_thumbnails (
+            attachment_id TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            file_path TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (attachment_id, size),
+            FOREIGN KEY (attachment_id) REFERENCES image_attachments(id) ON DELETE CASCADE
+        );"
+    )
+}
+
+// Existing rows are filled in from the files by a background pass at startup,
+// since the attachments root isn't known here.
+fn add_image_metadata(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE image_attachments ADD COLUMN width INTEGER;
+        ALTER TABLE image_attachments ADD COLUMN height INTEGER;
+        ALTER TABLE image_attachments ADD COLUMN byte_size INTEGER;"
+    )
+}
+
+// Attachment files are stored once per SHA-256 hash and shared by every row
+// with the same content. `ref_count` is kept by triggers so that every way a
+// row disappears, including the cascades from steps and notes, releases it.
+// Existing files are hashed into the store at startup, where the attachments
+// root is known.
+fn create_attachment_blobs(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE attachment_blobs (
+            hash TEXT PRIMARY KEY,
+            file_path TEXT NOT NULL,
+            byte_size INTEGER NOT NULL,
+            ref_count INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        );
+        ALTER TABLE image_attachments ADD COLUMN blob_hash TEXT REFERENCES attachment_blobs(hash);
+        CREATE INDEX idx_image_attachments_blob ON image_attachments (blob_hash);
+
+        CREATE TRIGGER image_attachments_blob_insert AFTER INSERT ON image_attachments
+        WHEN new.blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.blob_hash;
+        END;
+        CREATE TRIGGER image_attachments_blob_delete AFTER DELETE ON image_attachments
+        WHEN old.blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.blob_hash;
+        END;
+        CREATE TRIGGER image_attachments_blob_update AFTER UPDATE OF blob_hash ON image_attachments BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.blob_hash;
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.blob_hash;
+        END;"
+    )
+}
+
+// A kept original is a blob like any other and holds its own reference.
+fn add_image_transform_tracking(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE image_attachments ADD COLUMN transformed INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE image_attachments ADD COLUMN original_blob_hash TEXT REFERENCES attachment_blobs(hash);
+
+        CREATE TRIGGER image_attachments_original_insert AFTER INSERT ON image_attachments
+        WHEN new.original_blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.original_blob_hash;
+        END;
+        CREATE TRIGGER image_attachments_original_delete AFTER DELETE ON image_attachments
+        WHEN old.original_blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.original_blob_hash;
+        END;
+        CREATE TRIGGER image_attachments_original_update AFTER UPDATE OF original_blob_hash ON image_attachments BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.original_blob_hash;
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.original_blob_hash;
+        END;"
+    )
+}
+
+// Renaming rewrites the references in triggers, indexes and foreign keys, so
+// the cascades and reference counts set up for image_attachments carry over.
+// Every existing row is an image.
+fn generalize_attachments(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE image_attachments RENAME TO attachments;
+        ALTER TABLE attachments ADD COLUMN kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'file'));"
+    )
+}
+
+// The owner check is a trigger for the same reason cascading is: one column
+// can't reference three tables. Widening the owner CHECK for `project` needs a
+// rebuild, so the table is recreated along with its index and triggers. Runs
+// with foreign keys off, so rows pointing at thumbnails and blobs survive.
+fn validate_attachment_owners(conn: &Connection) -> Result<()> {
+    let owner_exists = "CASE new.content_type_enum
+                WHEN 'step' THEN EXISTS (SELECT 1 FROM steps WHERE id = new.content_id)
+                WHEN 'note' THEN EXISTS (SELECT 1 FROM notes WHERE id = new.content_id)
+                ELSE EXISTS (SELECT 1 FROM projects WHERE id = new.content_id)
+            END";
+
+    conn.execute_batch(&format!(
+        "CREATE TABLE attachments_new (
+            id TEXT PRIMARY KEY,
+            file_path TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            content_id TEXT NOT NULL,
+            content_type_enum TEXT NOT NULL CHECK (content_type_enum IN ('project', 'project_description', 'step', 'note')),
+            created_at TEXT NOT NULL,
+            deleted_at TEXT,
+            width INTEGER,
+            height INTEGER,
+            byte_size INTEGER,
+            blob_hash TEXT REFERENCES attachment_blobs(hash),
+            transformed INTEGER NOT NULL DEFAULT 0,
+            original_blob_hash TEXT REFERENCES attachment_blobs(hash),
+            kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'file'))
+        );
+        INSERT INTO attachments_new (id, file_path, filename, content_type, content_id, content_type_enum, created_at,
+                deleted_at, width, height, byte_size, blob_hash, transformed, original_blob_hash, kind)
+            SELECT id, file_path, filename, content_type, content_id, content_type_enum, created_at,
+                deleted_at, width, height, byte_size, blob_hash, transformed, original_blob_hash, kind
+            FROM attachments;
+
+        DROP TRIGGER projects_attachments_delete;
+        DROP TRIGGER steps_attachments_delete;
+        DROP TRIGGER notes_attachments_delete;
+        DROP TABLE attachments;
+        ALTER TABLE attachments_new RENAME TO attachments;
+
+        CREATE INDEX idx_attachments_blob ON attachments (blob_hash);
+        CREATE INDEX idx_attachments_owner ON attachments (content_type_enum, content_id);
+
+        CREATE TRIGGER attachments_blob_insert AFTER INSERT ON attachments
+        WHEN new.blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.blob_hash;
+        END;
+        CREATE TRIGGER attachments_blob_delete AFTER DELETE ON attachments
+        WHEN old.blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.blob_hash;
+        END;
+        CREATE TRIGGER attachments_blob_update AFTER UPDATE OF blob_hash ON attachments BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.blob_hash;
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.blob_hash;
+        END;
+        CREATE TRIGGER attachments_original_insert AFTER INSERT ON attachments
+        WHEN new.original_blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.original_blob_hash;
+        END;
+        CREATE TRIGGER attachments_original_delete AFTER DELETE ON attachments
+        WHEN old.original_blob_hash IS NOT NULL BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.original_blob_hash;
+        END;
+        CREATE TRIGGER attachments_original_update AFTER UPDATE OF original_blob_hash ON attachments BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1 WHERE hash = old.original_blob_hash;
+            UPDATE attachment_blobs SET ref_count = ref_count + 1 WHERE hash = new.original_blob_hash;
+        END;
+
+        CREATE TRIGGER attachments_owner_insert BEFORE INSERT ON attachments
+        WHEN NOT ({owner_exists}) BEGIN
+            SELECT RAISE(ABORT, 'attachment owner does not exist');
+        END;
+        CREATE TRIGGER attachments_owner_update BEFORE UPDATE OF content_id, content_type_enum ON attachments
+        WHEN NOT ({owner_exists}) BEGIN
+            SELECT RAISE(ABORT, 'attachment owner does not exist');
+        END;
+
+        CREATE TRIGGER projects_attachments_delete AFTER DELETE ON projects BEGIN
+            DELETE FROM attachments
+            WHERE content_type_enum IN ('project', 'project_description') AND content_id = old.id;
+        END;
+        CREATE TRIGGER steps_attachments_delete AFTER DELETE ON steps BEGIN
+            DELETE FROM attachments
+            WHERE content_type_enum = 'step' AND content_id = old.id;
+        END;
+        CREATE TRIGGER notes_attachments_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM attachments
+            WHERE content_type_enum = 'note' AND content_id = old.id;
+        END;",
+        owner_exists = owner_exists,
+    ))
+}
+
+// Existing steps keep a NULL parent, so they become top-level steps and their
+// order_index is already scoped to their siblings. Purging a step removes the
+// steps nested under it.
+fn add_step_parent(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE steps ADD COLUMN parent_step_id TEXT REFERENCES steps(id) ON DELETE CASCADE;
+        CREATE INDEX idx_steps_parent ON steps (project_id, parent_step_id, order_index);",
+    )
+}
+
+fn create_step_dependencies(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE step_dependencies (
+            step_id TEXT NOT NULL REFERENCES steps(id) ON DELETE CASCADE,
+            depends_on_step_id TEXT NOT NULL REFERENCES steps(id) ON DELETE CASCADE,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (step_id, depends_on_step_id),
+            CHECK (step_id != depends_on_step_id)
+        );
+        CREATE INDEX idx_step_dependencies_depends_on ON step_dependencies (depends_on_step_id);",
+    )
+}
+
+// The global status set is seeded with fixed ids; projects can define their
+// own set instead. `completed` stays on steps, kept in step with the status.
+fn add_step_statuses(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE step_statuses (
+            id TEXT PRIMARY KEY,
+            project_id TEXT REFERENCES projects(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            order_index INTEGER NOT NULL,
+            counts_as_done INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX idx_step_statuses_project ON step_statuses (project_id, order_index);
+
+        INSERT INTO step_statuses (id, project_id, name, order_index, counts_as_done) VALUES
+            ('todo', NULL, 'To do', 0, 0),
+            ('in_progress', NULL, 'In progress', 1, 0),
+            ('blocked', NULL, 'Blocked', 2, 0),
+            ('in_review', NULL, 'In review', 3, 0),
+            ('done', NULL, 'Done', 4, 1),
+            ('skipped', NULL, 'Skipped', 5, 1);
+
+        ALTER TABLE steps ADD COLUMN status TEXT;
+        ALTER TABLE steps ADD COLUMN status_changed_at TEXT;
+        UPDATE steps SET
+            status = CASE WHEN completed = 1 THEN 'done' ELSE 'todo' END,
+            status_changed_at = CASE WHEN completed = 1 THEN updated_at ELSE created_at END;
+
+        CREATE TABLE step_status_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            step_id TEXT NOT NULL REFERENCES steps(id) ON DELETE CASCADE,
+            from_status TEXT,
+            to_status TEXT NOT NULL,
+            changed_at TEXT NOT NULL
+        );
+        CREATE INDEX idx_step_status_changes_step ON step_status_changes (step_id, changed_at);
+        INSERT INTO step_status_changes (step_id, from_status, to_status, changed_at)
+            SELECT id, NULL, status, status_changed_at FROM steps;",
+    )
+}
+
+// Dates are a day ("2024-05-01") or a UTC RFC 3339 time; see schedule.rs
+fn add_schedule_dates(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE projects ADD COLUMN start_date TEXT;
+        ALTER TABLE projects ADD COLUMN due_date TEXT;
+        ALTER TABLE steps ADD COLUMN start_date TEXT;
+        ALTER TABLE steps ADD COLUMN due_date TEXT;
+        CREATE INDEX idx_steps_due_date ON steps (due_date) WHERE due_date IS NOT NULL;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use std::path::PathBuf;
+
+    fn temp_db_path() -> PathBuf {
+        std::env::temp_dir().join(format!("migrations-{}.db", uuid::Uuid::new_v4()))
+    }
+
+    // Builds a database as an older build would have left it
+    fn migrate_to(conn: &Connection, version: i32) {
+        get_schema_version(conn).unwrap();
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+            (migration.up)(conn).unwrap();
+            set_schema_version(conn, migration.version).unwrap();
+        }
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    fn schema(conn: &Connection) -> Vec<(String, String, Option<String>)> {
+        let mut stmt = conn
+            .prepare("SELECT type, name, sql FROM sqlite_master ORDER BY type, name")
+            .unwrap();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        rows.collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn upgrading_from_1_matches_a_fresh_database() {
+        let upgraded_path = temp_db_path();
+        Connection::open(&upgraded_path)
+            .unwrap()
+            .execute_batch(include_str!("../tests/fixtures/schema_v1.sql"))
+            .unwrap();
+        let upgraded = Database::new(&upgraded_path).unwrap();
+        let fresh_path = temp_db_path();
+        drop(Database::new(&fresh_path).unwrap());
+
+        let project = upgraded.get_project("p1").unwrap();
+        assert_eq!(project.name, "Garden shed");
+        let steps = upgraded.get_steps_by_project("p1").unwrap();
+        assert_eq!(steps.iter().map(|step| step.id.as_str()).collect::<Vec<_>>(), ["s1", "s2"]);
+        assert!(steps[0].completed);
+        drop(upgraded);
+
+        let upgraded = Connection::open(&upgraded_path).unwrap();
+        let fresh = Connection::open(&fresh_path).unwrap();
+        assert_eq!(get_schema_version(&upgraded).unwrap(), latest_version());
+        assert_eq!(schema(&upgraded), schema(&fresh));
+        let _ = fs::remove_file(&upgraded_path);
+        let _ = fs::remove_file(&fresh_path);
+    }
+
+    #[test]
+    fn a_newer_schema_is_refused_untouched() {
+        let path = temp_db_path();
+        {
+            let conn = Connection::open(&path).unwrap();
+            get_schema_version(&conn).unwrap();
+            set_schema_version(&conn, latest_version() + 1).unwrap();
+        }
+
+        match Database::new(&path) {
+            Err(AppError::SchemaTooNew { version, supported }) => {
+                assert_eq!(version, latest_version() + 1);
+                assert_eq!(supported, latest_version());
+            }
+            Err(e) => panic!("expected SchemaTooNew, got {:?}", e),
+            Ok(_) => panic!("a newer schema was opened"),
+        }
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), latest_version() + 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sqlite_master WHERE name = 'projects'"), 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn upgrading_from_7_removes_files_of_orphaned_steps() {
+        let path = temp_db_path();
+        let file = std::env::temp_dir().join(format!("orphan-{}.png", uuid::Uuid::new_v4()));
+        fs::write(&file, b"png").unwrap();
+        {
+            let conn = Connection::open(&path).unwrap();
+            // Orphans could only be left behind while foreign keys were off
+            conn.execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+            migrate_to(&conn, 7);
+            conn.execute(
+                "INSERT INTO steps (id, project_id, title, description, order_index, completed, created_at, updated_at)
+                    VALUES ('s1', 'gone', 'Step', '', 0, 0, 'now', 'now')",
+                [],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO image_attachments (id, file_path, filename, content_type, content_id, content_type_enum, created_at)
+                    VALUES ('a1', ?1, 'a.png', 'image/png', 's1', 'step', 'now')",
+                [file.to_string_lossy()],
+            )
+            .unwrap();
+        }
+
+        drop(Database::new(&path).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM steps"), 0);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM attachments"), 0);
+        assert!(!file.exists());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn upgrading_from_16_keeps_thumbnails_and_blob_references() {
+        let path = temp_db_path();
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+            migrate_to(&conn, 16);
+            conn.execute_batch(
+                "INSERT INTO projects (id, name, description, created_at, updated_at, gradient)
+                    VALUES ('p1', 'Project', '', 'now', 'now', 'g');
+                INSERT INTO steps (id, project_id, title, description, order_index, completed, created_at, updated_at)
+                    VALUES ('s1', 'p1', 'Step', '', 0, 0, 'now', 'now');
+                INSERT INTO attachment_blobs (hash, file_path, byte_size, ref_count, created_at)
+                    VALUES ('abc', '/blobs/abc.png', 3, 0, 'now');
+                INSERT INTO attachments (id, file_path, filename, content_type, content_id, content_type_enum,
+                        created_at, blob_hash, kind)
+                    VALUES ('a1', '/blobs/abc.png', 'a.png', 'image/png', 's1', 'step', 'now', 'abc', 'image');
+                INSERT INTO image_thumbnails (attachment_id, size, file_path, content_type, width, height, created_at)
+                    VALUES ('a1', 256, '/thumbnails/a1-256.png', 'image/png', 256, 128, 'now');",
+            )
+            .unwrap();
+        }
+
+        drop(Database::new(&path).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), latest_version());
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM attachments"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM image_thumbnails WHERE attachment_id = 'a1'"), 1);
+        assert_eq!(count(&conn, "SELECT ref_count FROM attachment_blobs WHERE hash = 'abc'"), 1);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::database::{Step, StepDependency};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StepState {
+    // Waits on a step that isn't done, directly or through a parent step
+    Blocked,
+    Ready,
+    // Completed, or every step nested under it is done
+    Done,
+}
+
+// The computed state of each step and the steps left to do in an order that
+// respects their dependencies
+pub struct Plan {
+    pub states: HashMap<String, StepState>,
+    // Only steps without nested steps, since a parent is done through them.
+    // Ties are broken by tree order.
+    pub next: Vec<String>,
+}
+
+// A project's steps and dependencies by position in the step list. A step
+// waits on what it depends on and on what its parents depend on, and
+// depending on a parent step means waiting on every step nested under it.
+struct Graph<'a> {
+    steps: &'a [Step],
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    requires: Vec<Vec<usize>>,
+}
+
+impl<'a> Graph<'a> {
+    // `steps` must be in tree order, as `get_steps_by_project` returns them.
+    // Dependencies on steps that aren't listed, such as trashed ones, are ignored.
+    fn new(steps: &'a [Step], dependencies: &[StepDependency]) -> Graph<'a> {
+        let index: HashMap<&str, usize> = steps.iter().enumerate().map(|(i, step)| (step.id.as_str(), i)).collect();
+        let parent: Vec<Option<usize>> = steps
+            .iter()
+            .map(|step| step.parent_step_id.as_deref().and_then(|id| index.get(id).copied()))
+            .collect();
+
+        let mut children = vec![Vec::new(); steps.len()];
+        for (i, parent) in parent.iter().enumerate() {
+            if let Some(parent) = parent {
+                children[*parent].push(i);
+            }
+        }
+
+        let mut requires = vec![Vec::new(); steps.len()];
+        for dependency in dependencies {
+            if let (Some(&step), Some(&on)) = (
+                index.get(dependency.step_id.as_str()),
+                index.get(dependency.depends_on_step_id.as_str()),
+            ) {
+                requires[step].push(on);
+            }
+        }
+
+        Graph { steps, parent, children, requires }
+    }
+
+    fn is_leaf(&self, i: usize) -> bool {
+        self.children[i].is_empty()
+    }
+
+    // Parents come before their children in tree order, so walking backwards
+    // settles every child before its parent
+    fn done(&self) -> Vec<bool> {
+        let mut done = vec![false; self.steps.len()];
+        for i in (0..self.steps.len()).rev() {
+            done[i] = self.steps[i].completed || (!self.is_leaf(i) && self.children[i].iter().all(|&child| done[child]));
+        }
+        done
+    }
+
+    // What the step waits on, including through its parents
+    fn inherited_requirements(&self, i: usize) -> Vec<usize> {
+        let mut requirements = Vec::new();
+        let mut current = Some(i);
+        while let Some(step) = current {
+            requirements.extend(&self.requires[step]);
+            current = self.parent[step];
+        }
+        requirements
+    }
+
+    fn leaves_under(&self, i: usize, leaves: &mut Vec<usize>) {
+        if self.is_leaf(i) {
+            leaves.push(i);
+        }
+        for &child in &self.children[i] {
+            self.leaves_under(child, leaves);
+        }
+    }
+
+    // Orders the leaves for which `include` holds so that each comes after the
+    // included leaves it waits on, preferring tree order. Leaves caught in a
+    // cycle are left out.
+    fn order_leaves(&self, include: impl Fn(usize) -> bool) -> (Vec<usize>, usize) {
+        let candidates: Vec<usize> = (0..self.steps.len()).filter(|&i| self.is_leaf(i) && include(i)).collect();
+        let mut waiting_on = vec![0; self.steps.len()];
+        let mut unblocks: Vec<Vec<usize>> = vec![Vec::new(); self.steps.len()];
+        for &leaf in &candidates {
+            let mut prerequisites = Vec::new();
+            for requirement in self.inherited_requirements(leaf) {
+                self.leaves_under(requirement, &mut prerequisites);
+            }
+            prerequisites.sort_unstable();
+            prerequisites.dedup();
+            for prerequisite in prerequisites.into_iter().filter(|&p| include(p)) {
+                waiting_on[leaf] += 1;
+                unblocks[prerequisite].push(leaf);
+            }
+        }
+
+        let mut available: BTreeSet<usize> = candidates.iter().copied().filter(|&leaf| waiting_on[leaf] == 0).collect();
+        let mut ordered = Vec::with_capacity(candidates.len());
+        while let Some(leaf) = available.pop_first() {
+            ordered.push(leaf);
+            for &next in &unblocks[leaf] {
+                waiting_on[next] -= 1;
+                if waiting_on[next] == 0 {
+                    available.insert(next);
+                }
+            }
+        }
+        let stuck = candidates.len() - ordered.len();
+        (ordered, stuck)
+    }
+}
+
+pub fn plan(steps: &[Step], dependencies: &[StepDependency]) -> Plan {
+    let graph = Graph::new(steps, dependencies);
+    let done = graph.done();
+
+    let states = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let state = if done[i] {
+                StepState::Done
+            } else if graph.inherited_requirements(i).iter().any(|&requirement| !done[requirement]) {
+                StepState::Blocked
+            } else {
+                StepState::Ready
+            };
+            (step.id.clone(), state)
+        })
+        .collect();
+
+    let (ordered, _) = graph.order_leaves(|i| !done[i]);
+    Plan {
+        states,
+        next: ordered.into_iter().map(|i| steps[i].id.clone()).collect(),
+    }
+}
+
+// Whether some steps could never become ready, regardless of what is done.
+// This includes a step depending on a step it is nested under, or the other
+// way round.
+pub fn has_cycle(steps: &[Step], dependencies: &[StepDependency]) -> bool {
+    let (_, stuck) = Graph::new(steps, dependencies).order_leaves(|_| true);
+    stuck > 0
+}
+
+// The step to make current once `completed_step_id` is done: preferably one it
+// was holding up, then one next to it under the same parent, and otherwise the
+// first step that can be done
+pub fn next_current_step(plan: &Plan, steps: &[Step], dependencies: &[StepDependency], completed_step_id: &str) -> Option<String> {
+    let ready: Vec<&String> = plan
+        .next
+        .iter()
+        .filter(|id| plan.states.get(id.as_str()) == Some(&StepState::Ready))
+        .collect();
+
+    let unblocked = ready.iter().copied().find(|id| {
+        dependencies
+            .iter()
+            .any(|dependency| dependency.step_id == **id && dependency.depends_on_step_id == completed_step_id)
+    });
+    if let Some(id) = unblocked {
+        return Some(id.clone());
+    }
+
+    let parent_of = |id: &str| steps.iter().find(|step| step.id == id).and_then(|step| step.parent_step_id.clone());
+    let completed_parent = parent_of(completed_step_id);
+    let sibling = ready.iter().copied().find(|id| parent_of(id) == completed_parent);
+    sibling.or(ready.first().copied()).cloned()
+}
@@ -0,0 +1,240 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use image::ImageFormat;
+use tauri::http::{header, Method, Request, Response, StatusCode, Uri};
+use tauri::{Manager, Runtime, UriSchemeContext};
+
+use crate::database::ATTACHMENT_KIND_IMAGE;
+use crate::error::AppError;
+use crate::AppState;
+
+// Attachments are served as `projectsteps-attachment://localhost/<id>` (or
+// `http://projectsteps-attachment.localhost/<id>` on Windows); the frontend
+// builds these URLs with `convertFileSrc(id, 'projectsteps-attachment')`.
+// Adding `?size=<px>` serves the closest thumbnail instead of the image, and
+// `?original=1` the bytes as uploaded if they were kept. Non-image files are
+// served the same way, sandboxed so they can't script the app.
+pub const ATTACHMENT_SCHEME: &str = "projectsteps-attachment";
+
+// Attachment files never change once written, so the webview may cache them
+const CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
+// Protocol responses are built in memory, so a range request gets at most this
+// much back and media elements ask for the rest as they play. Requests without a
+// Range header always get the whole file.
+const MAX_BODY_BYTES: u64 = 32 * 1024 * 1024;
+
+struct ResolvedAttachment {
+    path: PathBuf,
+    content_type: String,
+    etag: String,
+    // Non-image files may be HTML or similar, which must not run as part of the app
+    sandboxed: bool,
+}
+
+pub fn handle_attachment_request<R: Runtime>(
+    ctx: UriSchemeContext<'_, R>,
+    request: Request<Vec<u8>>,
+) -> Response<Vec<u8>> {
+    let state = ctx.app_handle().state::<AppState>();
+    serve_attachment(&state, &request).unwrap_or_else(|e| error_response(&e))
+}
+
+// The id is the first path segment. A bare `projectsteps-attachment://<id>`
+// puts it in the host instead.
+fn attachment_id(uri: &Uri) -> Option<String> {
+    let from_path = uri.path().trim_matches('/');
+    let id = if from_path.is_empty() {
+        uri.host().unwrap_or_default()
+    } else {
+        from_path.split('/').next().unwrap_or_default()
+    };
+    (!id.is_empty() && id != "localhost").then(|| id.to_string())
+}
+
+fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn resolve(state: &AppState, id: &str, size: Option<u32>, original: bool) -> Result<ResolvedAttachment, AppError> {
+    let db = state.db.lock().unwrap();
+    let storage = state.storage.lock().unwrap();
+
+    if original {
+        let attachment = db.get_attachment(id)?;
+        let blob = match attachment.original_blob_hash {
+            Some(hash) => db.get_blob(&hash)?,
+            None => None,
+        }
+        .ok_or_else(|| AppError::NotFound("The original of this image wasn't kept".to_string()))?;
+        let path = storage.checked_attachment_path(&blob.file_path)?;
+        let content_type = ImageFormat::from_path(&path)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream");
+
+        return Ok(ResolvedAttachment {
+            path,
+            content_type: content_type.to_string(),
+            etag: format!("\"{}-original-{}\"", id, blob.byte_size),
+            sandboxed: false,
+        });
+    }
+
+    // Falls back to the original while thumbnails haven't been generated yet
+    if let Some(size) = size {
+        let thumbnail = db
+            .get_thumbnail(id, size)
+            .map_err(AppError::from)
+            .and_then(|thumbnail| {
+                let path = storage.checked_attachment_path(&thumbnail.file_path)?;
+                Ok((thumbnail, path))
+            });
+        match thumbnail {
+            Ok((thumbnail, path)) => {
+                let len = fs::metadata(&path)?.len();
+                return Ok(ResolvedAttachment {
+                    path,
+                    content_type: thumbnail.content_type,
+                    etag: format!("\"{}-{}-{}\"", id, thumbnail.size, len),
+                    sandboxed: false,
+                });
+            }
+            Err(AppError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let attachment = db.get_attachment(id)?;
+    let path = storage.checked_attachment_path(&attachment.file_path)?;
+    let len = fs::metadata(&path)?.len();
+
+    Ok(ResolvedAttachment {
+        path,
+        content_type: attachment.content_type,
+        etag: format!("\"{}-{}\"", attachment.id, len),
+        sandboxed: attachment.kind != ATTACHMENT_KIND_IMAGE,
+    })
+}
+
+fn serve_attachment(state: &AppState, request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, AppError> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "GET, HEAD")
+            .body(Vec::new())
+            .unwrap());
+    }
+
+    let id = attachment_id(request.uri())
+        .ok_or_else(|| AppError::InvalidInput("Missing attachment id".to_string()))?;
+    // Locks are released before the file is read
+    let size = query_param(request.uri(), "size").and_then(|size| size.parse().ok());
+    let original = query_param(request.uri(), "original") == Some("1");
+    let attachment = resolve(state, &id, size, original)?;
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, &attachment.content_type)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .header(header::ETAG, &attachment.etag);
+    if attachment.sandboxed {
+        builder = builder.header(header::CONTENT_SECURITY_POLICY, "sandbox");
+    }
+
+    let if_none_match = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if if_none_match == Some(attachment.etag.as_str()) {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Vec::new()).unwrap());
+    }
+
+    let mut file = File::open(&attachment.path)?;
+    let len = file.metadata()?.len();
+    // Multiple ranges aren't supported; answering with the whole file is allowed
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.contains(','))
+        .map(|value| parse_range(value, len));
+
+    let (status, start, end, builder) = match range {
+        Some(Some((start, end))) => {
+            let end = end.min(start + MAX_BODY_BYTES - 1);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                start,
+                end,
+                builder.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len)),
+            )
+        }
+        Some(None) => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(Vec::new())
+                .unwrap());
+        }
+        None if len == 0 => (StatusCode::OK, 0, 0, builder),
+        None => (StatusCode::OK, 0, len - 1, builder),
+    };
+
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+    let mut body = Vec::new();
+    if request.method() == Method::GET && body_len > 0 {
+        file.seek(SeekFrom::Start(start))?;
+        body.reserve(body_len as usize);
+        file.take(body_len).read_to_end(&mut body)?;
+    }
+
+    Ok(builder
+        .status(status)
+        .header(header::CONTENT_LENGTH, body_len)
+        .body(body)
+        .unwrap())
+}
+
+// Parses a single `bytes=` range into inclusive offsets. Returns None when the
+// range is malformed or can't be satisfied.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = value.strip_prefix("bytes=")?.trim();
+    if len == 0 {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len - 1)),
+    };
+
+    (start <= end && start < len).then_some((start, end))
+}
+
+fn error_response(e: &AppError) -> Response<Vec<u8>> {
+    let status = match e {
+        AppError::NotFound(_) => StatusCode::NOT_FOUND,
+        AppError::AccessDenied(_) | AppError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+        AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        AppError::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(e.to_string().into_bytes())
+        .unwrap()
+}
@@ -0,0 +1,279 @@
+use chrono::{DateTime, Datelike, Days, Duration, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+// Start and due dates are stored either as a day ("2024-05-01"), which counts
+// in whatever zone the user is in, or as an instant in UTC RFC 3339
+const DAY_FORMAT: &str = "%Y-%m-%d";
+const LOCAL_TIME_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+// The zone days are counted in: the one set in the settings, or the system's
+#[derive(Debug, Clone, Copy)]
+pub enum Zone {
+    System,
+    Named(Tz),
+}
+
+impl Zone {
+    // An unknown name falls back to the system zone
+    pub fn from_name(name: Option<&str>) -> Zone {
+        match name.and_then(|name| name.parse::<Tz>().ok()) {
+            Some(tz) => Zone::Named(tz),
+            None => Zone::System,
+        }
+    }
+
+    pub fn day_of(&self, at: DateTime<Utc>) -> NaiveDate {
+        match self {
+            Zone::System => at.with_timezone(&Local).date_naive(),
+            Zone::Named(tz) => at.with_timezone(tz).date_naive(),
+        }
+    }
+
+    fn utc_of(&self, naive: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Zone::System => local_to_utc(&Local, naive),
+            Zone::Named(tz) => local_to_utc(tz, naive),
+        }
+    }
+
+    fn start_of(&self, day: NaiveDate) -> DateTime<Utc> {
+        self.utc_of(day.and_time(Default::default()))
+    }
+}
+
+// A wall time skipped by a clock change is taken from just after the change
+fn local_to_utc<Z: TimeZone>(zone: &Z, naive: NaiveDateTime) -> DateTime<Utc> {
+    zone.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| zone.from_local_datetime(&(naive + Duration::hours(1))).earliest())
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&naive))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleDate {
+    Day(NaiveDate),
+    At(DateTime<Utc>),
+}
+
+impl ScheduleDate {
+    // Takes a day, an RFC 3339 time, or a time without an offset, which is
+    // read in `zone`
+    pub fn parse(value: &str, zone: &Zone) -> Option<ScheduleDate> {
+        let value = value.trim();
+        if let Ok(day) = NaiveDate::parse_from_str(value, DAY_FORMAT) {
+            return Some(ScheduleDate::Day(day));
+        }
+        if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+            return Some(ScheduleDate::At(at.with_timezone(&Utc)));
+        }
+        LOCAL_TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+            .map(|naive| ScheduleDate::At(zone.utc_of(naive)))
+    }
+
+    pub fn to_stored(self) -> String {
+        match self {
+            ScheduleDate::Day(day) => day.format(DAY_FORMAT).to_string(),
+            ScheduleDate::At(at) => at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    pub fn is_all_day(self) -> bool {
+        matches!(self, ScheduleDate::Day(_))
+    }
+
+    pub fn day(self, zone: &Zone) -> NaiveDate {
+        match self {
+            ScheduleDate::Day(day) => day,
+            ScheduleDate::At(at) => zone.day_of(at),
+        }
+    }
+
+    pub fn begins(self, zone: &Zone) -> DateTime<Utc> {
+        match self {
+            ScheduleDate::Day(day) => zone.start_of(day),
+            ScheduleDate::At(at) => at,
+        }
+    }
+
+    // A day is over when the next one starts
+    pub fn ends(self, zone: &Zone) -> DateTime<Utc> {
+        match self {
+            ScheduleDate::Day(day) => day.checked_add_days(Days::new(1)).map_or(DateTime::<Utc>::MAX_UTC, |next| zone.start_of(next)),
+            ScheduleDate::At(at) => at,
+        }
+    }
+}
+
+// Validates a start and due date pair for storage. Blank values clear the date.
+pub fn normalize_dates(start: Option<&str>, due: Option<&str>, zone: &Zone) -> Result<(Option<String>, Option<String>), String> {
+    let parse = |value: Option<&str>, what: &str| -> Result<Option<ScheduleDate>, String> {
+        match value.map(str::trim).filter(|value| !value.is_empty()) {
+            Some(value) => ScheduleDate::parse(value, zone)
+                .map(Some)
+                .ok_or_else(|| format!("'{}' is not a valid {} date", value, what)),
+            None => Ok(None),
+        }
+    };
+    let start = parse(start, "start")?;
+    let due = parse(due, "due")?;
+    if let (Some(start), Some(due)) = (start, due) {
+        // A day ends as the next one begins
+        if start != due && start.begins(zone) >= due.ends(zone) {
+            return Err("The start date is after the due date".to_string());
+        }
+    }
+    Ok((start.map(ScheduleDate::to_stored), due.map(ScheduleDate::to_stored)))
+}
+
+// Orders by day, all-day dates ahead of times on the same day, with undated last
+pub fn due_order(date: Option<&str>, zone: &Zone) -> (bool, NaiveDate, bool, DateTime<Utc>) {
+    match date.and_then(|date| ScheduleDate::parse(date, zone)) {
+        Some(date) => (false, date.day(zone), !date.is_all_day(), date.begins(zone)),
+        None => (true, NaiveDate::MIN, false, DateTime::<Utc>::MIN_UTC),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DueWindow {
+    // Past due and not done
+    Overdue,
+    // Due today, whether or not the time has passed
+    Today,
+    // Due between Monday and Sunday of the current week
+    ThisWeek,
+}
+
+impl DueWindow {
+    fn contains(self, due: ScheduleDate, zone: &Zone, now: DateTime<Utc>) -> bool {
+        let today = zone.day_of(now);
+        match self {
+            DueWindow::Overdue => due.ends(zone) <= now,
+            DueWindow::Today => due.day(zone) == today,
+            DueWindow::ThisWeek => {
+                let monday = today - Days::new(today.weekday().num_days_from_monday() as u64);
+                let day = due.day(zone);
+                day >= monday && day < monday + Days::new(7)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AgendaItemKind {
+    Project,
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AgendaEvent {
+    Start,
+    Due,
+}
+
+// A project or step with a start or due date, as read from the database
+pub struct Dated {
+    pub kind: AgendaItemKind,
+    pub id: String,
+    pub project_id: String,
+    pub project_name: String,
+    pub title: String,
+    pub start_date: Option<String>,
+    pub due_date: Option<String>,
+    pub done: bool,
+}
+
+// Days as "YYYY-MM-DD", both included
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgendaRange {
+    pub from: String,
+    pub to: String,
+}
+
+// One start or due date of a project or step on the timeline
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgendaItem {
+    pub kind: AgendaItemKind,
+    pub event: AgendaEvent,
+    pub id: String,
+    #[serde(rename = "projectId")]
+    pub project_id: String,
+    #[serde(rename = "projectName")]
+    pub project_name: String,
+    pub title: String,
+    // As stored: a day or a UTC time
+    pub date: String,
+    // The day it falls on in the configured zone
+    pub day: String,
+    #[serde(rename = "allDay")]
+    pub all_day: bool,
+    pub done: bool,
+    pub overdue: bool,
+}
+
+fn agenda_item(dated: &Dated, event: AgendaEvent, date: ScheduleDate, zone: &Zone, now: DateTime<Utc>) -> AgendaItem {
+    AgendaItem {
+        kind: dated.kind,
+        event,
+        id: dated.id.clone(),
+        project_id: dated.project_id.clone(),
+        project_name: dated.project_name.clone(),
+        title: dated.title.clone(),
+        date: date.to_stored(),
+        day: date.day(zone).format(DAY_FORMAT).to_string(),
+        all_day: date.is_all_day(),
+        done: dated.done,
+        overdue: event == AgendaEvent::Due && !dated.done && date.ends(zone) <= now,
+    }
+}
+
+fn sort_agenda(items: &mut [AgendaItem], zone: &Zone) {
+    items.sort_by(|a, b| {
+        due_order(Some(&a.date), zone)
+            .cmp(&due_order(Some(&b.date), zone))
+            .then_with(|| a.project_name.cmp(&b.project_name))
+            .then_with(|| a.title.cmp(&b.title))
+    });
+}
+
+// Items not done whose due date falls in `window`, soonest first
+pub fn due_items(dated: &[Dated], window: DueWindow, zone: &Zone, now: DateTime<Utc>) -> Vec<AgendaItem> {
+    let mut items: Vec<AgendaItem> = dated
+        .iter()
+        .filter(|dated| !dated.done)
+        .filter_map(|dated| {
+            let due = ScheduleDate::parse(dated.due_date.as_deref()?, zone)?;
+            window.contains(due, zone, now).then(|| agenda_item(dated, AgendaEvent::Due, due, zone, now))
+        })
+        .collect();
+    sort_agenda(&mut items, zone);
+    items
+}
+
+// Every start and due date falling on a day from `from` to `to`, inclusive
+pub fn agenda(dated: &[Dated], from: NaiveDate, to: NaiveDate, zone: &Zone, now: DateTime<Utc>) -> Vec<AgendaItem> {
+    let mut items = Vec::new();
+    for dated in dated {
+        for (event, date) in [(AgendaEvent::Start, &dated.start_date), (AgendaEvent::Due, &dated.due_date)] {
+            let Some(date) = date.as_deref().and_then(|date| ScheduleDate::parse(date, zone)) else {
+                continue;
+            };
+            let day = date.day(zone);
+            if day >= from && day <= to {
+                items.push(agenda_item(dated, event, date, zone, now));
+            }
+        }
+    }
+    sort_agenda(&mut items, zone);
+    items
+}
+
+pub fn parse_day(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), DAY_FORMAT).ok()
+}
@@ -0,0 +1,423 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::database::Database;
+use crate::error::AppError;
+
+pub const DATA_DIR_FLAG: &str = "--data-dir";
+pub const DATA_DIR_ENV: &str = "PROJECTSTEPS_DATA_DIR";
+pub const DB_FILENAME: &str = "projectsteps.db";
+pub const CONFIG_FILENAME: &str = "config.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DataDirSource {
+    CliFlag,
+    Environment,
+    Setting,
+    Default,
+}
+
+// Settings that decide where the database lives, so they are kept in a file
+// next to the app config rather than in the database itself.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(rename = "dataDir")]
+    pub data_dir: Option<PathBuf>,
+}
+
+impl StorageConfig {
+    pub fn load(config_path: &Path) -> StorageConfig {
+        match fs::read_to_string(config_path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                println!("Ignoring unreadable config {}: {}", config_path.display(), e);
+                StorageConfig::default()
+            }),
+            Err(_) => StorageConfig::default(),
+        }
+    }
+
+    pub fn save(&self, config_path: &Path) -> Result<(), AppError> {
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        fs::write(config_path, contents)?;
+        Ok(())
+    }
+}
+
+// Where the database and attachments live. Both are derived from one data
+// directory so they can't end up in different places.
+#[derive(Debug, Clone)]
+pub struct StoragePaths {
+    pub data_dir: PathBuf,
+    pub source: DataDirSource,
+    pub config_path: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub checked: usize,
+    pub relinked: usize,
+    #[serde(rename = "missingIds")]
+    pub missing_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageInfo {
+    #[serde(rename = "dataDir")]
+    pub data_dir: String,
+    pub source: DataDirSource,
+    #[serde(rename = "databasePath")]
+    pub database_path: String,
+    #[serde(rename = "attachmentsDir")]
+    pub attachments_dir: String,
+    #[serde(rename = "backupsDir")]
+    pub backups_dir: String,
+    #[serde(rename = "configPath")]
+    pub config_path: String,
+}
+
+impl StoragePaths {
+    // Resolves the data directory in order of precedence: the `--data-dir`
+    // flag, the PROJECTSTEPS_DATA_DIR environment variable, the persisted
+    // setting, and finally the platform's app data directory.
+    pub fn resolve(args: &[String], env_dir: Option<String>, config_path: PathBuf, default_dir: PathBuf) -> StoragePaths {
+        let (data_dir, source) = if let Some(dir) = data_dir_from_args(args) {
+            (dir, DataDirSource::CliFlag)
+        } else if let Some(dir) = env_dir.filter(|dir| !dir.trim().is_empty()) {
+            (PathBuf::from(dir), DataDirSource::Environment)
+        } else if let Some(dir) = StorageConfig::load(&config_path).data_dir {
+            (dir, DataDirSource::Setting)
+        } else {
+            (default_dir, DataDirSource::Default)
+        };
+
+        StoragePaths {
+            data_dir: std::path::absolute(&data_dir).unwrap_or(data_dir),
+            source,
+            config_path,
+        }
+    }
+
+    pub fn db_path(&self) -> PathBuf {
+        self.data_dir.join(DB_FILENAME)
+    }
+
+    pub fn attachments_dir(&self) -> PathBuf {
+        self.data_dir.join("attachmentSources").join("images")
+    }
+
+    pub fn backups_dir(&self) -> PathBuf {
+        self.data_dir.join("backups")
+    }
+
+    // Attachment paths are stored relative to the attachments root. Absolute
+    // paths left over from before that change are returned as they are.
+    pub fn resolve_attachment_path(&self, stored: &str) -> PathBuf {
+        let path = Path::new(stored);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.attachments_dir().join(path)
+        }
+    }
+
+    // Resolves a stored attachment path to the file on disk, refusing anything
+    // that doesn't canonicalize to a location inside the attachments root, such
+    // as `..` components, symlinks pointing elsewhere, or legacy absolute paths
+    // outside the store (`repair_attachment_paths` copies those back in).
+    pub fn checked_attachment_path(&self, stored: &str) -> Result<PathBuf, AppError> {
+        let outside_store = || {
+            AppError::AccessDenied(format!(
+                "Attachment path {} is outside the attachment store",
+                stored
+            ))
+        };
+
+        let resolved = self.resolve_attachment_path(stored);
+        if resolved.components().any(|c| matches!(c, Component::ParentDir))
+            || !resolved.starts_with(self.attachments_dir())
+        {
+            return Err(outside_store());
+        }
+
+        let root = self.attachments_dir().canonicalize()?;
+        let path = resolved.canonicalize()?;
+        if path.starts_with(&root) && path != root {
+            Ok(path)
+        } else {
+            Err(outside_store())
+        }
+    }
+
+    pub fn info(&self) -> StorageInfo {
+        StorageInfo {
+            data_dir: self.data_dir.to_string_lossy().to_string(),
+            source: self.source,
+            database_path: self.db_path().to_string_lossy().to_string(),
+            attachments_dir: self.attachments_dir().to_string_lossy().to_string(),
+            backups_dir: self.backups_dir().to_string_lossy().to_string(),
+            config_path: self.config_path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+fn data_dir_from_args(args: &[String]) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(dir) = arg.strip_prefix(DATA_DIR_FLAG).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    None
+}
+
+fn copy_dir_all(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+// Moves the database and attachments to `new_dir` and persists it as the data
+// directory. Everything is copied first and the new database is opened before
+// anything old is removed, so a failure part-way leaves the current data intact.
+pub fn relocate(db: &mut Database, paths: &mut StoragePaths, new_dir: &Path) -> Result<(), AppError> {
+    if matches!(paths.source, DataDirSource::CliFlag | DataDirSource::Environment) {
+        return Err(AppError::InvalidInput(format!(
+            "The data directory is set by {} and can't be changed from the app",
+            if paths.source == DataDirSource::CliFlag { DATA_DIR_FLAG } else { DATA_DIR_ENV }
+        )));
+    }
+
+    let new_dir = std::path::absolute(new_dir)?;
+    if new_dir == paths.data_dir {
+        return Ok(());
+    }
+    if new_dir.starts_with(&paths.data_dir) {
+        return Err(AppError::InvalidInput(
+            "The new data directory can't be inside the current one".to_string(),
+        ));
+    }
+    if new_dir.exists() && fs::read_dir(&new_dir)?.next().is_some() {
+        return Err(AppError::InvalidInput(format!(
+            "{} is not empty",
+            new_dir.display()
+        )));
+    }
+
+    let new_paths = StoragePaths {
+        data_dir: new_dir.clone(),
+        source: DataDirSource::Setting,
+        config_path: paths.config_path.clone(),
+    };
+
+    let result = (|| -> Result<Database, AppError> {
+        fs::create_dir_all(&new_paths.data_dir)?;
+        db.copy_to(&new_paths.db_path())?;
+        if paths.attachments_dir().exists() {
+            copy_dir_all(&paths.attachments_dir(), &new_paths.attachments_dir())?;
+        }
+        if paths.backups_dir().exists() {
+            copy_dir_all(&paths.backups_dir(), &new_paths.backups_dir())?;
+        }
+
+        let new_db = Database::new(&new_paths.db_path())?;
+
+        let mut config = StorageConfig::load(&paths.config_path);
+        config.data_dir = Some(new_dir.clone());
+        config.save(&paths.config_path)?;
+        Ok(new_db)
+    })();
+
+    let new_db = match result {
+        Ok(new_db) => new_db,
+        Err(e) => {
+            let _ = fs::remove_file(new_paths.db_path());
+            let _ = fs::remove_dir_all(new_paths.data_dir.join("attachmentSources"));
+            let _ = fs::remove_dir_all(new_paths.backups_dir());
+            return Err(e);
+        }
+    };
+
+    let old_paths = std::mem::replace(paths, new_paths);
+    *db = new_db;
+
+    // The new location is live; leftovers here are only logged
+    if let Err(e) = fs::remove_file(old_paths.db_path()) {
+        println!("Failed to remove old database {}: {}", old_paths.db_path().display(), e);
+    }
+    if old_paths.attachments_dir().exists() {
+        if let Err(e) = fs::remove_dir_all(old_paths.attachments_dir()) {
+            println!("Failed to remove old attachments {}: {}", old_paths.attachments_dir().display(), e);
+        }
+    }
+    if old_paths.backups_dir().exists() {
+        if let Err(e) = fs::remove_dir_all(old_paths.backups_dir()) {
+            println!("Failed to remove old backups {}: {}", old_paths.backups_dir().display(), e);
+        }
+    }
+
+    println!("Data directory moved to {}", paths.data_dir.display());
+    Ok(())
+}
+
+fn index_files_by_name(dir: &Path, index: &mut HashMap<String, PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            index_files_by_name(&entry.path(), index)?;
+        } else {
+            index
+                .entry(entry.file_name().to_string_lossy().to_string())
+                .or_insert_with(|| entry.path());
+        }
+    }
+    Ok(())
+}
+
+// Re-links attachments whose file is missing by looking for their stored
+// file name under the attachments root and, if given, `search_dir`. Files found
+// outside the root are copied into it so every row ends up root-relative.
+pub fn repair_attachment_paths(db: &Database, paths: &StoragePaths, search_dir: Option<&Path>) -> Result<RepairReport, AppError> {
+    let mut index = HashMap::new();
+    let attachments_dir = paths.attachments_dir();
+    if attachments_dir.exists() {
+        index_files_by_name(&attachments_dir, &mut index)?;
+    }
+    if let Some(search_dir) = search_dir {
+        index_files_by_name(search_dir, &mut index)?;
+    }
+
+    let mut report = RepairReport::default();
+    for attachment in db.get_all_attachments()? {
+        report.checked += 1;
+        if paths.resolve_attachment_path(&attachment.file_path).exists() {
+            continue;
+        }
+
+        // Stored files are named uniquely (by hash for blobs), unlike `filename`
+        let stored_name = Path::new(&attachment.file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let found = match index.get(&stored_name) {
+            Some(found) => found,
+            None => {
+                report.missing_ids.push(attachment.id);
+                continue;
+            }
+        };
+
+        let relative = match found.strip_prefix(&attachments_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => {
+                // Relative paths keep their place in the store, such as `blobs/`
+                let relative = if Path::new(&attachment.file_path).is_absolute() {
+                    PathBuf::from(&stored_name)
+                } else {
+                    PathBuf::from(&attachment.file_path)
+                };
+                let target = attachments_dir.join(&relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(found, target)?;
+                relative
+            }
+        };
+
+        db.update_attachment_path(&attachment.id, &relative.to_string_lossy())?;
+        report.relinked += 1;
+    }
+
+    println!(
+        "Attachment repair: {} checked, {} relinked, {} missing",
+        report.checked,
+        report.relinked,
+        report.missing_ids.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> StoragePaths {
+        let data_dir = std::env::temp_dir().join(format!("storage-{}", uuid::Uuid::new_v4()));
+        let paths = StoragePaths {
+            config_path: data_dir.join(CONFIG_FILENAME),
+            data_dir,
+            source: DataDirSource::Default,
+        };
+        fs::create_dir_all(paths.attachments_dir()).unwrap();
+        fs::write(paths.attachments_dir().join("inside.png"), b"png").unwrap();
+        fs::write(paths.data_dir.join("outside.txt"), b"secret").unwrap();
+        paths
+    }
+
+    fn assert_denied(paths: &StoragePaths, stored: &str) {
+        match paths.checked_attachment_path(stored) {
+            Err(AppError::AccessDenied(_)) => {}
+            other => panic!("{} should be denied, got {:?}", stored, other),
+        }
+    }
+
+    #[test]
+    fn attachment_paths_inside_the_store_resolve() {
+        let paths = temp_store();
+        let resolved = paths.checked_attachment_path("inside.png").unwrap();
+        assert_eq!(resolved, paths.attachments_dir().join("inside.png").canonicalize().unwrap());
+        let _ = fs::remove_dir_all(&paths.data_dir);
+    }
+
+    #[test]
+    fn parent_components_are_denied() {
+        let paths = temp_store();
+        assert_denied(&paths, "../../outside.txt");
+        assert_denied(&paths, "nested/../../../outside.txt");
+        let _ = fs::remove_dir_all(&paths.data_dir);
+    }
+
+    #[test]
+    fn absolute_paths_outside_the_store_are_denied() {
+        let paths = temp_store();
+        assert_denied(&paths, &paths.data_dir.join("outside.txt").to_string_lossy());
+        let _ = fs::remove_dir_all(&paths.data_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_store_are_denied() {
+        let paths = temp_store();
+        std::os::unix::fs::symlink(paths.data_dir.join("outside.txt"), paths.attachments_dir().join("link.png")).unwrap();
+        assert_denied(&paths, "link.png");
+        let _ = fs::remove_dir_all(&paths.data_dir);
+    }
+
+    #[test]
+    fn the_store_root_itself_is_denied() {
+        let paths = temp_store();
+        assert_denied(&paths, "");
+        assert_denied(&paths, ".");
+        assert_denied(&paths, &paths.attachments_dir().to_string_lossy());
+        let _ = fs::remove_dir_all(&paths.data_dir);
+    }
+}
@@ -0,0 +1,109 @@
+use chrono::Utc;
+use image::{DynamicImage, ImageFormat};
+use std::fs;
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::database::{Attachment, Thumbnail};
+use crate::error::AppError;
+use crate::storage::StoragePaths;
+use crate::AppState;
+
+// Bounding boxes, in pixels, of the thumbnails generated for every image
+pub const THUMBNAIL_SIZES: &[u32] = &[128, 256, 512];
+
+// Subdirectory of the attachments root the thumbnails are written to
+const THUMBNAILS_DIR: &str = "thumbnails";
+
+// Renders every thumbnail size of an attachment. Images with an alpha channel
+// are kept as PNG and everything else becomes JPEG; an image already smaller
+// than a size is stored at its own dimensions rather than upscaled.
+pub fn generate(storage: &StoragePaths, attachment: &Attachment) -> Result<Vec<Thumbnail>, AppError> {
+    let source = storage.checked_attachment_path(&attachment.file_path)?;
+    let image = image::open(&source).map_err(|e| {
+        AppError::InvalidInput(format!("Can't decode image {}: {}", attachment.filename, e))
+    })?;
+
+    let (format, extension, content_type) = if image.color().has_alpha() {
+        (ImageFormat::Png, "png", "image/png")
+    } else {
+        (ImageFormat::Jpeg, "jpg", "image/jpeg")
+    };
+
+    fs::create_dir_all(storage.attachments_dir().join(THUMBNAILS_DIR))?;
+
+    let mut thumbnails = Vec::new();
+    for &size in THUMBNAIL_SIZES {
+        let resized = if image.width() <= size && image.height() <= size {
+            image.clone()
+        } else {
+            image.thumbnail(size, size)
+        };
+        let resized = match format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(resized.into_rgb8()),
+            _ => resized,
+        };
+
+        let file_path = format!("{}/{}_{}.{}", THUMBNAILS_DIR, attachment.id, size, extension);
+        resized
+            .save_with_format(storage.attachments_dir().join(&file_path), format)
+            .map_err(|e| AppError::Internal(format!("Failed to write thumbnail {}: {}", file_path, e)))?;
+
+        thumbnails.push(Thumbnail {
+            attachment_id: attachment.id.clone(),
+            size,
+            file_path,
+            content_type: content_type.to_string(),
+            width: resized.width(),
+            height: resized.height(),
+            created_at: Utc::now().to_rfc3339(),
+        });
+    }
+
+    Ok(thumbnails)
+}
+
+// Generates and records thumbnails for one attachment. The image is decoded
+// without holding any lock; if the attachment was deleted in the meantime the
+// insert fails on its foreign key, no row is kept and the files just written
+// are removed.
+pub fn generate_and_save(state: &AppState, attachment: &Attachment) -> Result<Vec<Thumbnail>, AppError> {
+    let storage = state.storage.lock().unwrap().clone();
+    let thumbnails = generate(&storage, attachment)?;
+
+    let db = state.db.lock().unwrap();
+    let saved = db.with_transaction(|| thumbnails.iter().try_for_each(|thumbnail| db.save_thumbnail(thumbnail)));
+    if let Err(e) = saved {
+        let file_paths: Vec<String> = thumbnails.into_iter().map(|t| t.file_path).collect();
+        crate::remove_attachment_files(&storage, &file_paths);
+        return Err(e.into());
+    }
+
+    Ok(thumbnails)
+}
+
+// Creates thumbnails for attachments uploaded before they were generated on
+// upload. Runs on its own thread so startup isn't held up by large images.
+pub fn spawn_backfill<R: Runtime>(app: AppHandle<R>) {
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let pending = match state.db.lock().unwrap().get_attachments_without_thumbnails() {
+            Ok(pending) => pending,
+            Err(e) => {
+                println!("Failed to list attachments for thumbnail backfill: {}", e);
+                return;
+            }
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut generated = 0;
+        for attachment in &pending {
+            match generate_and_save(&state, attachment) {
+                Ok(_) => generated += 1,
+                Err(e) => println!("Failed to generate thumbnails for {}: {}", attachment.id, e),
+            }
+        }
+        println!("Thumbnail backfill: {} of {} attachments", generated, pending.len());
+    });
+}
@@ -0,0 +1,36 @@
+-- A database as the first versioned build left it: schema version 1 with a
+-- project and its steps
+CREATE TABLE projects (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            gradient TEXT NOT NULL
+        );
+
+CREATE TABLE steps (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            description TEXT,
+            order_index INTEGER NOT NULL,
+            completed INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+        );
+
+CREATE TABLE schema_version (
+            version INTEGER PRIMARY KEY
+        );
+
+INSERT INTO schema_version (version) VALUES (1);
+
+INSERT INTO projects (id, name, description, created_at, updated_at, gradient)
+    VALUES ('p1', 'Garden shed', '<p>Build it before winter</p>', '2024-03-01T10:00:00+00:00', '2024-03-02T09:30:00+00:00', 'from-green-400 to-blue-500');
+
+INSERT INTO steps (id, project_id, title, description, order_index, completed, created_at, updated_at)
+    VALUES ('s1', 'p1', 'Pour the foundation', '<p>Concrete, <b>level</b></p>', 0, 1, '2024-03-01T10:05:00+00:00', '2024-03-02T09:30:00+00:00');
+INSERT INTO steps (id, project_id, title, description, order_index, completed, created_at, updated_at)
+    VALUES ('s2', 'p1', 'Frame the walls', '', 1, 0, '2024-03-01T10:06:00+00:00', '2024-03-01T10:06:00+00:00');
@@ -1,39 +1,11 @@
-import React, { useState, useEffect } from 'react'
-import { createImageThumbnail } from '../utils/imageUtils'
+import React, { useState } from 'react'
+import { getThumbnailUrl } from '../utils/imageUtils'
 import ThumbnailModal from './ThumbnailModal'
 
 const ImageThumbnails = ({ attachments, onDelete, compact = false }) => {
-  const [thumbnails, setThumbnails] = useState({})
+  const [failedThumbnails, setFailedThumbnails] = useState({})
   const [selectedImageIndex, setSelectedImageIndex] = useState(null)
 
-  useEffect(() => {
-    loadNewThumbnails()
-  }, [attachments])
-
-  const loadNewThumbnails = async () => {
-    // Only load thumbnails for attachments that don't already have them
-    const attachmentsToLoad = attachments.filter(attachment => 
-      !thumbnails[attachment.id]
-    )
-    
-    if (attachmentsToLoad.length === 0) {
-      return
-    }
-    
-    const newThumbnails = { ...thumbnails }
-    
-    for (const attachment of attachmentsToLoad) {
-      try {
-        const thumbnail = await createImageThumbnail(attachment)
-        newThumbnails[attachment.id] = thumbnail
-      } catch (error) {
-        console.error('Error creating thumbnail for', attachment.filename, error)
-      }
-    }
-    
-    setThumbnails(newThumbnails)
-  }
-
   const handleThumbnailClick = (index) => {
     setSelectedImageIndex(index)
   }
@@ -56,7 +28,7 @@ const ImageThumbnails = ({ attachments, onDelete, compact = false }) => {
       <div className="image-thumbnails relative z-20">
         <div className={`flex flex-wrap ${compact ? 'gap-1' : 'gap-2'}`}>
           {attachments.map((attachment, index) => {
-            const thumbnail = thumbnails[attachment.id]
+            const hasThumbnail = !failedThumbnails[attachment.id]
             
             return (
               <div
@@ -66,10 +38,12 @@ const ImageThumbnails = ({ attachments, onDelete, compact = false }) => {
                 onClick={() => handleThumbnailClick(index)}
               >
                 <div className={`${compact ? 'w-12 h-12' : 'w-20 h-20'} rounded-lg overflow-hidden border-2 border-gray-200 dark:border-gray-600 hover:border-cyan-500 transition-colors`}>
-                  {thumbnail ? (
+                  {hasThumbnail ? (
                     <img
-                      src={thumbnail.dataUrl}
+                      src={getThumbnailUrl(attachment.id, compact ? 128 : 256)}
                       alt={attachment.filename}
+                      loading="lazy"
+                      onError={() => setFailedThumbnails(prev => ({ ...prev, [attachment.id]: true }))}
                       className="w-full h-full object-cover"
                     />
                   ) : (
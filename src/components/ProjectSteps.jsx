@@ -4,7 +4,7 @@ import NoteModal from './NoteModal'
 import ContextMenu from './ContextMenu'
 import ConfirmationModal from './ConfirmationModal'
 import { DragDropContext, Droppable, Draggable } from '@hello-pangea/dnd'
-import { createStep, updateStep, updateProjectCurrentStep, deleteStep, getImportantNote, setImportantNote, createNote, updateNote, loadSteps } from '../utils/storage'
+import { createStep, updateStep, updateProjectCurrentStep, deleteStep, restoreItem, getImportantNote, setImportantNote, createNote, updateNote, loadSteps } from '../utils/storage'
 import { undoRedoSystem, DeleteStepCommand } from '../utils/undoRedoSystem'
 
 const ProjectSteps = ({ project, steps, onBack, onUpdateSteps, allSteps, onUpdateProject, onNavigateToNotes }) => {
@@ -167,7 +167,10 @@ const ProjectSteps = ({ project, steps, onBack, onUpdateSteps, allSteps, onUpdat
 
   const handleUpdateStep = async (updatedStep) => {
     try {
-      await updateStep(updatedStep)
+      const currentStepId = await updateStep(updatedStep)
+      if ((currentStepId || null) !== (project.currentStepId || null)) {
+        onUpdateProject({ ...project, currentStepId })
+      }
       const updatedLocalSteps = localSteps.map(s => 
         s.id === updatedStep.id ? updatedStep : s
       )
@@ -194,7 +197,7 @@ const ProjectSteps = ({ project, steps, onBack, onUpdateSteps, allSteps, onUpdat
       stepToDelete,
       project.id,
       deleteStep,
-      createStep
+      (step) => restoreItem('step', step.id)
     )
     
     try {
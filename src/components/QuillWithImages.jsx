@@ -61,7 +61,7 @@ const QuillWithImages = React.forwardRef(({
 
   const handleDeleteImage = async (attachment) => {
     try {
-      await deleteImageAttachment(attachment.id, attachment.filePath)
+      await deleteImageAttachment(attachment.id)
       setImageAttachments(prev => prev.filter(img => img.id !== attachment.id))
     } catch (error) {
       console.error('Error deleting image:', error)
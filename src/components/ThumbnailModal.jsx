@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react'
-import { convertImageToBase64 } from '../utils/imageUtils'
+import { getAttachmentUrl } from '../utils/imageUtils'
 
 const ThumbnailModal = ({ attachments, initialIndex, onClose, onDelete }) => {
   const [currentIndex, setCurrentIndex] = useState(initialIndex)
@@ -46,12 +46,7 @@ const ThumbnailModal = ({ attachments, initialIndex, onClose, onDelete }) => {
     const newImageDataUrls = {}
     
     for (const attachment of attachments) {
-      try {
-        const base64 = await convertImageToBase64(attachment.filePath)
-        newImageDataUrls[attachment.id] = `data:${attachment.contentType};base64,${base64}`
-      } catch (error) {
-        console.error('Error loading image:', attachment.filename, error)
-      }
+      newImageDataUrls[attachment.id] = getAttachmentUrl(attachment.id)
     }
     
     setImageDataUrls(newImageDataUrls)
@@ -155,6 +150,8 @@ const ThumbnailModal = ({ attachments, initialIndex, onClose, onDelete }) => {
             <img
               src={currentImageUrl}
               alt={currentAttachment.filename}
+              width={currentAttachment.width ?? undefined}
+              height={currentAttachment.height ?? undefined}
               className="max-w-full max-h-full object-contain rounded-lg shadow-2xl"
             />
           ) : (
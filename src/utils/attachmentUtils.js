@@ -0,0 +1,56 @@
+import { invoke } from '@tauri-apps/api/core';
+
+export { getAttachmentUrl } from './imageUtils';
+
+// Images uploaded here are still treated as images; anything else is stored as a plain file.
+// `contentTypeEnum` is 'project', 'project_description', 'step' or 'note', and the owner must exist.
+export const uploadAttachment = async (fileData, filename, contentType, contentId, contentTypeEnum) => {
+  try {
+    const fileArray = Array.from(new Uint8Array(fileData));
+    const result = await invoke('upload_attachment', {
+      fileData: fileArray,
+      filename,
+      contentType,
+      contentId,
+      contentTypeEnum
+    });
+    return result;
+  } catch (error) {
+    console.error('Error uploading attachment:', error);
+    throw error;
+  }
+};
+
+// `kind` is 'image', 'file', or omitted for both
+export const getAttachments = async (contentId, contentTypeEnum, kind = null) => {
+  try {
+    const result = await invoke('get_attachments', {
+      contentId,
+      contentTypeEnum,
+      kind
+    });
+    return result;
+  } catch (error) {
+    console.error('Error getting attachments:', error);
+    return [];
+  }
+};
+
+// Opens a copy in the system's default app; edits there aren't saved back
+export const openAttachment = async (attachmentId) => {
+  try {
+    await invoke('open_attachment', { attachmentId });
+  } catch (error) {
+    console.error('Error opening attachment:', error);
+    throw error;
+  }
+};
+
+export const deleteAttachment = async (attachmentId) => {
+  try {
+    await invoke('delete_attachment', { attachmentId });
+  } catch (error) {
+    console.error('Error deleting attachment:', error);
+    throw error;
+  }
+};
@@ -1,4 +1,31 @@
-import { invoke } from '@tauri-apps/api/core';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
+
+const ATTACHMENT_SCHEME = 'projectsteps-attachment';
+
+// Attachments are streamed by the backend's custom protocol instead of over IPC
+export const getAttachmentUrl = (attachmentId) => {
+  return convertFileSrc(attachmentId, ATTACHMENT_SCHEME);
+};
+
+// Thumbnails are generated on upload; the closest stored size is served
+export const getThumbnailUrl = (attachmentId, size = 256) => {
+  return `${getAttachmentUrl(attachmentId)}?size=${size}`;
+};
+
+// Only available when the original was kept for a transformed upload
+export const getOriginalImageUrl = (attachmentId) => {
+  return `${getAttachmentUrl(attachmentId)}?original=1`;
+};
+
+export const getThumbnail = async (attachmentId, size) => {
+  try {
+    const thumbnail = await invoke('get_thumbnail', { attachmentId, size });
+    return thumbnail;
+  } catch (error) {
+    console.error('Error getting thumbnail:', error);
+    throw error;
+  }
+};
 
 export const uploadImage = async (imageData, filename, contentType, contentId, contentTypeEnum) => {
   try {
@@ -34,11 +61,10 @@ export const getImageAttachments = async (contentId, contentTypeEnum) => {
   }
 };
 
-export const deleteImageAttachment = async (attachmentId, filePath) => {
+export const deleteImageAttachment = async (attachmentId) => {
   try {
     await invoke('delete_image_attachment', {
-      attachmentId,
-      filePath
+      attachmentId
     });
   } catch (error) {
     console.error('Error deleting image attachment:', error);
@@ -46,10 +72,10 @@ export const deleteImageAttachment = async (attachmentId, filePath) => {
   }
 };
 
-export const getImageFileData = async (filePath) => {
+export const getImageFileData = async (attachmentId) => {
   try {
     const result = await invoke('get_image_file_data', {
-      filePath
+      attachmentId
     });
     return result;
   } catch (error) {
@@ -58,21 +84,6 @@ export const getImageFileData = async (filePath) => {
   }
 };
 
-export const convertImageToBase64 = async (filePath) => {
-  try {
-    const imageData = await getImageFileData(filePath);
-    const uint8Array = new Uint8Array(imageData);
-    let binary = '';
-    for (let i = 0; i < uint8Array.byteLength; i++) {
-      binary += String.fromCharCode(uint8Array[i]);
-    }
-    return btoa(binary);
-  } catch (error) {
-    console.error('Error converting image to base64:', error);
-    throw error;
-  }
-};
-
 export const handleClipboardPaste = async (event, contentId, contentTypeEnum) => {
   const items = event.clipboardData?.items;
   if (!items) return null;
@@ -96,12 +107,9 @@ export const handleClipboardPaste = async (event, contentId, contentTypeEnum) =>
           contentTypeEnum
         );
         
-        const base64 = await convertImageToBase64(attachment.filePath);
-        const dataUrl = `data:${attachment.contentType};base64,${base64}`;
-        
         return {
           attachment,
-          dataUrl,
+          url: getAttachmentUrl(attachment.id),
           filename: attachment.filename
         };
       } catch (error) {
@@ -114,41 +122,6 @@ export const handleClipboardPaste = async (event, contentId, contentTypeEnum) =>
   return null;
 };
 
-export const createImageThumbnail = async (attachment, maxWidth = 150, maxHeight = 150) => {
-  try {
-    const base64 = await convertImageToBase64(attachment.filePath);
-    const dataUrl = `data:${attachment.contentType};base64,${base64}`;
-    
-    return new Promise((resolve) => {
-      const img = new Image();
-      img.onload = () => {
-        const canvas = document.createElement('canvas');
-        const ctx = canvas.getContext('2d');
-        
-        const ratio = Math.min(maxWidth / img.width, maxHeight / img.height);
-        const width = img.width * ratio;
-        const height = img.height * ratio;
-        
-        canvas.width = width;
-        canvas.height = height;
-        
-        ctx.drawImage(img, 0, 0, width, height);
-        
-        resolve({
-          dataUrl: canvas.toDataURL('image/jpeg', 0.8),
-          width,
-          height,
-          originalDataUrl: dataUrl
-        });
-      };
-      img.src = dataUrl;
-    });
-  } catch (error) {
-    console.error('Error creating thumbnail:', error);
-    throw error;
-  }
-};
-
 export const generateImageId = () => {
   return Date.now().toString(36) + Math.random().toString(36).substr(2);
-};
\ No newline at end of file
+};
@@ -49,9 +49,10 @@ export const deleteProject = async (projectId) => {
   }
 }
 
-export const loadSteps = async () => {
+// `sort` is 'tree' or 'dueDate'
+export const loadSteps = async (sort = 'tree') => {
   try {
-    const steps = await invoke('get_all_steps')
+    const steps = await invoke('get_all_steps', { sort })
     return steps.map(step => ({
       ...step,
       order: step.order_index,
@@ -75,7 +76,9 @@ export const saveSteps = async (steps) => {
       order_index: step.order,
       completed: step.completed || false,
       created_at: step.createdAt,
-      updated_at: step.updatedAt
+      updated_at: step.updatedAt,
+      startDate: step.startDate || null,
+      dueDate: step.dueDate || null
     }))
     
     await invoke('update_steps_batch', { steps: formattedSteps })
@@ -95,7 +98,9 @@ export const createStep = async (step) => {
       order_index: step.order,
       completed: step.completed || false,
       created_at: step.createdAt,
-      updated_at: step.updatedAt
+      updated_at: step.updatedAt,
+      startDate: step.startDate || null,
+      dueDate: step.dueDate || null
     }
     
     await invoke('create_step', { step: formattedStep })
@@ -115,16 +120,63 @@ export const updateStep = async (step) => {
       order_index: step.order,
       completed: step.completed || false,
       created_at: step.createdAt,
-      updated_at: step.updatedAt
+      updated_at: step.updatedAt,
+      startDate: step.startDate || null,
+      dueDate: step.dueDate || null
     }
     
-    await invoke('update_step', { step: formattedStep })
+    // The project's current step, which moves on when the current step is completed
+    const currentStepId = await invoke('update_step', { step: formattedStep })
+    return currentStepId
   } catch (error) {
     console.error('Error updating step:', error)
     throw error
   }
 }
 
+// Returns the project's current step, which moves on when the current step is completed
+export const setStepStatus = async (stepId, status) => {
+  try {
+    const currentStepId = await invoke('set_step_status', { stepId, status })
+    return currentStepId
+  } catch (error) {
+    console.error('Error setting step status:', error)
+    throw error
+  }
+}
+
+export const getStepStatusHistory = async (stepId) => {
+  try {
+    const changes = await invoke('get_step_status_history', { stepId })
+    return changes
+  } catch (error) {
+    console.error('Error getting step status history:', error)
+    throw error
+  }
+}
+
+// `projectId` null is the global set; a project without its own set uses it
+export const getStepStatuses = async (projectId = null) => {
+  try {
+    const statuses = await invoke('get_step_statuses', { projectId })
+    return statuses
+  } catch (error) {
+    console.error('Error getting step statuses:', error)
+    throw error
+  }
+}
+
+// An empty list puts a project back on the global set
+export const setStepStatuses = async (projectId, statuses) => {
+  try {
+    const saved = await invoke('set_step_statuses', { projectId, statuses })
+    return saved
+  } catch (error) {
+    console.error('Error setting step statuses:', error)
+    throw error
+  }
+}
+
 export const updateProjectCurrentStep = async (projectId, stepId) => {
   try {
     console.log('Calling update_project_current_step with:', { projectId: projectId, stepId: stepId })
@@ -138,10 +190,12 @@ export const updateProjectCurrentStep = async (projectId, stepId) => {
   }
 }
 
-export const deleteStep = async (stepId) => {
+// Steps nested under it are trashed with it unless `keepChildren` is set,
+// in which case they take its place
+export const deleteStep = async (stepId, keepChildren = false) => {
   try {
     console.log('Invoking delete_step Tauri command with ID:', stepId)
-    await invoke('delete_step', { stepId: stepId })
+    await invoke('delete_step', { stepId: stepId, keepChildren })
     console.log('Step deleted successfully via Tauri')
   } catch (error) {
     console.error('Error deleting step:', error)
@@ -149,6 +203,108 @@ export const deleteStep = async (stepId) => {
   }
 }
 
+export const getStepTree = async (projectId) => {
+  try {
+    const tree = await invoke('get_step_tree', { projectId })
+    return tree
+  } catch (error) {
+    console.error('Error getting step tree:', error)
+    throw error
+  }
+}
+
+// Moves the step with everything nested under it; `parentStepId` null means top level
+export const moveStep = async (stepId, parentStepId, index) => {
+  try {
+    await invoke('move_step', { stepId, parentStepId, index })
+  } catch (error) {
+    console.error('Error moving step:', error)
+    throw error
+  }
+}
+
+export const getStepDependencies = async (projectId) => {
+  try {
+    const dependencies = await invoke('get_step_dependencies', { projectId })
+    return dependencies
+  } catch (error) {
+    console.error('Error getting step dependencies:', error)
+    throw error
+  }
+}
+
+// Fails when the dependency would leave steps waiting on each other
+export const addStepDependency = async (stepId, dependsOnStepId) => {
+  try {
+    await invoke('add_step_dependency', { stepId, dependsOnStepId })
+  } catch (error) {
+    console.error('Error adding step dependency:', error)
+    throw error
+  }
+}
+
+export const removeStepDependency = async (stepId, dependsOnStepId) => {
+  try {
+    await invoke('remove_step_dependency', { stepId, dependsOnStepId })
+  } catch (error) {
+    console.error('Error removing step dependency:', error)
+    throw error
+  }
+}
+
+// Steps left to do in an order that respects their dependencies, each with its state
+export const getNextSteps = async (projectId) => {
+  try {
+    const steps = await invoke('get_next_steps', { projectId })
+    return steps
+  } catch (error) {
+    console.error('Error getting next steps:', error)
+    throw error
+  }
+}
+
+// `window` is 'overdue', 'today' or 'thisWeek'; covers projects and steps across all projects
+export const getDueItems = async (window) => {
+  try {
+    const items = await invoke('get_due_items', { window })
+    return items
+  } catch (error) {
+    console.error('Error getting due items:', error)
+    throw error
+  }
+}
+
+// `from` and `to` are YYYY-MM-DD days, both included
+export const getAgenda = async (from, to) => {
+  try {
+    const items = await invoke('get_agenda', { range: { from, to } })
+    return items
+  } catch (error) {
+    console.error('Error getting agenda:', error)
+    throw error
+  }
+}
+
+// null means days follow the system's time zone
+export const getTimeZone = async () => {
+  try {
+    const timeZone = await invoke('get_time_zone')
+    return timeZone
+  } catch (error) {
+    console.error('Error getting time zone:', error)
+    throw error
+  }
+}
+
+export const setTimeZone = async (timeZone) => {
+  try {
+    await invoke('set_time_zone', { timeZone })
+  } catch (error) {
+    console.error('Error setting time zone:', error)
+    throw error
+  }
+}
+
 // Notes CRUD operations
 export const loadNotesByProject = async (projectId) => {
   try {
@@ -204,4 +360,267 @@ export const setImportantNote = async (projectId, noteId) => {
     console.error('Error setting important note:', error)
     throw error
   }
-}
\ No newline at end of file
+}
+export const searchEverything = async (query, filters = {}) => {
+  try {
+    const hits = await invoke('search_everything', { query, filters })
+    return hits
+  } catch (error) {
+    console.error('Error searching:', error)
+    return []
+  }
+}
+
+// Trash operations
+export const listTrash = async () => {
+  try {
+    const items = await invoke('list_trash')
+    return items
+  } catch (error) {
+    console.error('Error loading trash:', error)
+    return []
+  }
+}
+
+export const restoreItem = async (kind, id) => {
+  try {
+    await invoke('restore_item', { kind, id })
+  } catch (error) {
+    console.error('Error restoring item:', error)
+    throw error
+  }
+}
+
+export const purgeItem = async (kind, id) => {
+  try {
+    await invoke('purge_item', { kind, id })
+  } catch (error) {
+    console.error('Error purging item:', error)
+    throw error
+  }
+}
+
+// Revision history
+export const listRevisions = async (itemType, itemId) => {
+  try {
+    const revisions = await invoke('list_revisions', { itemType, itemId })
+    return revisions
+  } catch (error) {
+    console.error('Error loading revisions:', error)
+    return []
+  }
+}
+
+export const restoreRevision = async (revisionId) => {
+  try {
+    await invoke('restore_revision', { revisionId })
+  } catch (error) {
+    console.error('Error restoring revision:', error)
+    throw error
+  }
+}
+
+export const diffRevisions = async (fromRevisionId, toRevisionId = null) => {
+  try {
+    const diff = await invoke('diff_revisions', { fromRevisionId, toRevisionId })
+    return diff
+  } catch (error) {
+    console.error('Error diffing revisions:', error)
+    throw error
+  }
+}
+
+// Data directory
+export const getStorageInfo = async () => {
+  try {
+    const info = await invoke('get_storage_info')
+    return info
+  } catch (error) {
+    console.error('Error getting storage info:', error)
+    throw error
+  }
+}
+
+export const relocateDataDir = async (newDir) => {
+  try {
+    const info = await invoke('relocate_data_dir', { newDir })
+    return info
+  } catch (error) {
+    console.error('Error moving data directory:', error)
+    throw error
+  }
+}
+
+export const repairAttachmentPaths = async (searchDir = null) => {
+  try {
+    const report = await invoke('repair_attachment_paths', { searchDir })
+    return report
+  } catch (error) {
+    console.error('Error repairing attachment paths:', error)
+    throw error
+  }
+}
+
+export const getAttachmentStorageReport = async () => {
+  try {
+    const report = await invoke('get_attachment_storage_report')
+    return report
+  } catch (error) {
+    console.error('Error getting attachment storage report:', error)
+    throw error
+  }
+}
+
+export const scanAttachments = async () => {
+  try {
+    const report = await invoke('scan_attachments')
+    return report
+  } catch (error) {
+    console.error('Error scanning attachments:', error)
+    throw error
+  }
+}
+
+export const cleanAttachments = async (dryRun = true) => {
+  try {
+    const report = await invoke('clean_attachments', { dryRun })
+    return report
+  } catch (error) {
+    console.error('Error cleaning attachments:', error)
+    throw error
+  }
+}
+
+export const getImageProcessingSettings = async () => {
+  try {
+    const settings = await invoke('get_image_processing_settings')
+    return settings
+  } catch (error) {
+    console.error('Error getting image processing settings:', error)
+    throw error
+  }
+}
+
+export const setImageProcessingSettings = async (settings) => {
+  try {
+    await invoke('set_image_processing_settings', { settings })
+  } catch (error) {
+    console.error('Error saving image processing settings:', error)
+    throw error
+  }
+}
+
+export const listBackups = async () => {
+  try {
+    const backups = await invoke('list_backups')
+    return backups
+  } catch (error) {
+    console.error('Error listing backups:', error)
+    throw error
+  }
+}
+
+// Leave includeAttachments out to follow the backup settings
+export const createBackupNow = async (includeAttachments = null) => {
+  try {
+    const backup = await invoke('create_backup_now', { includeAttachments })
+    return backup
+  } catch (error) {
+    console.error('Error creating backup:', error)
+    throw error
+  }
+}
+
+// Resolves to the safety copy taken of the database before it was replaced
+export const restoreBackup = async (fileName) => {
+  try {
+    const safetyCopy = await invoke('restore_backup', { fileName })
+    return safetyCopy
+  } catch (error) {
+    console.error('Error restoring backup:', error)
+    throw error
+  }
+}
+
+export const deleteBackup = async (fileName) => {
+  try {
+    await invoke('delete_backup', { fileName })
+  } catch (error) {
+    console.error('Error deleting backup:', error)
+    throw error
+  }
+}
+
+export const getBackupSettings = async () => {
+  try {
+    const settings = await invoke('get_backup_settings')
+    return settings
+  } catch (error) {
+    console.error('Error getting backup settings:', error)
+    throw error
+  }
+}
+
+export const setBackupSettings = async (settings) => {
+  try {
+    await invoke('set_backup_settings', { settings })
+  } catch (error) {
+    console.error('Error saving backup settings:', error)
+    throw error
+  }
+}
+
+export const exportWorkspace = async (path) => {
+  try {
+    const report = await invoke('export_workspace', { path })
+    return report
+  } catch (error) {
+    console.error('Error exporting workspace:', error)
+    throw error
+  }
+}
+
+export const exportProject = async (projectId, path) => {
+  try {
+    const report = await invoke('export_project', { projectId, path })
+    return report
+  } catch (error) {
+    console.error('Error exporting project:', error)
+    throw error
+  }
+}
+
+// `mode` is 'remap' (import as copies) or 'merge' (keep ids, newer wins)
+export const importArchive = async (path, mode = 'remap') => {
+  try {
+    const report = await invoke('import_archive', { path, mode })
+    return report
+  } catch (error) {
+    console.error('Error importing archive:', error)
+    throw error
+  }
+}
+
+// `layout` is 'file' (path is the .md file) or 'folder' (path is an empty folder)
+export const exportProjectMarkdown = async (projectId, path, layout = 'file') => {
+  try {
+    const result = await invoke('export_project_markdown', { projectId, path, layout })
+    return result
+  } catch (error) {
+    console.error('Error exporting project as Markdown:', error)
+    throw error
+  }
+}
+
+// `format` is 'csv', 'json' or 'trello'. `mapping` names CSV columns, e.g.
+// { title: 'Task', completed: 'Status', project: 'List' }. Nothing is written
+// until this is called with `dryRun` false.
+export const importFile = async (path, format, mapping = null, dryRun = true) => {
+  try {
+    const report = await invoke('import_file', { path, format, mapping, dryRun })
+    return report
+  } catch (error) {
+    console.error('Error importing file:', error)
+    throw error
+  }
+}
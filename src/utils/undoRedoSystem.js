@@ -48,11 +48,11 @@ class UndoRedoSystem {
 }
 
 export class DeleteStepCommand {
-  constructor(step, projectId, deleteStepFn, createStepFn) {
+  constructor(step, projectId, deleteStepFn, restoreStepFn) {
     this.step = step;
     this.projectId = projectId;
     this.deleteStepFn = deleteStepFn;
-    this.createStepFn = createStepFn;
+    this.restoreStepFn = restoreStepFn;
   }
 
   async execute() {
@@ -60,7 +60,7 @@ export class DeleteStepCommand {
   }
 
   async undo() {
-    await this.createStepFn(this.step);
+    await this.restoreStepFn(this.step);
   }
 }
 